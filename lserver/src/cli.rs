@@ -0,0 +1,514 @@
+// cli.rs - The `quillguard` command line
+//
+//   quillguard [serve]                 HTTP server (the default)
+//   quillguard lsp                     Language server over stdio (also `--stdio`)
+//   quillguard check [PATH|GLOB|-]...  Print diagnostics for files or stdin
+//   quillguard fix [PATH|GLOB|-]...    Apply `auto_apply` corrections in place
+//
+// `check` and `fix` run the same pipeline as `/api/grammar/professional`,
+// with the same config file. They exit with `EXIT_FINDINGS` when a finding
+// at or above `--fail-on` remains, so they can gate CI.
+use anyhow::{Error as E, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures::StreamExt;
+use harper_core::Dialect;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::config::Config;
+use crate::lang::edits::{apply_edits, Edit};
+use crate::lang::format::TextFormat;
+use crate::lang::lint::{calculate_stats, check_grammar_professional, GrammarCorrection, GrammarResponse, GrammarStats};
+use crate::lang::rules::RuleConfig;
+use crate::lang::{Corrector, HarperConfig, OffsetEncoding};
+use crate::{AppState, CheckOptions};
+
+/// Nothing at or above the threshold was found.
+pub const EXIT_OK: i32 = 0;
+/// Findings at or above the threshold remain.
+pub const EXIT_FINDINGS: i32 = 1;
+/// Bad arguments, configuration or input; clap uses the same code for usage errors.
+pub const EXIT_ERROR: i32 = 2;
+
+/// Name diagnostics use for text read from stdin.
+const STDIN_NAME: &str = "<stdin>";
+
+#[derive(Parser)]
+#[command(name = "quillguard", version, about = "QuillGuard grammar checker and server")]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Speak the Language Server Protocol over stdio, like `quillguard lsp`
+    #[arg(long, hide = true)]
+    stdio: bool,
+}
+
+impl Cli {
+    /// The command to run; serving HTTP when none is given.
+    pub fn command(self) -> Command {
+        match self.command {
+            _ if self.stdio => Command::Lsp,
+            Some(command) => command,
+            None => Command::Serve,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the HTTP API on port 3000
+    Serve,
+    /// Serve the Language Server Protocol over stdio
+    Lsp,
+    /// Check files, globs or stdin and print what was found
+    Check(CheckArgs),
+    /// Apply the corrections marked `auto_apply`, rewriting files in place
+    Fix(FixArgs),
+}
+
+#[derive(Args)]
+pub struct CheckArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// How to print what was found
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+    /// Unit of `offset` and `length` in JSON output
+    #[arg(long, value_parser = parse_name::<OffsetEncoding>, default_value = "utf16")]
+    offset_encoding: OffsetEncoding,
+}
+
+#[derive(Args)]
+pub struct FixArgs {
+    #[command(flatten)]
+    input: InputArgs,
+}
+
+/// What to check and how, shared by `check` and `fix`.
+#[derive(Args)]
+struct InputArgs {
+    /// Files or glob patterns; `-` or nothing reads stdin
+    paths: Vec<String>,
+    /// English dialect: American, British, Canadian or Australian
+    #[arg(long, default_value = "American")]
+    dialect: Dialect,
+    /// How to read the text: plain, markdown or html; by default from each file's extension
+    #[arg(long, value_parser = parse_name::<TextFormat>)]
+    text_format: Option<TextFormat>,
+    /// Also run the configured model stages
+    #[arg(long)]
+    use_t5: bool,
+    /// Stages to run, in order, e.g. `harper,flan_t5`; overrides `--use-t5`
+    #[arg(long, value_delimiter = ',')]
+    stages: Option<Vec<String>>,
+    /// User word list whose words count as correctly spelled
+    #[arg(long)]
+    user: Option<String>,
+    /// Workspace word list whose words count as correctly spelled
+    #[arg(long)]
+    workspace: Option<String>,
+    /// Exit non-zero when a finding of this severity or worse remains
+    #[arg(long, value_enum, default_value_t = FailOn::Critical)]
+    fail_on: FailOn,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// One `path:line:column` line per finding
+    Human,
+    /// The API's response for each input, with its path
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FailOn {
+    Critical,
+    Important,
+    Enhancement,
+    Never,
+}
+
+impl FailOn {
+    fn fails(self, correction: &GrammarCorrection) -> bool {
+        let rank = |severity: &str| match severity {
+            "critical" => 3,
+            "important" => 2,
+            _ => 1,
+        };
+        let threshold = match self {
+            FailOn::Critical => 3,
+            FailOn::Important => 2,
+            FailOn::Enhancement => 1,
+            FailOn::Never => return false,
+        };
+        rank(&correction.severity) >= threshold
+    }
+}
+
+/// Parse a value by its name in the API, e.g. `markdown` or `utf-8`.
+fn parse_name<T: DeserializeOwned>(name: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(name.to_string())).map_err(|e| e.to_string())
+}
+
+/// A file or stdin, read whole.
+struct Input {
+    name: String,
+    // `None` for stdin
+    path: Option<PathBuf>,
+    text: String,
+}
+
+impl InputArgs {
+    /// Read every input, expanding globs. An empty list means stdin.
+    fn read(&self) -> Result<Vec<Input>> {
+        if self.paths.is_empty() {
+            return Ok(vec![read_stdin()?]);
+        }
+
+        let mut inputs = Vec::new();
+        for pattern in &self.paths {
+            if pattern == "-" {
+                inputs.push(read_stdin()?);
+                continue;
+            }
+            let paths = if pattern.contains(['*', '?', '[']) {
+                let matches = glob::glob(pattern)
+                    .map_err(|e| E::msg(format!("Invalid pattern '{}': {}", pattern, e)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                if matches.is_empty() {
+                    return Err(E::msg(format!("No files match '{}'", pattern)));
+                }
+                matches
+            } else {
+                vec![PathBuf::from(pattern)]
+            };
+
+            for path in paths.into_iter().filter(|path| !path.is_dir()) {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| E::msg(format!("Cannot read {}: {}", path.display(), e)))?;
+                inputs.push(Input { name: path.display().to_string(), path: Some(path), text });
+            }
+        }
+        Ok(inputs)
+    }
+
+    /// Request options for one input.
+    fn options(&self, input: &Input) -> CheckOptions {
+        let format = self.text_format.unwrap_or_else(|| {
+            let extension = input.path.as_deref().and_then(Path::extension).and_then(|ext| ext.to_str());
+            extension.map_or(TextFormat::Plain, TextFormat::from_extension)
+        });
+        CheckOptions {
+            dialect: self.dialect,
+            use_t5: self.use_t5,
+            format,
+            offset_encoding: OffsetEncoding::Bytes,
+            generation: Default::default(),
+            stages: self.stages.clone(),
+            stage_options: HashMap::new(),
+            budget_ms: None,
+            rules: RuleConfig::default(),
+            user: self.user.clone(),
+            workspace: self.workspace.clone(),
+        }
+    }
+
+    /// Check every input, a few at a time; corrections are in bytes and
+    /// leave out the whole-text summary, which is not a finding.
+    async fn check(&self, config: &Config, harper: HarperConfig, inputs: &[Input]) -> Result<Vec<GrammarResponse>> {
+        // Models take a while to load, so only when a model stage can run
+        let corrector = if self.use_t5 || self.stages.is_some() {
+            Corrector::new(config).await?
+        } else {
+            Corrector::disabled()
+        };
+        let state = Arc::new(AppState::new(config, harper, corrector));
+
+        let checks = inputs.iter().map(|input| {
+            let state = &state;
+            async move {
+                let options = self.options(input);
+                let harper = state.harper(&options)?;
+                let pipeline = state.pipeline(&options)?;
+                let mut response = check_grammar_professional(
+                    &harper,
+                    &input.text,
+                    options.dialect,
+                    &pipeline,
+                    &options.generation,
+                    &options.rules,
+                ).await;
+                response.corrections.retain(|correction| correction.category != "summary");
+                response.stats = calculate_stats(&response.corrections);
+                Ok(response)
+            }
+        });
+        futures::stream::iter(checks).buffered(state.batch_concurrency).collect::<Vec<_>>().await.into_iter().collect()
+    }
+}
+
+fn read_stdin() -> Result<Input> {
+    let mut text = String::new();
+    std::io::stdin()
+        .read_to_string(&mut text)
+        .map_err(|e| E::msg(format!("Cannot read stdin: {}", e)))?;
+    Ok(Input { name: STDIN_NAME.to_string(), path: None, text })
+}
+
+/// `quillguard check`: print findings; returns the exit code.
+pub async fn check(config: &Config, harper: HarperConfig, args: CheckArgs) -> i32 {
+    let inputs = match args.input.read() {
+        Ok(inputs) => inputs,
+        Err(e) => return fail(e),
+    };
+    let responses = match args.input.check(config, harper, &inputs).await {
+        Ok(responses) => responses,
+        Err(e) => return fail(e),
+    };
+
+    let failing = responses.iter().flat_map(|response| &response.corrections).any(|c| args.input.fail_on.fails(c));
+    let printed = match args.format {
+        OutputFormat::Human => print_human(&inputs, &responses),
+        OutputFormat::Json => print_json(&inputs, responses, args.offset_encoding),
+    };
+    if let Err(e) = printed {
+        return fail(e);
+    }
+    if failing { EXIT_FINDINGS } else { EXIT_OK }
+}
+
+/// `quillguard fix`: apply `auto_apply` corrections, rewriting files or
+/// printing fixed stdin; returns the exit code for what is left.
+pub async fn fix(config: &Config, harper: HarperConfig, args: FixArgs) -> i32 {
+    let inputs = match args.input.read() {
+        Ok(inputs) => inputs,
+        Err(e) => return fail(e),
+    };
+    let responses = match args.input.check(config, harper, &inputs).await {
+        Ok(responses) => responses,
+        Err(e) => return fail(e),
+    };
+
+    let mut failing = false;
+    for (input, response) in inputs.iter().zip(responses) {
+        let (fixed, fixes, left) = apply_fixes(&input.text, response.corrections);
+
+        let written = match &input.path {
+            Some(path) if fixed != input.text => std::fs::write(path, &fixed)
+                .map_err(|e| E::msg(format!("Cannot write {}: {}", path.display(), e))),
+            Some(_) => Ok(()),
+            None => std::io::stdout().write_all(fixed.as_bytes()).map_err(E::from),
+        };
+        if let Err(e) = written {
+            return fail(e);
+        }
+        // stdout may be carrying the fixed text, so the report goes to stderr
+        eprintln!("{}: {} fixed, {} left", input.name, fixes.len(), left.len());
+        failing |= left.iter().any(|c| args.input.fail_on.fails(c));
+    }
+    if failing { EXIT_FINDINGS } else { EXIT_OK }
+}
+
+/// Apply the `auto_apply` corrections to `text`: the fixed text, then the
+/// corrections that were applied and those left.
+fn apply_fixes(text: &str, corrections: Vec<GrammarCorrection>) -> (String, Vec<GrammarCorrection>, Vec<GrammarCorrection>) {
+    let edits: Vec<Edit> = corrections
+        .iter()
+        .filter(|correction| correction.auto_apply)
+        .map(|fix| Edit::new(fix.offset, fix.length, fix.primary_suggestion.clone()))
+        .collect();
+    let (fixed, map) = apply_edits(text, &edits);
+
+    // Edits that overlap an earlier one are skipped, so what was not applied is still left.
+    // Among edits at the same span the first one wins, as in `apply_edits`
+    let mut applied: Vec<(usize, usize)> = map.applied().collect();
+    let (fixes, left) = corrections.into_iter().partition(|correction| {
+        let span = (correction.offset, correction.length);
+        match applied.iter().position(|&applied| correction.auto_apply && applied == span) {
+            Some(index) => {
+                applied.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    });
+    (fixed, fixes, left)
+}
+
+fn fail(e: E) -> i32 {
+    eprintln!("quillguard: {:#}", e);
+    EXIT_ERROR
+}
+
+/// `path:line:column: severity: explanation` for each finding, then a total.
+fn print_human(inputs: &[Input], responses: &[GrammarResponse]) -> Result<()> {
+    let color = std::io::stdout().is_terminal();
+    let mut out = std::io::stdout().lock();
+    let mut total = GrammarStats::default();
+
+    for (input, response) in inputs.iter().zip(responses) {
+        let lines = LineIndex::new(&input.text);
+        for correction in &response.corrections {
+            let (line, column) = lines.position(&input.text, correction.offset);
+            let severity = match (color, correction.severity.as_str()) {
+                (true, "critical") => format!("\x1b[31m{}\x1b[0m", correction.severity),
+                (true, "important") => format!("\x1b[33m{}\x1b[0m", correction.severity),
+                _ => correction.severity.clone(),
+            };
+            let explanation = correction.explanation.lines().next().unwrap_or_default();
+            writeln!(
+                out,
+                "{}:{}:{}: {}: {} ({:?} -> {:?}) [{}]",
+                input.name,
+                line,
+                column,
+                severity,
+                explanation,
+                correction.original_text,
+                correction.primary_suggestion,
+                correction.rule.as_deref().unwrap_or(&correction.source_stage),
+            )?;
+        }
+        total.add(&response.stats);
+    }
+
+    let files = match inputs.len() {
+        1 => "1 file".to_string(),
+        n => format!("{} files", n),
+    };
+    match total.total_issues {
+        0 => writeln!(out, "No problems found in {}", files)?,
+        n => writeln!(
+            out,
+            "{} problem{} ({} critical, {} important, {} enhancement) in {}",
+            n,
+            if n == 1 { "" } else { "s" },
+            total.critical,
+            total.important,
+            total.enhancement,
+            files,
+        )?,
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonOutput {
+    files: Vec<JsonFile>,
+    // Totals over every file
+    stats: GrammarStats,
+}
+
+#[derive(Serialize)]
+struct JsonFile {
+    path: String,
+    #[serde(flatten)]
+    response: GrammarResponse,
+}
+
+fn print_json(inputs: &[Input], responses: Vec<GrammarResponse>, encoding: OffsetEncoding) -> Result<()> {
+    let mut output = JsonOutput { files: Vec::new(), stats: GrammarStats::default() };
+    for (input, response) in inputs.iter().zip(responses) {
+        output.stats.add(&response.stats);
+        output.files.push(JsonFile {
+            path: input.name.clone(),
+            response: response.with_offset_encoding(&input.text, encoding),
+        });
+    }
+    let mut out = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &output)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Byte offset to 1-based line and column, columns counted in characters.
+struct LineIndex {
+    // Byte offset each line starts at
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let starts = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        Self { starts }
+    }
+
+    fn position(&self, text: &str, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let column = text[self.starts[line]..offset].chars().count() + 1;
+        (line + 1, column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correction(text: &str, word: &str, fix: &str, severity: &str, auto_apply: bool) -> GrammarCorrection {
+        GrammarCorrection {
+            id: String::new(),
+            category: "correctness".to_string(),
+            subcategory: "spelling".to_string(),
+            severity: severity.to_string(),
+            confidence: 0.99,
+            visual_treatment: "highlight".to_string(),
+            offset: text.find(word).unwrap(),
+            length: word.len(),
+            original_text: word.to_string(),
+            suggestions: vec![fix.to_string()],
+            primary_suggestion: fix.to_string(),
+            explanation: String::new(),
+            source_stage: "harper".to_string(),
+            auto_apply,
+            rule: None,
+        }
+    }
+
+    #[test]
+    fn positions_are_lines_and_characters_from_one() {
+        let text = "first\ncafé teh\n\nlast";
+        let index = LineIndex::new(text);
+        assert_eq!(index.position(text, 0), (1, 1));
+        assert_eq!(index.position(text, 5), (1, 6));
+        // é is two bytes but one column
+        assert_eq!(index.position(text, text.find("teh").unwrap()), (2, 6));
+        assert_eq!(index.position(text, text.find("\n\n").unwrap() + 1), (3, 1));
+        assert_eq!(index.position(text, text.len()), (4, 5));
+    }
+
+    #[test]
+    fn fail_on_counts_its_severity_and_above() {
+        let text = "a b c";
+        let [critical, important, enhancement] = ["critical", "important", "enhancement"].map(|severity| correction(text, "a", "A", severity, false));
+        let failing = |fail_on: FailOn| [&critical, &important, &enhancement].map(|c| fail_on.fails(c));
+        assert_eq!(failing(FailOn::Critical), [true, false, false]);
+        assert_eq!(failing(FailOn::Important), [true, true, false]);
+        assert_eq!(failing(FailOn::Enhancement), [true, true, true]);
+        assert_eq!(failing(FailOn::Never), [false, false, false]);
+    }
+
+    #[test]
+    fn only_applied_fixes_are_counted_as_fixed() {
+        let text = "This is teh end of teh story.";
+        let first = correction(text, "teh", "the", "critical", true);
+        // Same span as the first: it loses
+        let duplicate = correction(text, "teh", "tea", "critical", true);
+        // Starts where the first does but is longer, so comes after it and overlaps it
+        let overlapping = correction(text, "teh end", "the end", "important", true);
+        // Not safe to apply
+        let advice = correction(text, "end", "finish", "enhancement", false);
+        let mut second = correction(text, "teh", "the", "critical", true);
+        second.offset = text.rfind("teh").unwrap();
+
+        let corrections = vec![first, duplicate, overlapping, advice, second];
+        let (fixed, fixes, left) = apply_fixes(text, corrections);
+        assert_eq!(fixed, "This is the end of the story.");
+        let fixed: Vec<(&str, usize)> = fixes.iter().map(|c| (c.primary_suggestion.as_str(), c.offset)).collect();
+        assert_eq!(fixed, [("the", 8), ("the", 19)]);
+        let left: Vec<&str> = left.iter().map(|c| c.primary_suggestion.as_str()).collect();
+        assert_eq!(left, ["tea", "the end", "finish"]);
+    }
+}
@@ -0,0 +1,260 @@
+// config.rs - Server configuration from a TOML file and environment variables
+//
+// Lookup order: built-in defaults, then the file named by QUILLGUARD_CONFIG
+// (or ./quillguard.toml when present), then QUILLGUARD_* variables.
+//
+//   [models]
+//   gramformer_dir = "/srv/models/gramformer_onnx"
+//   flan_t5_dir = "/srv/models/flan_t5_onnx"
+//   offline = true      # never touch the network
+//   required = true     # refuse to start without both models
+//
+//   [inference]
+//   sessions = 2
+//   batch_size = 8
+//   batch_window_ms = 10
+//
+//   [pipeline]
+//   stages = ["harper", "gramformer"]
+//
+//   [rules]             # Harper rules by name
+//   LongSentences = false
+//
+//   [dictionaries]
+//   dir = "/srv/quillguard/dictionaries"
+//
+//   [jobs]
+//   max_running = 4
+use anyhow::{Error as E, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+use crate::lang::pipeline::BUILTIN_STAGES;
+use crate::lang::rules::RuleConfig;
+
+const DEFAULT_CONFIG_FILE: &str = "quillguard.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub models: ModelConfig,
+    pub inference: InferenceConfig,
+    pub pipeline: PipelineConfig,
+    /// Server-wide Harper rule settings; requests can override them
+    pub rules: RuleConfig,
+    pub dictionaries: DictionaryConfig,
+    pub jobs: JobConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelConfig {
+    /// Gramformer export: `tokenizer.json` plus the ONNX graphs
+    pub gramformer_dir: PathBuf,
+    /// FLAN-T5 export: `tokenizer.json` plus the ONNX graphs under `onnx/`
+    pub flan_t5_dir: PathBuf,
+    /// Hugging Face repository FLAN-T5 is fetched from when `download` is set
+    pub flan_t5_repo: String,
+    /// Fetch a missing FLAN-T5 model from Hugging Face instead of failing
+    pub download: bool,
+    /// Never touch the network, whatever else is configured
+    pub offline: bool,
+    /// Exit at startup unless both models load
+    pub required: bool,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            gramformer_dir: PathBuf::from("./gramformer_onnx"),
+            flan_t5_dir: PathBuf::from("./flan_t5_onnx"),
+            flan_t5_repo: "pszemraj/flan-t5-large-grammar-synthesis".to_string(),
+            download: false,
+            offline: false,
+            required: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InferenceConfig {
+    /// Model copies (worker threads) per model; each serves one batch at a time
+    pub sessions: usize,
+    /// Most sentences decoded together; 1 disables batching
+    pub batch_size: usize,
+    /// How long a sentence waits for others to join its batch
+    pub batch_window_ms: u64,
+}
+
+impl Default for InferenceConfig {
+    fn default() -> Self {
+        Self {
+            sessions: 1,
+            batch_size: 8,
+            batch_window_ms: 10,
+        }
+    }
+}
+
+impl InferenceConfig {
+    /// Sentences the models can work on at once, over every session
+    pub fn concurrency(&self) -> usize {
+        self.sessions * self.batch_size
+    }
+
+    pub fn batch_window(&self) -> Duration {
+        Duration::from_millis(self.batch_window_ms)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Correction stages in the order they run; model stages only run when a
+    /// request asks for AI corrections
+    pub stages: Vec<String>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            stages: BUILTIN_STAGES.iter().map(|stage| stage.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DictionaryConfig {
+    /// Where user and workspace word lists are kept
+    pub dir: PathBuf,
+}
+
+impl Default for DictionaryConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("./dictionaries"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    /// Background jobs checked at once; further jobs are turned away
+    pub max_running: usize,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self { max_running: 4 }
+    }
+}
+
+impl Config {
+    /// Defaults, overridden by the config file, overridden by the environment.
+    pub fn load() -> Result<Self> {
+        let mut config = match std::env::var_os("QUILLGUARD_CONFIG") {
+            Some(path) => Self::from_file(Path::new(&path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| E::msg(format!("Cannot read config file {}: {}", path.display(), e)))?;
+        let config = toml::from_str(&contents)
+            .map_err(|e| E::msg(format!("Invalid config file {}: {}", path.display(), e)))?;
+        info!("Loaded configuration from {}", path.display());
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(dir) = std::env::var_os("QUILLGUARD_GRAMFORMER_DIR") {
+            self.models.gramformer_dir = PathBuf::from(dir);
+        }
+        if let Some(dir) = std::env::var_os("QUILLGUARD_FLAN_T5_DIR") {
+            self.models.flan_t5_dir = PathBuf::from(dir);
+        }
+        if let Ok(repo) = std::env::var("QUILLGUARD_FLAN_T5_REPO") {
+            self.models.flan_t5_repo = repo;
+        }
+        if let Some(download) = env_parse("QUILLGUARD_DOWNLOAD_MODELS", parse_bool)? {
+            self.models.download = download;
+        }
+        // The Hugging Face client's own switch counts too
+        if let Some(offline) = env_parse("QUILLGUARD_OFFLINE", parse_bool)?.or(env_parse("HF_HUB_OFFLINE", parse_bool)?) {
+            self.models.offline = offline;
+        }
+        if let Some(required) = env_parse("QUILLGUARD_MODELS_REQUIRED", parse_bool)? {
+            self.models.required = required;
+        }
+        if let Some(sessions) = env_parse("QUILLGUARD_MODEL_SESSIONS", |v| v.parse().ok())? {
+            self.inference.sessions = sessions;
+        }
+        if let Some(size) = env_parse("QUILLGUARD_BATCH_SIZE", |v| v.parse().ok())? {
+            self.inference.batch_size = size;
+        }
+        if let Some(window) = env_parse("QUILLGUARD_BATCH_WINDOW_MS", |v| v.parse().ok())? {
+            self.inference.batch_window_ms = window;
+        }
+        if let Some(dir) = std::env::var_os("QUILLGUARD_DICTIONARY_DIR") {
+            self.dictionaries.dir = PathBuf::from(dir);
+        }
+        if let Some(max) = env_parse("QUILLGUARD_MAX_JOBS", |v| v.parse().ok())? {
+            self.jobs.max_running = max;
+        }
+        if let Ok(stages) = std::env::var("QUILLGUARD_PIPELINE") {
+            self.pipeline.stages = stages.split(',').map(|stage| stage.trim().to_string()).filter(|stage| !stage.is_empty()).collect();
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.models.offline && self.models.download {
+            return Err(E::msg("models.offline and models.download are both set; offline mode never downloads"));
+        }
+        if self.inference.sessions == 0 {
+            return Err(E::msg("inference.sessions must be at least 1"));
+        }
+        if self.inference.batch_size == 0 {
+            return Err(E::msg("inference.batch_size must be at least 1"));
+        }
+        if self.jobs.max_running == 0 {
+            return Err(E::msg("jobs.max_running must be at least 1"));
+        }
+        for (index, stage) in self.pipeline.stages.iter().enumerate() {
+            if !BUILTIN_STAGES.contains(&stage.as_str()) {
+                return Err(E::msg(format!("Unknown pipeline stage '{}' (expected one of {})", stage, BUILTIN_STAGES.join(", "))));
+            }
+            if self.pipeline.stages[..index].contains(stage) {
+                return Err(E::msg(format!("Pipeline stage '{}' is listed twice", stage)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse an environment variable if it is set; a value that does not parse is an error.
+fn env_parse<T>(name: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => parse(value.trim())
+            .map(Some)
+            .ok_or_else(|| E::msg(format!("Invalid value for {}: '{}'", name, value))),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,529 @@
+// jobs.rs - Background checks for documents too large for one request
+//
+// `POST /api/jobs` returns at once; a background task then runs the text
+// through the pipeline a chunk of paragraphs at a time, saving progress and
+// the corrections found in each chunk as it goes. Clients poll
+// `GET /api/jobs/{id}` for progress and partial results.
+//
+// Only the task checking a job holds its text, and drops it when done. Jobs
+// live behind [`JobStore`] as a small progress record plus their corrections,
+// already in the client's offset unit. [`MemoryJobStore`] keeps them in this
+// process; a store backed by a database or files only has to implement the
+// trait. Finished jobs are forgotten after `FINISHED_JOB_TTL`. Only
+// `jobs.max_running` jobs are checked at once; more are turned away with 503.
+use anyhow::{Error as E, Result};
+use async_trait::async_trait;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use crate::lang::lint::{calculate_stats, check_grammar_professional, GrammarCorrection, GrammarStats};
+use crate::lang::{HarperConfig, OffsetConverter, OffsetEncoding, Pipeline};
+use crate::{bad_request, AppState, CheckOptions};
+
+/// Largest text a job accepts, in bytes: several books' worth.
+pub const MAX_JOB_BYTES: usize = 32 * 1024 * 1024;
+
+/// Text checked per step, in bytes. Paragraphs are never split, so a chunk
+/// can be larger when one paragraph is.
+const JOB_CHUNK_BYTES: usize = 16 * 1024;
+
+/// How long a completed, cancelled or failed job can still be fetched.
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Cancelled,
+    /// Progress could not be saved, or checking a chunk panicked; what was
+    /// saved before is kept
+    Failed,
+}
+
+impl JobStatus {
+    fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Cancelled | JobStatus::Failed)
+    }
+}
+
+/// A job's progress as stored. Its corrections are stored alongside.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    /// Unit of the stored corrections' offsets
+    pub offset_encoding: OffsetEncoding,
+    pub text_bytes: usize,
+    /// Bytes of the text checked so far
+    pub checked_bytes: usize,
+    pub chunks_done: usize,
+    pub chunks_total: usize,
+    pub total_corrections: usize,
+    /// Over every correction so far
+    pub stats: GrammarStats,
+}
+
+#[async_trait]
+pub trait JobStore: std::fmt::Debug + Send + Sync {
+    /// The job's progress, without its corrections.
+    async fn get(&self, id: &str) -> Result<Option<Job>>;
+
+    /// The job's corrections from index `from` on, in document order.
+    async fn corrections(&self, id: &str, from: usize) -> Result<Vec<GrammarCorrection>>;
+
+    /// Insert or replace the job's progress, adding `found` to its corrections.
+    async fn save(&self, job: &Job, found: &[GrammarCorrection]) -> Result<()>;
+
+    async fn remove(&self, id: &str) -> Result<()>;
+}
+
+/// Jobs in memory; they are lost on restart.
+#[derive(Debug, Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<HashMap<String, (Job, Vec<GrammarCorrection>)>>,
+}
+
+#[async_trait]
+impl JobStore for MemoryJobStore {
+    async fn get(&self, id: &str) -> Result<Option<Job>> {
+        Ok(self.jobs.lock().unwrap().get(id).map(|(job, _)| job.clone()))
+    }
+
+    async fn corrections(&self, id: &str, from: usize) -> Result<Vec<GrammarCorrection>> {
+        let jobs = self.jobs.lock().unwrap();
+        let corrections = jobs.get(id).map_or(&[][..], |(_, corrections)| corrections);
+        Ok(corrections.get(from..).unwrap_or_default().to_vec())
+    }
+
+    async fn save(&self, job: &Job, found: &[GrammarCorrection]) -> Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let (stored, corrections) = jobs.entry(job.id.clone()).or_insert_with(|| (job.clone(), Vec::new()));
+        stored.clone_from(job);
+        corrections.extend_from_slice(found);
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> Result<()> {
+        self.jobs.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+/// The job store and the tasks working on its jobs.
+#[derive(Debug)]
+pub struct Jobs {
+    store: Arc<dyn JobStore>,
+    running: Mutex<HashMap<String, JoinHandle<()>>>,
+    max_running: usize,
+}
+
+impl Jobs {
+    pub fn new(store: Arc<dyn JobStore>, max_running: usize) -> Self {
+        Self { store, running: Mutex::new(HashMap::new()), max_running }
+    }
+
+    fn is_full(&self) -> bool {
+        self.running.lock().unwrap().len() >= self.max_running
+    }
+
+    /// Stop the job's task, if it has one, once it has really stopped.
+    async fn stop(&self, id: &str) -> bool {
+        let task = self.running.lock().unwrap().remove(id);
+        match task {
+            Some(task) => {
+                task.abort();
+                let _ = task.await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget a finished job once `FINISHED_JOB_TTL` has passed.
+    fn expire(&self, id: String) {
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(FINISHED_JOB_TTL).await;
+            if let Err(e) = store.remove(&id).await {
+                warn!("Cannot forget job {}: {:#}", id, e);
+            }
+        });
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JobRequest {
+    text: String,
+    #[serde(flatten)]
+    options: CheckOptions,
+}
+
+#[derive(Deserialize)]
+pub struct JobQuery {
+    // Only return corrections from this index on, to page through results while polling
+    #[serde(default)]
+    from: usize,
+}
+
+/// A job as reported to clients.
+#[derive(Serialize)]
+struct JobResponse {
+    id: String,
+    status: JobStatus,
+    // Share of the text checked, 0 to 100
+    progress: f64,
+    chunks_done: usize,
+    chunks_total: usize,
+    // All corrections so far; `corrections` may be a later slice of them
+    total_corrections: usize,
+    from: usize,
+    corrections: Vec<GrammarCorrection>,
+    stats: GrammarStats,
+    offset_encoding: OffsetEncoding,
+}
+
+impl JobResponse {
+    /// `corrections` are the job's from index `from` on.
+    fn new(job: &Job, from: usize, corrections: Vec<GrammarCorrection>) -> Self {
+        let progress = match job.text_bytes {
+            0 => 100.0,
+            total => (job.checked_bytes as f64 * 1000.0 / total as f64).round() / 10.0,
+        };
+
+        Self {
+            id: job.id.clone(),
+            status: job.status,
+            progress,
+            chunks_done: job.chunks_done,
+            chunks_total: job.chunks_total,
+            total_corrections: job.total_corrections,
+            from: from.min(job.total_corrections),
+            corrections,
+            stats: job.stats.clone(),
+            offset_encoding: job.offset_encoding,
+        }
+    }
+}
+
+fn store_error(e: E) -> Response {
+    warn!("Job store failed: {:#}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": e.to_string() }))).into_response()
+}
+
+fn too_busy(max_running: usize) -> Response {
+    let error = format!("{} jobs are already running; try again later", max_running);
+    (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({ "error": error }))).into_response()
+}
+
+fn not_found(id: &str) -> Response {
+    (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": format!("No job '{}'", id) }))).into_response()
+}
+
+/// Start checking the text in the background
+pub async fn create_job(State(state): State<Arc<AppState>>, Json(request): Json<JobRequest>) -> Response {
+    if state.jobs.is_full() {
+        return too_busy(state.jobs.max_running);
+    }
+    let harper = match state.harper(&request.options) {
+        Ok(harper) => harper,
+        Err(e) => return bad_request(e),
+    };
+    let pipeline = match state.pipeline(&request.options) {
+        Ok(pipeline) => pipeline,
+        Err(e) => return bad_request(e),
+    };
+    state.count_request().await;
+
+    let chunks = job_chunks(&harper, &request.text);
+    let job = Job {
+        id: uuid::Uuid::new_v4().to_string(),
+        status: JobStatus::Queued,
+        offset_encoding: request.options.offset_encoding,
+        text_bytes: request.text.len(),
+        checked_bytes: 0,
+        chunks_done: 0,
+        chunks_total: chunks.len(),
+        total_corrections: 0,
+        stats: GrammarStats::default(),
+    };
+    if let Err(e) = state.jobs.store.save(&job, &[]).await {
+        return store_error(e);
+    }
+    info!("Job {} queued: {} bytes in {} chunks", job.id, job.text_bytes, chunks.len());
+
+    let response = JobResponse::new(&job, 0, Vec::new());
+    let id = job.id.clone();
+    let run = JobRun { text: request.text, chunks, options: request.options, harper, pipeline };
+    // Hold the lock across the spawn so the task cannot finish and deregister before it is registered,
+    // and so jobs created since the check above cannot push past the limit
+    let started = {
+        let mut running = state.jobs.running.lock().unwrap();
+        let started = running.len() < state.jobs.max_running;
+        if started {
+            running.insert(id.clone(), tokio::spawn(run_job(state.clone(), job, run)));
+        }
+        started
+    };
+    if !started {
+        if let Err(e) = state.jobs.store.remove(&id).await {
+            warn!("Cannot forget job {}: {:#}", id, e);
+        }
+        return too_busy(state.jobs.max_running);
+    }
+
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
+
+/// Progress and the corrections found so far
+pub async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<JobQuery>,
+) -> Response {
+    let job = match state.jobs.store.get(&id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return not_found(&id),
+        Err(e) => return store_error(e),
+    };
+    match state.jobs.store.corrections(&id, query.from).await {
+        Ok(corrections) => Json(JobResponse::new(&job, query.from, corrections)).into_response(),
+        Err(e) => store_error(e),
+    }
+}
+
+/// Stop a job, keeping what it found so far
+pub async fn cancel_job(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    let stopped = state.jobs.stop(&id).await;
+    let mut job = match state.jobs.store.get(&id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return not_found(&id),
+        Err(e) => return store_error(e),
+    };
+
+    if stopped && !job.status.is_finished() {
+        job.status = JobStatus::Cancelled;
+        if let Err(e) = state.jobs.store.save(&job, &[]).await {
+            return store_error(e);
+        }
+        state.jobs.expire(id.clone());
+        info!("Job {} cancelled after {} of {} chunks", id, job.chunks_done, job.chunks_total);
+    }
+    // Status only; the corrections are a GET away
+    Json(JobResponse::new(&job, job.total_corrections, Vec::new())).into_response()
+}
+
+/// Forget a job, stopping it first if it is still running
+pub async fn delete_job(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    state.jobs.stop(&id).await;
+    match state.jobs.store.get(&id).await {
+        Ok(Some(_)) => match state.jobs.store.remove(&id).await {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(e) => store_error(e),
+        },
+        Ok(None) => not_found(&id),
+        Err(e) => store_error(e),
+    }
+}
+
+/// What a job's task needs besides the job itself.
+struct JobRun {
+    text: String,
+    chunks: Vec<Range<usize>>,
+    options: CheckOptions,
+    harper: HarperConfig,
+    pipeline: Pipeline<'static>,
+}
+
+/// Check a job chunk by chunk, saving after each one.
+async fn run_job(state: Arc<AppState>, mut job: Job, run: JobRun) {
+    let JobRun { text, chunks, options, harper, pipeline } = run;
+    let encoding = job.offset_encoding;
+    job.status = JobStatus::Running;
+    if let Err(e) = state.jobs.store.save(&job, &[]).await {
+        warn!("Job {} not started: {:#}", job.id, e);
+    }
+
+    let mut placer = ChunkPlacer::new(&text, encoding);
+    for chunk in chunks {
+        let check = check_grammar_professional(
+            &harper,
+            &text[chunk.clone()],
+            options.dialect,
+            &pipeline,
+            &options.generation,
+            &options.rules,
+        );
+        // A panic would otherwise end the task with the job left running forever
+        let response = match AssertUnwindSafe(check).catch_unwind().await {
+            Ok(response) => response,
+            Err(_) => {
+                warn!("Job {} failed: checking chunk {} panicked", job.id, job.chunks_done + 1);
+                job.status = JobStatus::Failed;
+                break;
+            }
+        };
+
+        let found = placer.place(chunk.clone(), response.corrections, job.total_corrections);
+
+        let mut next = job.clone();
+        next.stats.add(&calculate_stats(&found));
+        next.total_corrections += found.len();
+        next.checked_bytes = chunk.end;
+        next.chunks_done += 1;
+        if let Err(e) = state.jobs.store.save(&next, &found).await {
+            warn!("Job {} failed: cannot save progress: {:#}", job.id, e);
+            job.status = JobStatus::Failed;
+            break;
+        }
+        job = next;
+        // Linting is CPU-bound; let requests in between chunks
+        tokio::task::yield_now().await;
+    }
+    drop(text);
+
+    if job.status == JobStatus::Running {
+        job.status = JobStatus::Completed;
+        job.checked_bytes = job.text_bytes;
+    }
+    match state.jobs.store.save(&job, &[]).await {
+        Ok(()) if job.status == JobStatus::Completed => {
+            info!("Job {} completed with {} corrections", job.id, job.total_corrections)
+        }
+        Ok(()) => {}
+        Err(e) => warn!("Job {} finished but its status cannot be saved: {:#}", job.id, e),
+    }
+    state.jobs.running.lock().unwrap().remove(&job.id);
+    state.jobs.expire(job.id);
+}
+
+/// Moves through the text chunk by chunk, placing each chunk's corrections in
+/// the whole text.
+struct ChunkPlacer<'a> {
+    text: &'a str,
+    encoding: OffsetEncoding,
+    // A byte offset in the text and the same offset in the client's unit
+    at: (usize, usize),
+}
+
+impl<'a> ChunkPlacer<'a> {
+    fn new(text: &'a str, encoding: OffsetEncoding) -> Self {
+        Self { text, encoding, at: (0, 0) }
+    }
+
+    /// The corrections found in `chunk`, with offsets into the whole text in
+    /// the client's unit, numbered on from the `numbered` already found.
+    /// Chunks must come in order.
+    fn place(&mut self, chunk: Range<usize>, corrections: Vec<GrammarCorrection>, numbered: usize) -> Vec<GrammarCorrection> {
+        let (bytes, units) = self.at;
+        self.at = (chunk.start, units + self.encoding.measure(&self.text[bytes..chunk.start]));
+        let converter = OffsetConverter::new(&self.text[chunk]);
+        // The whole-text summary would only cover this chunk
+        corrections
+            .into_iter()
+            .filter(|c| c.category != "summary")
+            .enumerate()
+            .map(|(index, mut correction)| {
+                let (offset, length) = converter.encode_span(correction.offset, correction.length, self.encoding);
+                (correction.offset, correction.length) = (self.at.1 + offset, length);
+                correction.id = format!("{}_{}", correction.source_stage, numbered + index + 1);
+                correction
+            })
+            .collect()
+    }
+}
+
+/// Runs of whole paragraphs of about `JOB_CHUNK_BYTES` each.
+fn job_chunks(harper: &HarperConfig, text: &str) -> Vec<Range<usize>> {
+    let mut chunks: Vec<Range<usize>> = Vec::new();
+    for paragraph in harper.paragraphs(text) {
+        match chunks.last_mut() {
+            Some(chunk) if paragraph.end - chunk.start <= JOB_CHUNK_BYTES => chunk.end = paragraph.end,
+            _ => chunks.push(paragraph),
+        }
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correction(text: &str, word: &str, category: &str) -> GrammarCorrection {
+        GrammarCorrection {
+            id: String::new(),
+            category: category.to_string(),
+            subcategory: "spelling".to_string(),
+            severity: "critical".to_string(),
+            confidence: 0.99,
+            visual_treatment: "highlight".to_string(),
+            offset: text.find(word).unwrap(),
+            length: word.len(),
+            original_text: word.to_string(),
+            suggestions: Vec::new(),
+            primary_suggestion: String::new(),
+            explanation: String::new(),
+            source_stage: "harper".to_string(),
+            auto_apply: false,
+            rule: None,
+        }
+    }
+
+    fn utf16_len(text: &str) -> usize {
+        text.encode_utf16().count()
+    }
+
+    #[test]
+    fn chunks_gather_whole_paragraphs() {
+        let paragraph = |bytes: usize| format!("{}\n\n", "a".repeat(bytes - 2));
+        let text = [paragraph(6000), paragraph(6000), paragraph(6000), paragraph(20000), paragraph(100)].concat();
+
+        // Paragraphs end after their first newline; the third would push the first chunk past 16 KB,
+        // and the 20 KB one is a chunk of its own however large
+        let chunks = job_chunks(&HarperConfig::new(), &text);
+        assert_eq!(chunks, [0..11999, 12000..17999, 18000..37999, 38000..38099]);
+    }
+
+    #[test]
+    fn chunks_of_empty_text() {
+        assert!(job_chunks(&HarperConfig::new(), "").is_empty());
+        assert!(job_chunks(&HarperConfig::new(), "\n\n  \n").is_empty());
+    }
+
+    #[test]
+    fn corrections_are_placed_in_the_whole_text() {
+        let text = "Thé wörld 👍 is big.\n\nTeh end is nigh 👍 und near.\n";
+        let second = text.find("Teh").unwrap();
+        let (first, second) = (0..second - 2, second..text.len());
+        let mut placer = ChunkPlacer::new(text, OffsetEncoding::Utf16);
+
+        let found = placer.place(first.clone(), vec![correction(&text[first.clone()], "big", "correctness")], 0);
+        let big = text.find("big").unwrap();
+        assert_eq!((found[0].offset, found[0].length, found[0].id.as_str()), (utf16_len(&text[..big]), 3, "harper_1"));
+
+        let chunk = &text[second.clone()];
+        let corrections = vec![
+            correction(chunk, "Teh", "correctness"),
+            correction(chunk, "Teh", "summary"),
+            correction(chunk, "und", "correctness"),
+        ];
+        let found = placer.place(second, corrections, 1);
+        let at = |word: &str| utf16_len(&text[..text.rfind(word).unwrap()]);
+        let placed: Vec<(usize, usize, &str)> = found.iter().map(|c| (c.offset, c.length, c.id.as_str())).collect();
+        // The summary only covered the chunk
+        assert_eq!(placed, [(at("Teh"), 3, "harper_2"), (at("und"), 3, "harper_3")]);
+        // Both emoji are two bytes longer than in UTF-16, both accented letters one
+        assert_eq!(at("und"), text.rfind("und").unwrap() - 6);
+    }
+}
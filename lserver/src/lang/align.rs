@@ -0,0 +1,241 @@
+// lang/align.rs - Token alignment between a text and its corrected version
+//
+// The seq2seq models return a whole rewritten sentence. To show what changed
+// we align the two texts over words and punctuation (whitespace is ignored, the
+// models normalise it anyway) and turn every unaligned run into one edit with
+// exact byte offsets into the original.
+use std::ops::Range;
+use crate::lang::edits::Edit;
+
+/// Byte ranges of the words and punctuation marks in `text`. Apostrophes and
+/// hyphens between letters stay inside the word ("don't", "well-known").
+pub fn tokenize(text: &str) -> Vec<Range<usize>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, ch)) = chars.next() {
+        if ch.is_whitespace() {
+            continue;
+        }
+
+        let mut end = start + ch.len_utf8();
+        if ch.is_alphanumeric() {
+            while let Some(&(index, next)) = chars.peek() {
+                let joins_word = matches!(next, '\'' | '’' | '-')
+                    && text[index + next.len_utf8()..].chars().next().is_some_and(char::is_alphanumeric);
+                if !next.is_alphanumeric() && !joins_word {
+                    break;
+                }
+                end = index + next.len_utf8();
+                chars.next();
+            }
+        }
+        tokens.push(start..end);
+    }
+
+    tokens
+}
+
+/// The minimal edits turning `original` into `corrected`, plus how much of the
+/// original they touch (changed tokens, each insertion counting as one, over
+/// the original token count).
+#[derive(Debug, Clone)]
+pub struct Alignment {
+    pub edits: Vec<Edit>,
+    pub change_ratio: f32,
+}
+
+/// Align the tokens of both texts (longest common subsequence) and emit an
+/// insert, delete or replace edit for every run that does not match.
+pub fn align(original: &str, corrected: &str) -> Alignment {
+    let orig = tokenize(original);
+    let corr = tokenize(corrected);
+    let orig_words: Vec<&str> = orig.iter().map(|r| &original[r.clone()]).collect();
+    let corr_words: Vec<&str> = corr.iter().map(|r| &corrected[r.clone()]).collect();
+
+    let mut edits = Vec::new();
+    let mut changed = 0;
+
+    for (orig_run, corr_run) in unmatched_runs(&orig_words, &corr_words) {
+        changed += orig_run.len().max(1);
+        edits.push(run_edit(original, &orig, orig_run, corrected, &corr, corr_run));
+    }
+
+    Alignment {
+        edits,
+        change_ratio: changed as f32 / orig.len().max(1) as f32,
+    }
+}
+
+/// Pairs of token index ranges, one per maximal run the LCS leaves unmatched.
+fn unmatched_runs(orig: &[&str], corr: &[&str]) -> Vec<(Range<usize>, Range<usize>)> {
+    // Common prefix and suffix never need the table
+    let prefix = orig.iter().zip(corr).take_while(|(a, b)| a == b).count();
+    let suffix = orig[prefix..].iter().rev()
+        .zip(corr[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &orig[prefix..orig.len() - suffix];
+    let b = &corr[prefix..corr.len() - suffix];
+
+    // lcs[i][j] = LCS length of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut runs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut run_i, mut run_j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            if run_i < i || run_j < j {
+                runs.push((prefix + run_i..prefix + i, prefix + run_j..prefix + j));
+            }
+            i += 1;
+            j += 1;
+            (run_i, run_j) = (i, j);
+        } else if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if run_i < i || run_j < j {
+        runs.push((prefix + run_i..prefix + i, prefix + run_j..prefix + j));
+    }
+
+    runs
+}
+
+/// The edit for one unmatched run. Deletions take one adjacent space with them
+/// and insertions bring the corrected text's spacing, so applying every edit
+/// leaves natural spacing behind.
+fn run_edit(
+    original: &str,
+    orig: &[Range<usize>],
+    orig_run: Range<usize>,
+    corrected: &str,
+    corr: &[Range<usize>],
+    corr_run: Range<usize>,
+) -> Edit {
+    if orig_run.is_empty() {
+        // Insertion: attach to the end of the preceding token, or the start of the text
+        let inserted_end = corr[corr_run.end - 1].end;
+        return match orig_run.start.checked_sub(1) {
+            Some(previous) => {
+                let inserted_start = corr_run.start.checked_sub(1).map(|p| corr[p].end).unwrap_or(0);
+                Edit::new(orig[previous].end, 0, &corrected[inserted_start..inserted_end])
+            }
+            None => {
+                let offset = orig.first().map(|t| t.start).unwrap_or(original.len());
+                let inserted_end = corr.get(corr_run.end).map(|t| t.start).unwrap_or(inserted_end);
+                Edit::new(offset, 0, &corrected[corr[corr_run.start].start..inserted_end])
+            }
+        };
+    }
+
+    let mut start = orig[orig_run.start].start;
+    let mut end = orig[orig_run.end - 1].end;
+
+    if corr_run.is_empty() {
+        // Deletion: also drop the whitespace after the run, or before it at the end of the text
+        let next_start = orig.get(orig_run.end).map(|t| t.start).unwrap_or(original.len());
+        if next_start > end && orig_run.end < orig.len() {
+            end = next_start;
+        } else if orig_run.start > 0 {
+            start = orig[orig_run.start - 1].end;
+        }
+        return Edit::new(start, end - start, "");
+    }
+
+    let replacement = &corrected[corr[corr_run.start].start..corr[corr_run.end - 1].end];
+    Edit::new(start, end - start, replacement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::edits::apply_edits;
+
+    fn words(text: &str) -> Vec<&str> {
+        tokenize(text).into_iter().map(|range| &text[range]).collect()
+    }
+
+    /// Align, check that the edits rebuild `corrected` exactly, and return them.
+    fn edits(original: &str, corrected: &str) -> Vec<Edit> {
+        let alignment = align(original, corrected);
+        let (applied, _) = apply_edits(original, &alignment.edits);
+        assert_eq!(applied, corrected);
+        alignment.edits
+    }
+
+    #[test]
+    fn tokens_keep_contractions_and_hyphenated_words() {
+        assert_eq!(words("I don't know, well-known ’tis it’s."), [
+            "I", "don't", "know", ",", "well-known", "’", "tis", "it’s", ".",
+        ]);
+        assert_eq!(words("  中文 café-au-lait -- end "), ["中文", "café-au-lait", "-", "-", "end"]);
+        assert!(words(" \n\t").is_empty());
+    }
+
+    #[test]
+    fn identical_texts_need_no_edits() {
+        let alignment = align("Nothing to see here.", "Nothing  to see\nhere.");
+        assert!(alignment.edits.is_empty());
+        assert_eq!(alignment.change_ratio, 0.0);
+    }
+
+    #[test]
+    fn replaced_word_is_one_edit() {
+        assert_eq!(edits("She go home.", "She goes home."), [Edit::new(4, 2, "goes")]);
+    }
+
+    #[test]
+    fn separate_runs_are_separate_edits() {
+        let edits = edits("He go to school and she like it.", "He goes to school and she likes it.");
+        assert_eq!(edits, [Edit::new(3, 2, "goes"), Edit::new(24, 4, "likes")]);
+    }
+
+    #[test]
+    fn adjacent_changes_merge_into_one_run() {
+        assert_eq!(edits("I has went there.", "I have gone there."), [Edit::new(2, 8, "have gone")]);
+    }
+
+    #[test]
+    fn insertions_bring_their_spacing() {
+        assert_eq!(edits("I went store.", "I went to the store."), [Edit::new(6, 0, " to the")]);
+        assert_eq!(edits("cat sat.", "The cat sat."), [Edit::new(0, 0, "The ")]);
+        assert_eq!(edits("It works", "It works."), [Edit::new(8, 0, ".")]);
+    }
+
+    #[test]
+    fn deletions_take_one_space_with_them() {
+        assert_eq!(edits("I really really like it.", "I really like it."), [Edit::new(9, 7, "")]);
+        assert_eq!(edits("Go now please", "Go now"), [Edit::new(6, 7, "")]);
+        assert_eq!(edits("Well I agree", "I agree"), [Edit::new(0, 5, "")]);
+    }
+
+    #[test]
+    fn offsets_are_bytes_of_the_original() {
+        assert_eq!(edits("Le café est fermé.", "Le café est ouvert."), [Edit::new(13, 6, "ouvert")]);
+        assert_eq!(edits("😀 teh cat", "😀 the cat"), [Edit::new(5, 3, "the")]);
+    }
+
+    #[test]
+    fn change_ratio_counts_changed_tokens() {
+        // One of four tokens replaced
+        assert_eq!(align("She go home.", "She goes home.").change_ratio, 0.25);
+        // An insertion counts as one token
+        assert_eq!(align("I went store.", "I went to the store.").change_ratio, 0.25);
+        assert_eq!(align("", "Something new").change_ratio, 1.0);
+    }
+}
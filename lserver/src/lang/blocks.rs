@@ -0,0 +1,136 @@
+// lang/blocks.rs - Block-structured documents, as edited by the Editor.js frontend
+//
+// Every block is checked on its own, so offsets are relative to the block's
+// text and no correction spans two blocks. The block type decides what runs:
+// code and raw HTML are not checked at all, and headers get no FLAN-T5 rewrites.
+use serde::{Deserialize, Serialize};
+use crate::lang::format::TextFormat;
+use crate::lang::lint::{GrammarCorrection, GrammarResponse, GrammarStats};
+use crate::lang::pipeline::{StageReport, FLAN_T5};
+
+fn default_block_type() -> String {
+    "paragraph".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Block {
+    pub id: String,
+    /// Editor.js tool name: "paragraph", "header", "list", "quote", "code", ...
+    #[serde(rename = "type", default = "default_block_type")]
+    pub kind: String,
+    pub text: String,
+    /// Overrides the request's format, e.g. `html` for inline markup
+    #[serde(default)]
+    pub format: Option<TextFormat>,
+}
+
+impl Block {
+    /// Whether blocks of this type hold prose worth checking.
+    pub fn is_checked(&self) -> bool {
+        !matches!(self.kind.as_str(), "code" | "raw" | "delimiter" | "image" | "embed")
+    }
+
+    /// Stages that never run on this type of block.
+    pub fn excluded_stages(&self) -> &'static [&'static str] {
+        match self.kind.as_str() {
+            // Headings are short and deliberate; fix them, don't rewrite them
+            "header" => &[FLAN_T5],
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockResult {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Offsets relative to the block's text
+    pub corrections: Vec<GrammarCorrection>,
+    pub stats: GrammarStats,
+    pub stages: Vec<StageReport>,
+}
+
+impl BlockResult {
+    pub fn new(block: &Block, response: GrammarResponse) -> Self {
+        Self {
+            id: block.id.clone(),
+            kind: block.kind.clone(),
+            corrections: response.corrections,
+            stats: response.stats,
+            stages: response.stages,
+        }
+    }
+
+    /// A block whose type is not checked.
+    pub fn unchecked(block: &Block) -> Self {
+        Self {
+            id: block.id.clone(),
+            kind: block.kind.clone(),
+            corrections: Vec::new(),
+            stats: GrammarStats::default(),
+            stages: Vec::new(),
+        }
+    }
+}
+
+/// Stats over every block.
+pub fn total_stats(blocks: &[BlockResult]) -> GrammarStats {
+    let mut total = GrammarStats::default();
+    blocks.iter().for_each(|block| total.add(&block.stats));
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn block(kind: &str) -> Block {
+        serde_json::from_value(json!({ "id": "b1", "type": kind, "text": "Some text." })).unwrap()
+    }
+
+    #[test]
+    fn blocks_default_to_paragraphs_in_the_request_format() {
+        let block: Block = serde_json::from_value(json!({ "id": "b1", "text": "Hi." })).unwrap();
+        assert_eq!((block.kind.as_str(), block.format), ("paragraph", None));
+
+        let block: Block = serde_json::from_value(json!({ "id": "b2", "text": "<b>Hi</b>", "format": "html" })).unwrap();
+        assert_eq!(block.format, Some(TextFormat::Html));
+    }
+
+    #[test]
+    fn code_and_media_blocks_are_not_checked() {
+        for kind in ["paragraph", "header", "list", "quote", "table", "someplugin"] {
+            assert!(block(kind).is_checked(), "{}", kind);
+        }
+        for kind in ["code", "raw", "delimiter", "image", "embed"] {
+            assert!(!block(kind).is_checked(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn headers_are_not_rewritten() {
+        assert_eq!(block("header").excluded_stages(), [FLAN_T5]);
+        assert!(block("paragraph").excluded_stages().is_empty());
+        assert!(block("list").excluded_stages().is_empty());
+    }
+
+    #[test]
+    fn totals_add_up_every_block() {
+        let stats = |critical, important, enhancement| GrammarStats {
+            total_issues: critical + important + enhancement,
+            critical,
+            important,
+            enhancement,
+        };
+        let mut first = BlockResult::unchecked(&block("paragraph"));
+        first.stats = stats(1, 2, 0);
+        let mut second = BlockResult::unchecked(&block("quote"));
+        second.stats = stats(0, 1, 3);
+        let skipped = BlockResult::unchecked(&block("code"));
+
+        let total = total_stats(&[first, second, skipped]);
+        assert_eq!((total.total_issues, total.critical, total.important, total.enhancement), (7, 1, 3, 3));
+    }
+}
@@ -0,0 +1,228 @@
+// lang/dictionary.rs - Persistent user and workspace word lists
+//
+// Each list is a plain text file with one word per line, stored under the
+// configured directory as `user/<id>.txt` or `workspace/<id>.txt`. Blank lines
+// and lines starting with `#` are ignored, so exported lists can be edited by
+// hand and imported again. Lists that exist are cached after the first read.
+use anyhow::{Error as E, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Custom words, merged from every list that applies to a request.
+pub type WordList = BTreeSet<String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    User,
+    Workspace,
+}
+
+impl Scope {
+    fn dir_name(self) -> &'static str {
+        match self {
+            Scope::User => "user",
+            Scope::Workspace => "workspace",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DictionaryStore {
+    dir: PathBuf,
+    cache: Mutex<HashMap<(Scope, String), Arc<WordList>>>,
+    // Serializes read-modify-write of the files
+    writes: Mutex<()>,
+}
+
+impl DictionaryStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            cache: Mutex::new(HashMap::new()),
+            writes: Mutex::new(()),
+        }
+    }
+
+    /// The words in one list; a list that was never written is empty.
+    pub fn words(&self, scope: Scope, id: &str) -> Result<Arc<WordList>> {
+        let path = self.path(scope, id)?;
+        let key = (scope, id.to_string());
+        if let Some(words) = self.cache.lock().unwrap().get(&key) {
+            return Ok(words.clone());
+        }
+
+        // Read without the lock so one slow disk read does not hold up every request.
+        // Missing lists are not cached: any id can be asked for, and they cost one stat.
+        let words = match std::fs::read_to_string(&path) {
+            Ok(text) => Arc::new(parse_word_list(&text)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Arc::new(WordList::new())),
+            Err(e) => return Err(E::msg(format!("Cannot read word list {}: {}", path.display(), e))),
+        };
+        // A list saved meanwhile is newer than what was read
+        Ok(self.cache.lock().unwrap().entry(key).or_insert(words).clone())
+    }
+
+    /// Words from the user's and the workspace's lists together.
+    pub fn merged(&self, user: Option<&str>, workspace: Option<&str>) -> Result<WordList> {
+        let mut words = WordList::new();
+        if let Some(user) = user {
+            words.extend(self.words(Scope::User, user)?.iter().cloned());
+        }
+        if let Some(workspace) = workspace {
+            words.extend(self.words(Scope::Workspace, workspace)?.iter().cloned());
+        }
+        Ok(words)
+    }
+
+    pub fn add(&self, scope: Scope, id: &str, words: &[String]) -> Result<Arc<WordList>> {
+        let added = words.iter().map(|word| check_word(word)).collect::<Result<Vec<_>>>()?;
+        self.update(scope, id, |list| list.extend(added))
+    }
+
+    pub fn remove(&self, scope: Scope, id: &str, words: &[String]) -> Result<Arc<WordList>> {
+        self.update(scope, id, |list| {
+            for word in words {
+                list.remove(word.trim());
+            }
+        })
+    }
+
+    /// Replace a list with the words in `text` (the export format).
+    pub fn import(&self, scope: Scope, id: &str, text: &str) -> Result<Arc<WordList>> {
+        let words = parse_word_list(text)?;
+        self.update(scope, id, |list| *list = words)
+    }
+
+    /// The list as text, one word per line.
+    pub fn export(&self, scope: Scope, id: &str) -> Result<String> {
+        Ok(format_word_list(&*self.words(scope, id)?))
+    }
+
+    /// Change a list and write it back before the change becomes visible.
+    fn update(&self, scope: Scope, id: &str, change: impl FnOnce(&mut WordList)) -> Result<Arc<WordList>> {
+        let _writing = self.writes.lock().unwrap();
+        let mut words = (*self.words(scope, id)?).clone();
+        change(&mut words);
+
+        let path = self.path(scope, id)?;
+        write_word_list(&path, &words)
+            .map_err(|e| E::msg(format!("Cannot write word list {}: {}", path.display(), e)))?;
+        info!("Saved {} word(s) to the {} list '{}'", words.len(), scope.dir_name(), id);
+
+        let words = Arc::new(words);
+        self.cache.lock().unwrap().insert((scope, id.to_string()), words.clone());
+        Ok(words)
+    }
+
+    fn path(&self, scope: Scope, id: &str) -> Result<PathBuf> {
+        // Ids become file names, so keep them to a safe alphabet
+        let valid = !id.is_empty()
+            && id.len() <= 64
+            && !id.starts_with('.')
+            && id.chars().all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'));
+        if !valid {
+            return Err(E::msg(format!("Invalid {} id '{}': use letters, digits, '-', '_' and '.'", scope.dir_name(), id)));
+        }
+        Ok(self.dir.join(scope.dir_name()).join(format!("{}.txt", id)))
+    }
+}
+
+/// Whether replacing `original` by `replacement` would change a custom word,
+/// including only its case. Used to keep the models off product names and jargon.
+pub fn rewrites_custom_word(words: &WordList, original: &str, replacement: &str) -> bool {
+    if words.is_empty() {
+        return false;
+    }
+    let tokens = |text: &str| -> Vec<String> {
+        text.split(|ch: char| !(ch.is_alphanumeric() || ch == '\'' || ch == '-'))
+            .filter(|token| !token.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let kept = tokens(replacement);
+
+    tokens(original).into_iter().any(|token| {
+        let lower = token.to_lowercase();
+        words.iter().any(|word| word.to_lowercase() == lower) && !kept.contains(&token)
+    })
+}
+
+/// Words in the export format; a line with more than one word is an error.
+pub fn parse_word_list(text: &str) -> Result<WordList> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| check_word(line).map_err(|e| E::msg(format!("Line {}: {}", index + 1, e))))
+        .collect()
+}
+
+fn check_word(word: &str) -> Result<String> {
+    let word = word.trim();
+    if word.is_empty() {
+        return Err(E::msg("Empty word"));
+    }
+    if word.chars().any(char::is_whitespace) {
+        return Err(E::msg(format!("'{}' is not a single word", word)));
+    }
+    Ok(word.to_string())
+}
+
+/// Write through a temporary file so a crash never leaves a half-written list.
+fn write_word_list(path: &Path, words: &WordList) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("txt.tmp");
+    std::fs::write(&temp, format_word_list(words))?;
+    std::fs::rename(&temp, path)
+}
+
+fn format_word_list(words: &WordList) -> String {
+    words.iter().map(|word| format!("{}\n", word)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store in a fresh directory under the system temp dir.
+    fn store(name: &str) -> DictionaryStore {
+        let dir = std::env::temp_dir().join(format!("quillguard-dictionary-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        DictionaryStore::new(dir)
+    }
+
+    #[test]
+    fn missing_lists_are_empty_and_not_cached() {
+        let store = store("missing");
+        for id in ["a", "b", "c"] {
+            assert!(store.words(Scope::User, id).unwrap().is_empty());
+        }
+        assert!(store.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn saved_lists_are_read_back() {
+        let store = store("saved");
+        store.add(Scope::Workspace, "docs", &["QuillGuard".to_string(), "Tokio".to_string()]).unwrap();
+        store.remove(Scope::Workspace, "docs", &["Tokio".to_string()]).unwrap();
+
+        let fresh = DictionaryStore::new(&store.dir);
+        let words = fresh.merged(Some("nobody"), Some("docs")).unwrap();
+        assert_eq!(words.into_iter().collect::<Vec<_>>(), ["QuillGuard"]);
+        let _ = std::fs::remove_dir_all(&store.dir);
+    }
+
+    #[test]
+    fn ids_that_are_not_file_names_are_rejected() {
+        let store = store("ids");
+        for id in ["", "../etc", ".hidden", "a b"] {
+            assert!(store.words(Scope::User, id).is_err(), "{:?}", id);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// lang/edits.rs - Applying byte-range edits and mapping offsets back to the source
+use serde::{Deserialize, Serialize};
+
+/// Replace `length` bytes at `offset` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    pub offset: usize,
+    pub length: usize,
+    pub replacement: String,
+}
+
+impl Edit {
+    pub fn new(offset: usize, length: usize, replacement: impl Into<String>) -> Self {
+        Self {
+            offset,
+            length,
+            replacement: replacement.into(),
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+}
+
+/// Where each applied edit landed: `(source offset, source length, target offset, target length)`.
+#[derive(Debug, Clone, Default)]
+pub struct EditMap {
+    segments: Vec<(usize, usize, usize, usize)>,
+}
+
+impl EditMap {
+    /// Map a position in the edited text back to the source. A span start
+    /// inside a replacement snaps to the start of what it replaced, a span end
+    /// to its end, so the mapped span covers every source byte involved.
+    fn to_source(&self, position: usize, end: bool) -> usize {
+        let mut shift = 0isize;
+
+        for &(source_offset, source_length, target_offset, target_length) in &self.segments {
+            let target_end = target_offset + target_length;
+            if end {
+                if position <= target_offset {
+                    break;
+                }
+                if position <= target_end {
+                    return source_offset + source_length;
+                }
+            } else {
+                if position < target_offset {
+                    break;
+                }
+                if position < target_end {
+                    return source_offset;
+                }
+            }
+            shift = (source_offset + source_length) as isize - target_end as isize;
+        }
+
+        (position as isize + shift) as usize
+    }
+
+    /// `(offset, length)` in the source of each edit that was applied, in offset order.
+    pub fn applied(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.segments.iter().map(|&(source_offset, source_length, _, _)| (source_offset, source_length))
+    }
+
+    /// Map a `[start, end)` span of the edited text back to the source.
+    pub fn span_to_source(&self, start: usize, end: usize) -> (usize, usize) {
+        let source_start = self.to_source(start, false);
+        let source_end = self.to_source(end, true).max(source_start);
+        (source_start, source_end)
+    }
+}
+
+/// Apply `edits` to `source`. Edits are applied in offset order; any that
+/// overlap an earlier one or do not fall on char boundaries are skipped.
+pub fn apply_edits(source: &str, edits: &[Edit]) -> (String, EditMap) {
+    let mut sorted: Vec<&Edit> = edits.iter().collect();
+    sorted.sort_by_key(|edit| (edit.offset, edit.length));
+
+    let mut output = String::with_capacity(source.len());
+    let mut map = EditMap::default();
+    let mut cursor = 0;
+
+    for edit in sorted {
+        let valid = edit.offset >= cursor
+            && edit.end() <= source.len()
+            && source.is_char_boundary(edit.offset)
+            && source.is_char_boundary(edit.end());
+        if !valid {
+            continue;
+        }
+
+        output.push_str(&source[cursor..edit.offset]);
+        map.segments.push((edit.offset, edit.length, output.len(), edit.replacement.len()));
+        output.push_str(&edit.replacement);
+        cursor = edit.end();
+    }
+    output.push_str(&source[cursor..]);
+
+    (output, map)
+}
@@ -0,0 +1,572 @@
+// lang/errant.rs - ERRANT-style error types for AI edits
+//
+// ERRANT (Bryant et al., 2017) labels an edit with an operation (missing,
+// replacement, unnecessary) and a linguistic type. We approximate its rules
+// with Harper's dictionary metadata for part of speech and a few closed word
+// lists, which is enough to name the common learner errors.
+use std::borrow::Cow;
+use harper_core::{DictWordMetadata, spell::Dictionary};
+use serde::{Deserialize, Serialize};
+use crate::lang::align::tokenize;
+
+/// What the edit does to the original text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Missing,
+    Replacement,
+    Unnecessary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorType {
+    Adjective,
+    Adverb,
+    Conjunction,
+    Contraction,
+    Determiner,
+    Morphology,
+    Noun,
+    NounNumber,
+    Orthography,
+    Other,
+    Preposition,
+    Pronoun,
+    Punctuation,
+    Spelling,
+    SubjectVerbAgreement,
+    Verb,
+    VerbForm,
+    VerbTense,
+    WordOrder,
+}
+
+impl ErrorType {
+    /// Value used for `GrammarCorrection.subcategory`.
+    pub fn subcategory(self) -> &'static str {
+        match self {
+            Self::Adjective => "adjective",
+            Self::Adverb => "adverb",
+            Self::Conjunction => "conjunction",
+            Self::Contraction => "contraction",
+            Self::Determiner => "determiner",
+            Self::Morphology => "morphology",
+            Self::Noun => "noun",
+            Self::NounNumber => "noun_number",
+            Self::Orthography => "orthography",
+            Self::Other => "other",
+            Self::Preposition => "preposition",
+            Self::Pronoun => "pronoun",
+            Self::Punctuation => "punctuation",
+            Self::Spelling => "spelling",
+            Self::SubjectVerbAgreement => "subject_verb_agreement",
+            Self::Verb => "verb",
+            Self::VerbForm => "verb_form",
+            Self::VerbTense => "verb_tense",
+            Self::WordOrder => "word_order",
+        }
+    }
+
+    /// Human-readable name, e.g. "Subject–verb agreement".
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Adjective => "Adjective",
+            Self::Adverb => "Adverb",
+            Self::Conjunction => "Conjunction",
+            Self::Contraction => "Contraction",
+            Self::Determiner => "Determiner",
+            Self::Morphology => "Word form",
+            Self::Noun => "Noun",
+            Self::NounNumber => "Noun number",
+            Self::Orthography => "Capitalization",
+            Self::Other => "Word",
+            Self::Preposition => "Preposition",
+            Self::Pronoun => "Pronoun",
+            Self::Punctuation => "Punctuation",
+            Self::Spelling => "Spelling",
+            Self::SubjectVerbAgreement => "Subject–verb agreement",
+            Self::Verb => "Verb",
+            Self::VerbForm => "Verb form",
+            Self::VerbTense => "Verb tense",
+            Self::WordOrder => "Word order",
+        }
+    }
+}
+
+/// The classification of one edit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EditClass {
+    pub operation: Operation,
+    pub error_type: ErrorType,
+}
+
+impl EditClass {
+    /// One-line explanation such as "Subject–verb agreement: has → have" or
+    /// "Missing determiner: add 'the'".
+    pub fn explain(&self, original: &str, corrected: &str) -> String {
+        let (original, corrected) = (original.trim(), corrected.trim());
+        let name = self.error_type.label().to_lowercase();
+
+        match self.operation {
+            Operation::Missing => format!("Missing {}: add '{}'", name, corrected),
+            Operation::Unnecessary => format!("Unnecessary {}: remove '{}'", name, original),
+            Operation::Replacement => format!("{}: {} → {}", self.error_type.label(), original, corrected),
+        }
+    }
+}
+
+const SUBJECT_VERB_PAIRS: &[(&str, &str)] = &[
+    ("is", "are"),
+    ("was", "were"),
+    ("has", "have"),
+    ("does", "do"),
+    ("isn't", "aren't"),
+    ("wasn't", "weren't"),
+    ("hasn't", "haven't"),
+    ("doesn't", "don't"),
+];
+
+const DETERMINERS: &[&str] = &[
+    "a", "an", "the", "this", "that", "these", "those", "some", "any", "each", "every", "no",
+    "my", "your", "his", "her", "its", "our", "their", "another", "either", "neither",
+];
+
+const PREPOSITIONS: &[&str] = &[
+    "about", "above", "across", "after", "against", "along", "among", "around", "at", "before",
+    "behind", "below", "beneath", "beside", "between", "beyond", "by", "despite", "down",
+    "during", "for", "from", "in", "inside", "into", "near", "of", "off", "on", "onto", "out",
+    "outside", "over", "since", "through", "throughout", "to", "toward", "towards", "under",
+    "until", "up", "upon", "with", "within", "without",
+];
+
+const CONJUNCTIONS: &[&str] = &[
+    "and", "or", "but", "nor", "yet", "so", "because", "although", "though", "while", "whereas",
+    "if", "unless", "whether",
+];
+
+const PRONOUNS: &[&str] = &[
+    "i", "me", "you", "he", "him", "she", "it", "we", "us", "they", "them", "myself", "yourself",
+    "himself", "herself", "itself", "ourselves", "themselves", "who", "whom", "which", "mine",
+    "yours", "hers", "ours", "theirs",
+];
+
+const AUXILIARIES: &[&str] = &[
+    "am", "is", "are", "was", "were", "be", "been", "being", "have", "has", "had", "do", "does",
+    "did", "will", "would", "shall", "should", "can", "could", "may", "might", "must",
+];
+
+const IRREGULAR_PAST: &[&str] = &[
+    "was", "were", "had", "did", "went", "came", "saw", "took", "gave", "made", "said", "got",
+    "knew", "thought", "told", "became", "left", "felt", "brought", "began", "kept", "held",
+    "wrote", "stood", "heard", "ran", "ate", "drank", "found", "bought", "taught", "caught",
+    "sat", "spoke", "chose", "drove", "flew", "grew", "threw", "wore", "won", "lost", "sent",
+    "spent", "built", "meant", "met", "paid", "sold", "understood", "forgot",
+];
+
+const QUANTIFIERS: &[&str] = &[
+    "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "many", "several",
+    "few", "both", "all", "most", "more", "various", "numerous",
+];
+
+/// Coarse part of speech of one word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pos {
+    Determiner,
+    Preposition,
+    Conjunction,
+    Pronoun,
+    Verb,
+    Noun,
+    Adjective,
+    Adverb,
+    Punctuation,
+    Unknown,
+}
+
+impl Pos {
+    fn error_type(self) -> ErrorType {
+        match self {
+            Self::Determiner => ErrorType::Determiner,
+            Self::Preposition => ErrorType::Preposition,
+            Self::Conjunction => ErrorType::Conjunction,
+            Self::Pronoun => ErrorType::Pronoun,
+            Self::Verb => ErrorType::Verb,
+            Self::Noun => ErrorType::Noun,
+            Self::Adjective => ErrorType::Adjective,
+            Self::Adverb => ErrorType::Adverb,
+            Self::Punctuation => ErrorType::Punctuation,
+            Self::Unknown => ErrorType::Other,
+        }
+    }
+}
+
+struct Classifier<'d, D: Dictionary> {
+    dictionary: &'d D,
+}
+
+impl<D: Dictionary> Classifier<'_, D> {
+    fn metadata(&self, word: &str) -> Option<Cow<'_, DictWordMetadata>> {
+        self.dictionary
+            .get_word_metadata_str(word)
+            .or_else(|| self.dictionary.get_word_metadata_str(&word.to_lowercase()))
+    }
+
+    /// Closed word classes win over dictionary metadata, which lists every
+    /// reading ("that" is also a pronoun and a conjunction).
+    fn pos(&self, word: &str) -> Pos {
+        let lower = word.to_lowercase();
+        if !word.chars().any(char::is_alphanumeric) {
+            return Pos::Punctuation;
+        }
+        if DETERMINERS.contains(&lower.as_str()) {
+            return Pos::Determiner;
+        }
+        if PREPOSITIONS.contains(&lower.as_str()) {
+            return Pos::Preposition;
+        }
+        if CONJUNCTIONS.contains(&lower.as_str()) {
+            return Pos::Conjunction;
+        }
+        if PRONOUNS.contains(&lower.as_str()) {
+            return Pos::Pronoun;
+        }
+
+        if AUXILIARIES.contains(&lower.as_str()) || IRREGULAR_PAST.contains(&lower.as_str()) {
+            return Pos::Verb;
+        }
+
+        // Most dictionary words carry several readings, so prefer the one the
+        // word's shape or exclusivity points to
+        let Some(metadata) = self.metadata(word) else {
+            return Pos::Unknown;
+        };
+        let (noun, verb, adjective) = (metadata.is_noun(), metadata.is_verb(), metadata.is_adjective());
+        if metadata.is_adverb() && (lower.ends_with("ly") || !(noun || verb || adjective)) {
+            Pos::Adverb
+        } else if verb && (lower.ends_with("ed") || lower.ends_with("ing") || !noun) {
+            Pos::Verb
+        } else if adjective && !verb {
+            Pos::Adjective
+        } else if noun {
+            Pos::Noun
+        } else if verb {
+            Pos::Verb
+        } else if adjective {
+            Pos::Adjective
+        } else {
+            Pos::Unknown
+        }
+    }
+
+    fn is_verb(&self, word: &str) -> bool {
+        AUXILIARIES.contains(&word.to_lowercase().as_str()) || self.metadata(word).is_some_and(|m| m.is_verb())
+    }
+
+    fn is_past(&self, word: &str) -> bool {
+        let lower = word.to_lowercase();
+        IRREGULAR_PAST.contains(&lower.as_str())
+            || lower.ends_with("ed")
+            || self.metadata(word).is_some_and(|m| m.is_verb_past_form())
+    }
+
+    fn is_known(&self, word: &str) -> bool {
+        self.dictionary.contains_word_str(word) || self.dictionary.contains_word_str(&word.to_lowercase())
+    }
+
+    fn classify(&self, original: &[&str], corrected: &[&str], previous: Option<&str>) -> ErrorType {
+        let all = || original.iter().chain(corrected);
+
+        if all().all(|w| self.pos(w) == Pos::Punctuation) {
+            return ErrorType::Punctuation;
+        }
+
+        // Missing or unnecessary words: the type of what was added or removed
+        if original.is_empty() || corrected.is_empty() {
+            let words: Vec<&str> = all().copied().filter(|w| self.pos(w) != Pos::Punctuation).collect();
+            let first = self.pos(words[0]);
+            return if words.iter().all(|w| self.pos(w) == first) {
+                first.error_type()
+            } else {
+                ErrorType::Other
+            };
+        }
+
+        if original.concat().to_lowercase() == corrected.concat().to_lowercase() {
+            return ErrorType::Orthography;
+        }
+
+        let mut sorted_original: Vec<String> = original.iter().map(|w| w.to_lowercase()).collect();
+        let mut sorted_corrected: Vec<String> = corrected.iter().map(|w| w.to_lowercase()).collect();
+        sorted_original.sort();
+        sorted_corrected.sort();
+        if original.len() > 1 && sorted_original == sorted_corrected {
+            return ErrorType::WordOrder;
+        }
+
+        let strip = |words: &[&str]| -> String {
+            words.iter().flat_map(|w| w.chars()).filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase()
+        };
+        let has_apostrophe = |words: &[&str]| words.iter().any(|w| w.contains(['\'', '’']));
+        if (has_apostrophe(original) || has_apostrophe(corrected))
+            && (strip(original) == strip(corrected) || is_contraction_of(original, corrected) || is_contraction_of(corrected, original))
+        {
+            return ErrorType::Contraction;
+        }
+
+        match (original, corrected) {
+            ([from], [to]) => self.classify_word(from, to, previous),
+            _ if all().all(|w| self.is_verb(w)) => {
+                if original.iter().any(|w| self.is_past(w)) != corrected.iter().any(|w| self.is_past(w)) {
+                    ErrorType::VerbTense
+                } else {
+                    ErrorType::VerbForm
+                }
+            }
+            _ => ErrorType::Other,
+        }
+    }
+
+    fn classify_word(&self, from: &str, to: &str, previous: Option<&str>) -> ErrorType {
+        let (lower_from, lower_to) = (from.to_lowercase(), to.to_lowercase());
+
+        if SUBJECT_VERB_PAIRS.iter().any(|&(a, b)| (a, b) == (&lower_from, &lower_to) || (b, a) == (&lower_from, &lower_to)) {
+            return ErrorType::SubjectVerbAgreement;
+        }
+        if !self.is_known(from) && self.is_known(to) && similar(&lower_from, &lower_to) {
+            return ErrorType::Spelling;
+        }
+
+        let (pos_from, pos_to) = (self.pos(from), self.pos(to));
+        if pos_from == pos_to && matches!(pos_from, Pos::Determiner | Pos::Preposition | Pos::Conjunction | Pos::Pronoun) {
+            return pos_from.error_type();
+        }
+
+        let same_stem = shares_stem(&lower_from, &lower_to);
+
+        // "like" → "likes" reads as a verb after a subject and as a noun after a determiner
+        if same_stem && differs_by_plural_suffix(&lower_from, &lower_to) {
+            let after_determiner = previous.is_some_and(|p| {
+                QUANTIFIERS.contains(&p.to_lowercase().as_str())
+                    || p.chars().all(|c| c.is_ascii_digit())
+                    || matches!(self.pos(p), Pos::Determiner | Pos::Adjective)
+            });
+            let after_subject = previous.is_some_and(|p| matches!(self.pos(p), Pos::Pronoun | Pos::Noun | Pos::Unknown));
+
+            if !after_determiner && after_subject && self.is_verb(from) && self.is_verb(to) {
+                return ErrorType::SubjectVerbAgreement;
+            }
+            return ErrorType::NounNumber;
+        }
+
+        if self.is_verb(from) && self.is_verb(to) && (same_stem || self.is_past(from) != self.is_past(to)) {
+            return if self.is_past(from) != self.is_past(to) {
+                ErrorType::VerbTense
+            } else {
+                ErrorType::VerbForm
+            };
+        }
+
+        if same_stem {
+            return ErrorType::Morphology;
+        }
+        if pos_from == pos_to {
+            return pos_from.error_type();
+        }
+        if !self.is_known(from) && similar(&lower_from, &lower_to) {
+            return ErrorType::Spelling;
+        }
+        ErrorType::Other
+    }
+}
+
+/// "don't" for "do not", "it's" for "it is".
+fn is_contraction_of(short: &[&str], long: &[&str]) -> bool {
+    let [word] = short else {
+        return false;
+    };
+    let Some(index) = word.find(['\'', '’']) else {
+        return false;
+    };
+    let stem = &word[..index];
+    long.len() == 2
+        && long[0].to_lowercase().starts_with(stem.to_lowercase().trim_end_matches('n'))
+        && matches!(long[1].to_lowercase().as_str(), "not" | "is" | "are" | "am" | "will" | "would" | "have" | "has" | "had")
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(x, y)| x == y).count()
+}
+
+/// Inflections of one another: "study"/"studies", "walk"/"walked", "quick"/"quickly".
+fn shares_stem(a: &str, b: &str) -> bool {
+    let shorter = a.chars().count().min(b.chars().count());
+    let prefix = common_prefix(a, b);
+    prefix >= 3.min(shorter) && prefix + 2 >= shorter
+}
+
+fn differs_by_plural_suffix(a: &str, b: &str) -> bool {
+    let (short, long) = if a.len() < b.len() { (a, b) } else { (b, a) };
+    let Some(suffix) = long.strip_prefix(short.trim_end_matches('y')) else {
+        return false;
+    };
+    matches!(suffix, "s" | "es" | "ies")
+}
+
+/// Close enough to be a misspelling: edit distance (counting a swap of two
+/// neighbouring letters as one) at most a third of the length.
+fn similar(a: &str, b: &str) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let width = b.len() + 1;
+
+    let mut distance = vec![0usize; (a.len() + 1) * width];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            distance[i * width + j] = if i == 0 || j == 0 {
+                i + j
+            } else {
+                let mut best = (distance[(i - 1) * width + j] + 1)
+                    .min(distance[i * width + j - 1] + 1)
+                    .min(distance[(i - 1) * width + j - 1] + usize::from(a[i - 1] != b[j - 1]));
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    best = best.min(distance[(i - 2) * width + j - 2] + 1);
+                }
+                best
+            };
+        }
+    }
+
+    distance[a.len() * width + b.len()] * 3 <= a.len().max(b.len()).max(3)
+}
+
+/// Classify the edit replacing `original` with `corrected`. `previous` is the
+/// word before the edit in the original text, used to tell verbs from nouns.
+pub fn classify_edit(dictionary: &impl Dictionary, original: &str, corrected: &str, previous: Option<&str>) -> EditClass {
+    let original_words: Vec<&str> = tokenize(original).into_iter().map(|r| &original[r]).collect();
+    let corrected_words: Vec<&str> = tokenize(corrected).into_iter().map(|r| &corrected[r]).collect();
+
+    let operation = match (original_words.is_empty(), corrected_words.is_empty()) {
+        (true, _) => Operation::Missing,
+        (_, true) => Operation::Unnecessary,
+        _ => Operation::Replacement,
+    };
+    let error_type = if original_words.is_empty() && corrected_words.is_empty() {
+        ErrorType::Other
+    } else {
+        Classifier { dictionary }.classify(&original_words, &corrected_words, previous)
+    };
+
+    EditClass { operation, error_type }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use harper_core::spell::FstDictionary;
+
+    fn classify(original: &str, corrected: &str, previous: Option<&str>) -> EditClass {
+        classify_edit(&*FstDictionary::curated(), original, corrected, previous)
+    }
+
+    fn error_type(original: &str, corrected: &str, previous: Option<&str>) -> ErrorType {
+        classify(original, corrected, previous).error_type
+    }
+
+    #[test]
+    fn operations_follow_what_is_added_or_removed() {
+        assert_eq!(classify("", "the", Some("read")).operation, Operation::Missing);
+        assert_eq!(classify("to", "", Some("went")).operation, Operation::Unnecessary);
+        assert_eq!(classify("has", "have", Some("they")).operation, Operation::Replacement);
+        assert_eq!(classify(" ", "", None), EditClass { operation: Operation::Missing, error_type: ErrorType::Other });
+    }
+
+    #[test]
+    fn subject_verb_agreement() {
+        assert_eq!(error_type("has", "have", Some("they")), ErrorType::SubjectVerbAgreement);
+        assert_eq!(error_type("Was", "were", Some("we")), ErrorType::SubjectVerbAgreement);
+        assert_eq!(error_type("doesn't", "don't", Some("they")), ErrorType::SubjectVerbAgreement);
+        // A plural suffix after a subject is agreement, after a determiner noun number
+        assert_eq!(error_type("like", "likes", Some("she")), ErrorType::SubjectVerbAgreement);
+        assert_eq!(error_type("book", "books", Some("two")), ErrorType::NounNumber);
+        assert_eq!(error_type("city", "cities", Some("the")), ErrorType::NounNumber);
+        assert_eq!(error_type("box", "boxes", Some("3")), ErrorType::NounNumber);
+    }
+
+    #[test]
+    fn closed_word_classes() {
+        assert_eq!(error_type("a", "an", None), ErrorType::Determiner);
+        assert_eq!(error_type("", "the", Some("read")), ErrorType::Determiner);
+        assert_eq!(error_type("in", "on", Some("sat")), ErrorType::Preposition);
+        assert_eq!(error_type("to", "", Some("went")), ErrorType::Preposition);
+        assert_eq!(error_type("and", "but", None), ErrorType::Conjunction);
+        assert_eq!(error_type("him", "he", None), ErrorType::Pronoun);
+        // Added words of different classes have no single type
+        assert_eq!(error_type("", "the big", None), ErrorType::Other);
+    }
+
+    #[test]
+    fn verb_tense_and_form() {
+        assert_eq!(error_type("walk", "walked", Some("I")), ErrorType::VerbTense);
+        assert_eq!(error_type("go", "went", Some("I")), ErrorType::VerbTense);
+        assert_eq!(error_type("will go", "went", Some("I")), ErrorType::VerbTense);
+        assert_eq!(error_type("is go", "is going", Some("it")), ErrorType::VerbForm);
+    }
+
+    #[test]
+    fn spelling_case_order_and_contractions() {
+        assert_eq!(error_type("recieve", "receive", None), ErrorType::Spelling);
+        assert_eq!(error_type("paris", "Paris", None), ErrorType::Orthography);
+        assert_eq!(error_type("is it", "it is", None), ErrorType::WordOrder);
+        assert_eq!(error_type("dont", "don't", None), ErrorType::Contraction);
+        assert_eq!(error_type("do not", "don't", None), ErrorType::Contraction);
+        assert_eq!(error_type(",", ";", None), ErrorType::Punctuation);
+        assert_eq!(error_type("", ",", None), ErrorType::Punctuation);
+    }
+
+    #[test]
+    fn open_word_classes_and_word_forms() {
+        assert_eq!(error_type("happy", "happily", None), ErrorType::Morphology);
+        assert_eq!(error_type("cat", "dog", Some("the")), ErrorType::Noun);
+        assert_eq!(error_type("quickly", "slowly", None), ErrorType::Adverb);
+        assert_eq!(error_type("tiny", "enormous", Some("very")), ErrorType::Adjective);
+        assert_eq!(error_type("seem", "appear", Some("they")), ErrorType::Verb);
+        assert_eq!(error_type("xqzt", "table", None), ErrorType::Other);
+    }
+
+    #[test]
+    fn explanations_name_the_edit() {
+        assert_eq!(classify("has", "have", Some("they")).explain(" has", "have "), "Subject–verb agreement: has → have");
+        assert_eq!(classify("", "the", Some("read")).explain("", " the"), "Missing determiner: add 'the'");
+        assert_eq!(classify("to", "", Some("went")).explain("to ", ""), "Unnecessary preposition: remove 'to'");
+    }
+
+    #[test]
+    fn subcategories_are_snake_case_labels() {
+        assert_eq!(ErrorType::SubjectVerbAgreement.subcategory(), "subject_verb_agreement");
+        assert_eq!(ErrorType::NounNumber.label(), "Noun number");
+        assert_eq!(ErrorType::Orthography.label(), "Capitalization");
+    }
+
+    #[test]
+    fn word_helpers() {
+        assert!(similar("recieve", "receive"));
+        assert!(similar("teh", "the"));
+        assert!(!similar("cat", "dog"));
+
+        assert!(shares_stem("study", "studies"));
+        assert!(shares_stem("walk", "walked"));
+        assert!(shares_stem("quick", "quickly"));
+        assert!(!shares_stem("walk", "talk"));
+
+        assert!(differs_by_plural_suffix("city", "cities"));
+        assert!(differs_by_plural_suffix("boxes", "box"));
+        assert!(!differs_by_plural_suffix("walk", "walked"));
+
+        assert!(is_contraction_of(&["don't"], &["do", "not"]));
+        assert!(is_contraction_of(&["It's"], &["it", "is"]));
+        assert!(is_contraction_of(&["won't"], &["wo", "not"]));
+        assert!(!is_contraction_of(&["dont"], &["do", "not"]));
+        assert!(!is_contraction_of(&["don't"], &["do"]));
+    }
+}
@@ -0,0 +1,311 @@
+// lang/format.rs - Input formats and the prose regions within them
+//
+// Harper parses Markdown itself and skips code, HTML and front matter; HTML
+// goes through our own parser in `html.rs`. The AI stages work on raw text, so
+// they are only given prose: each block (paragraph, heading, list item, table
+// cell) becomes a segment, and corrections that would touch markup inside a
+// segment (code spans, link targets, emphasis markers, tags) are dropped.
+// Offsets always point into the original source.
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use crate::lang::html::{self, PieceKind};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextFormat {
+    #[default]
+    Plain,
+    Markdown,
+    Html,
+}
+
+impl TextFormat {
+    /// Format for an LSP `languageId`.
+    pub fn from_language_id(language_id: &str) -> Self {
+        match language_id {
+            "markdown" | "mdx" => TextFormat::Markdown,
+            "html" => TextFormat::Html,
+            _ => TextFormat::Plain,
+        }
+    }
+
+    /// Format for a file extension, like `md` or `html`.
+    pub fn from_extension(extension: &str) -> Self {
+        match extension.to_ascii_lowercase().as_str() {
+            "md" | "markdown" | "mdx" => TextFormat::Markdown,
+            "html" | "htm" | "xhtml" => TextFormat::Html,
+            _ => TextFormat::Plain,
+        }
+    }
+
+    /// Byte ranges of the paragraphs of `text`: pieces that can be checked
+    /// on their own and give the same result as in place. Plain text splits
+    /// at blank lines, Markdown into top-level blocks and HTML at block
+    /// elements. Blank paragraphs are left out.
+    pub fn paragraphs(self, text: &str) -> Vec<Range<usize>> {
+        let mut paragraphs = match self {
+            TextFormat::Plain => plain_paragraphs(text),
+            TextFormat::Markdown => markdown_paragraphs(text),
+            TextFormat::Html => html_paragraphs(text),
+        };
+        paragraphs.retain(|paragraph| !text[paragraph.clone()].trim().is_empty());
+        paragraphs
+    }
+}
+
+fn plain_paragraphs(text: &str) -> Vec<Range<usize>> {
+    let mut paragraphs = Vec::new();
+    let mut start = None;
+    let mut line_start = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        match (line.trim().is_empty(), start) {
+            (true, Some(paragraph_start)) => {
+                paragraphs.push(paragraph_start..line_start);
+                start = None;
+            }
+            (false, None) => start = Some(line_start),
+            _ => {}
+        }
+        line_start = line_end;
+    }
+    if let Some(paragraph_start) = start {
+        paragraphs.push(paragraph_start..text.len());
+    }
+    paragraphs
+}
+
+fn markdown_paragraphs(text: &str) -> Vec<Range<usize>> {
+    let options = Options::all().difference(Options::ENABLE_SMART_PUNCTUATION);
+    let mut paragraphs = Vec::new();
+    let mut depth = 0;
+
+    // A top-level start event spans its whole block
+    for (event, range) in Parser::new_ext(text, options).into_offset_iter() {
+        match event {
+            Event::Start(_) => {
+                if depth == 0 {
+                    paragraphs.push(range);
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+    }
+    paragraphs
+}
+
+fn html_paragraphs(text: &str) -> Vec<Range<usize>> {
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+
+    for piece in html::scan(text) {
+        if piece.kind == PieceKind::Break {
+            paragraphs.push(start..piece.range.start);
+            start = piece.range.end;
+        }
+    }
+    paragraphs.push(start..text.len());
+    paragraphs
+}
+
+/// Where the prose is in a structured document, in bytes of the source.
+#[derive(Debug, Clone, Default)]
+pub struct ProseMap {
+    /// Blocks of prose, in order; markup inside a block is part of it
+    segments: Vec<Range<usize>>,
+    /// Runs of plain prose text, in order and merged where they touch
+    prose: Vec<Range<usize>>,
+}
+
+impl ProseMap {
+    pub fn markdown(text: &str) -> Self {
+        // Same extensions as Harper's own Markdown parser
+        let options = Options::all().difference(Options::ENABLE_SMART_PUNCTUATION);
+        let mut map = Self::default();
+        let mut stack: Vec<Tag> = Vec::new();
+        let mut segment: Option<Range<usize>> = None;
+
+        for (event, range) in Parser::new_ext(text, options).into_offset_iter() {
+            let is_prose = match &event {
+                Event::Text(_) => stack.last().is_some_and(is_prose_tag),
+                Event::SoftBreak | Event::HardBreak => true,
+                Event::Start(tag) => {
+                    if is_block_tag(tag) {
+                        map.close(&mut segment);
+                    }
+                    stack.push(tag.clone());
+                    false
+                }
+                Event::End(tag) => {
+                    if is_block_end(tag) {
+                        map.close(&mut segment);
+                    }
+                    stack.pop();
+                    false
+                }
+                _ => false,
+            };
+
+            if is_prose {
+                map.push_prose(range, &mut segment);
+            }
+        }
+        map.close(&mut segment);
+        map.drop_blank_segments(text);
+        map
+    }
+
+    pub fn html(text: &str) -> Self {
+        let mut map = Self::default();
+        let mut segment: Option<Range<usize>> = None;
+
+        for piece in html::scan(text) {
+            match piece.kind {
+                PieceKind::Text => map.push_prose(piece.range, &mut segment),
+                PieceKind::Markup => {}
+                PieceKind::Break => map.close(&mut segment),
+            }
+        }
+        map.close(&mut segment);
+        map.drop_blank_segments(text);
+        map
+    }
+
+    /// Extend the open segment, or open one, with a run of prose.
+    fn push_prose(&mut self, range: Range<usize>, segment: &mut Option<Range<usize>>) {
+        if range.is_empty() {
+            return;
+        }
+        match segment {
+            Some(segment) => segment.end = range.end,
+            None => *segment = Some(range.clone()),
+        }
+        match self.prose.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => self.prose.push(range),
+        }
+    }
+
+    /// Whitespace between blocks is no sentence.
+    fn drop_blank_segments(&mut self, text: &str) {
+        self.segments.retain(|segment| !text[segment.clone()].trim().is_empty());
+    }
+
+    fn close(&mut self, segment: &mut Option<Range<usize>>) {
+        if let Some(segment) = segment.take() {
+            self.segments.push(segment);
+        }
+    }
+
+    pub fn segments(&self) -> &[Range<usize>] {
+        &self.segments
+    }
+
+    /// Whether an edit of `range` stays within prose. Insertions may sit at
+    /// the edge of a prose run, e.g. right before a code span.
+    pub fn is_prose(&self, range: Range<usize>) -> bool {
+        self.prose.iter().any(|prose| prose.start <= range.start && range.end <= prose.end)
+    }
+}
+
+fn is_prose_tag(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Paragraph
+            | Tag::Heading { .. }
+            | Tag::Item
+            | Tag::TableCell
+            | Tag::Link { .. }
+            | Tag::Emphasis
+            | Tag::Strong
+            | Tag::Strikethrough
+    )
+}
+
+fn is_block_tag(tag: &Tag) -> bool {
+    !matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. } | Tag::Image { .. }
+            | Tag::Superscript | Tag::Subscript
+    )
+}
+
+fn is_block_end(tag: &TagEnd) -> bool {
+    !matches!(
+        tag,
+        TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link | TagEnd::Image
+            | TagEnd::Superscript | TagEnd::Subscript
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments<'a>(text: &'a str, map: &ProseMap) -> Vec<&'a str> {
+        map.segments().iter().map(|segment| &text[segment.clone()]).collect()
+    }
+
+    /// Whether `needle` (its first occurrence) is prose.
+    fn is_prose(text: &str, map: &ProseMap, needle: &str) -> bool {
+        let start = text.find(needle).expect("needle is in the text");
+        map.is_prose(start..start + needle.len())
+    }
+
+    #[test]
+    fn markdown_blocks_become_segments() {
+        let text = "# A title\n\nFirst paragraph\nwraps here.\n\n- one item\n- two\n";
+        let map = ProseMap::markdown(text);
+        assert_eq!(segments(text, &map), ["A title", "First paragraph\nwraps here.", "one item", "two"]);
+    }
+
+    #[test]
+    fn markdown_code_is_not_prose() {
+        let text = "Run `cargo tset` now.\n\n```rust\nlet teh = 1;\n```\n\n    indented teh code\n";
+        let map = ProseMap::markdown(text);
+        assert_eq!(segments(text, &map), ["Run `cargo tset` now."]);
+        assert!(is_prose(text, &map, "Run "));
+        assert!(!is_prose(text, &map, "tset"));
+        assert!(!is_prose(text, &map, "teh ="));
+        assert!(!is_prose(text, &map, "indented"));
+        // An insertion right before the code span is still in prose
+        let code = text.find('`').unwrap();
+        assert!(map.is_prose(code..code));
+    }
+
+    #[test]
+    fn markdown_links_keep_their_text_but_not_their_target() {
+        let text = "See [the dokumentation](https://exmaple.com/docs) and **bold teh** text.";
+        let map = ProseMap::markdown(text);
+        assert!(is_prose(text, &map, "dokumentation"));
+        assert!(is_prose(text, &map, "teh"));
+        assert!(!is_prose(text, &map, "exmaple"));
+        assert!(!is_prose(text, &map, "**"));
+        // Emphasis does not split the paragraph
+        assert_eq!(map.segments().len(), 1);
+    }
+
+    #[test]
+    fn markdown_front_matter_and_html_are_skipped() {
+        let text = "---\ntitel: Draft\n---\n\nBody text.\n\n<div>raw teh html</div>\n";
+        let map = ProseMap::markdown(text);
+        assert_eq!(segments(text, &map), ["Body text."]);
+        assert!(!is_prose(text, &map, "titel"));
+        assert!(!is_prose(text, &map, "raw teh"));
+    }
+
+    #[test]
+    fn paragraphs_split_by_format() {
+        let plain = "One line\nand more.\n\n  \nSecond.\n";
+        let ranges = TextFormat::Plain.paragraphs(plain);
+        assert_eq!(ranges.iter().map(|r| &plain[r.clone()]).collect::<Vec<_>>(), ["One line\nand more.\n", "Second.\n"]);
+
+        let markdown = "# Title\n\n- a\n- b\n\nText.\n";
+        let ranges = TextFormat::Markdown.paragraphs(markdown);
+        assert_eq!(ranges.iter().map(|r| &markdown[r.clone()]).collect::<Vec<_>>(), ["# Title\n", "- a\n- b\n\n", "Text.\n"]);
+    }
+}
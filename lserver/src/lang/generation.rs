@@ -0,0 +1,628 @@
+// lang/generation.rs - Beam search and n-best generation over a Seq2SeqModel
+use anyhow::{Error as E, Result};
+use serde::{Deserialize, Serialize};
+use crate::lang::manifest::SpecialTokens;
+use crate::lang::seq2seq::{Decoding, Seq2SeqModel};
+
+/// Widest beam a request may ask for; every beam holds its own decoder cache.
+pub const MAX_NUM_BEAMS: usize = 8;
+
+/// Most draft tokens a request may have checked per decoder call.
+pub const MAX_DRAFT_LENGTH: usize = 64;
+
+fn default_one() -> usize {
+    1
+}
+
+fn default_length_penalty() -> f32 {
+    1.0
+}
+
+fn default_draft_length() -> usize {
+    16
+}
+
+/// Decoding strategy for the seq2seq correctors. The default is greedy search
+/// returning a single output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationConfig {
+    /// Hypotheses kept per step; 1 means greedy decoding
+    #[serde(default = "default_one")]
+    pub num_beams: usize,
+    /// Distinct outputs to return (at most `num_beams`)
+    #[serde(default = "default_one")]
+    pub num_return_sequences: usize,
+    /// Exponent applied to the length when normalising scores; > 1 favours longer outputs
+    #[serde(default = "default_length_penalty")]
+    pub length_penalty: f32,
+    /// Forbid repeating any n-gram of this size; 0 disables the check
+    #[serde(default)]
+    pub no_repeat_ngram_size: usize,
+    /// Stop as soon as `num_beams` hypotheses have finished
+    #[serde(default)]
+    pub early_stopping: bool,
+    /// Greedy only: verify copied input tokens in bulk instead of one per decoder call
+    #[serde(default)]
+    pub speculative: bool,
+    /// Most draft tokens checked per decoder call in speculative mode
+    #[serde(default = "default_draft_length")]
+    pub draft_length: usize,
+    /// Cap on generated tokens, below the model's own limit
+    #[serde(default)]
+    pub max_new_tokens: Option<usize>,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            num_beams: 1,
+            num_return_sequences: 1,
+            length_penalty: default_length_penalty(),
+            no_repeat_ngram_size: 0,
+            early_stopping: false,
+            speculative: false,
+            draft_length: default_draft_length(),
+            max_new_tokens: None,
+        }
+    }
+}
+
+impl GenerationConfig {
+    pub fn is_greedy(&self) -> bool {
+        self.num_beams <= 1
+    }
+
+    pub fn is_speculative(&self) -> bool {
+        self.is_greedy() && self.speculative && self.draft_length > 0
+    }
+
+    /// Plain greedy decoding with the model's own limits, which can share a
+    /// batch with other requests.
+    pub fn is_batchable(&self) -> bool {
+        self.is_greedy() && !self.is_speculative() && self.max_new_tokens.is_none()
+    }
+
+    /// Reject settings beyond what the server allows a request.
+    pub fn validate(&self) -> Result<()> {
+        if self.num_beams > MAX_NUM_BEAMS {
+            return Err(E::msg(format!("num_beams is {}, at most {} is allowed", self.num_beams, MAX_NUM_BEAMS)));
+        }
+        if self.num_return_sequences > self.num_beams.max(1) {
+            return Err(E::msg(format!(
+                "num_return_sequences is {}, at most num_beams ({}) is allowed",
+                self.num_return_sequences, self.num_beams.max(1)
+            )));
+        }
+        if self.draft_length > MAX_DRAFT_LENGTH {
+            return Err(E::msg(format!("draft_length is {}, at most {} is allowed", self.draft_length, MAX_DRAFT_LENGTH)));
+        }
+        Ok(())
+    }
+
+    /// Decoding steps allowed for a model whose limit is `model_max`.
+    pub fn max_steps(&self, model_max: usize) -> usize {
+        self.max_new_tokens.map_or(model_max, |max| max.min(model_max))
+    }
+}
+
+/// A finished output: generated tokens (without the start token) and its
+/// length-normalised log-probability.
+#[derive(Debug, Clone)]
+pub struct Hypothesis {
+    pub tokens: Vec<i64>,
+    pub score: f32,
+}
+
+/// A decoded corrector output with its sequence score.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub text: String,
+    pub score: f32,
+}
+
+/// Turn logits into log-probabilities in place.
+pub fn log_softmax(logits: &mut [f32]) {
+    let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|&l| (l - max).exp()).sum::<f32>().ln() + max;
+    for logit in logits.iter_mut() {
+        *logit -= log_sum;
+    }
+}
+
+/// Tokens that would complete an n-gram already present in `tokens`.
+pub fn banned_ngram_tokens(tokens: &[i64], ngram_size: usize) -> Vec<i64> {
+    if ngram_size == 0 || tokens.len() + 1 < ngram_size {
+        return Vec::new();
+    }
+
+    let prefix = &tokens[tokens.len() + 1 - ngram_size..];
+    tokens
+        .windows(ngram_size)
+        .filter(|window| &window[..ngram_size - 1] == prefix)
+        .map(|window| window[ngram_size - 1])
+        .collect()
+}
+
+/// Greedy decoding of several inputs in one batch. Inputs are padded with
+/// `special.pad`; every row stops at its own EOS and then keeps feeding pad
+/// until all are done. `process_logits` works as in [`beam_search`]. Returns
+/// one hypothesis per input.
+pub fn greedy_batch(
+    model: &Seq2SeqModel,
+    inputs: &[Vec<i64>],
+    special: &SpecialTokens,
+    max_steps: usize,
+    process_logits: impl Fn(&[i64], &mut [f32]),
+) -> Result<Vec<Hypothesis>> {
+    let eos_tokens = &special.eos;
+    let mut decoding = model.start_batch(inputs, special.pad)?;
+    // Start token followed by the generated tokens, per row
+    let mut tokens: Vec<Vec<i64>> = vec![vec![special.decoder_start]; inputs.len()];
+    let mut log_probs = vec![0.0f32; inputs.len()];
+    let mut done = vec![false; inputs.len()];
+    let mut next: Vec<i64> = vec![special.decoder_start; inputs.len()];
+
+    for _ in 0..max_steps {
+        let rows = decoding.step_batch(&next)?;
+
+        for (row, mut logits) in rows.into_iter().enumerate() {
+            if done[row] {
+                next[row] = special.pad;
+                continue;
+            }
+            process_logits(&tokens[row], &mut logits);
+
+            let token = logits.iter().enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(token, _)| token as i64)
+                .unwrap_or(eos_tokens[0]);
+
+            log_softmax(&mut logits);
+            log_probs[row] += logits[token as usize];
+
+            if eos_tokens.contains(&token) {
+                done[row] = true;
+                next[row] = special.pad;
+            } else {
+                tokens[row].push(token);
+                next[row] = token;
+            }
+        }
+
+        if done.iter().all(|&d| d) {
+            break;
+        }
+    }
+
+    Ok(tokens
+        .into_iter()
+        .zip(log_probs)
+        .map(|(tokens, log_prob)| {
+            let tokens = tokens[1..].to_vec();
+            let score = log_prob / tokens.len().max(1) as f32;
+            Hypothesis { tokens, score }
+        })
+        .collect())
+}
+
+/// Draft continuation for `generated` copied from `source`: find where the
+/// last few generated tokens occur in the source (nearest the same position
+/// wins) and propose what follows them there.
+pub fn copy_draft(generated: &[i64], source: &[i64], max_len: usize) -> Vec<i64> {
+    if generated.is_empty() {
+        return source[..max_len.min(source.len())].to_vec();
+    }
+
+    for ngram in (1..=3.min(generated.len())).rev() {
+        let suffix = &generated[generated.len() - ngram..];
+        let best = source
+            .windows(ngram)
+            .enumerate()
+            .filter(|(_, window)| *window == suffix)
+            .map(|(start, _)| start + ngram)
+            .min_by_key(|&end| end.abs_diff(generated.len()));
+
+        if let Some(end) = best {
+            return source[end..(end + max_len).min(source.len())].to_vec();
+        }
+    }
+    Vec::new()
+}
+
+/// Greedy decoding that uses the input as a draft: each decoder call feeds the
+/// pending token plus up to `config.draft_length` copied input tokens, keeps
+/// the longest prefix the model agrees with, plus its own token at the first
+/// disagreement, and rolls the cache back past the rest. Produces exactly the
+/// greedy output; arguments are as for [`beam_search`].
+pub fn speculative_greedy(
+    model: &Seq2SeqModel,
+    input_ids: &[i64],
+    config: &GenerationConfig,
+    special: &SpecialTokens,
+    max_steps: usize,
+    process_logits: impl Fn(&[i64], &mut [f32]),
+) -> Result<Hypothesis> {
+    let mut decoding = model.start(input_ids, special.pad)?;
+    speculate(&mut decoding, input_ids, config, special, max_steps, process_logits)
+}
+
+/// The decoder calls speculative decoding makes.
+trait DraftDecoder {
+    fn step_many(&mut self, tokens: &[i64]) -> Result<Vec<Vec<f32>>>;
+    fn rollback(&mut self, len: usize) -> Result<()>;
+}
+
+impl DraftDecoder for Decoding<'_> {
+    fn step_many(&mut self, tokens: &[i64]) -> Result<Vec<Vec<f32>>> {
+        Decoding::step_many(self, tokens)
+    }
+
+    fn rollback(&mut self, len: usize) -> Result<()> {
+        Decoding::rollback(self, len)
+    }
+}
+
+/// [`speculative_greedy`] over a decoding that has already encoded `input_ids`.
+fn speculate(
+    decoding: &mut impl DraftDecoder,
+    input_ids: &[i64],
+    config: &GenerationConfig,
+    special: &SpecialTokens,
+    max_steps: usize,
+    process_logits: impl Fn(&[i64], &mut [f32]),
+) -> Result<Hypothesis> {
+    let eos_tokens = &special.eos;
+    // The tokenizer's trailing EOS is not something to copy
+    let mut draft_source = input_ids;
+    while let Some((last, rest)) = draft_source.split_last() {
+        if !eos_tokens.contains(last) {
+            break;
+        }
+        draft_source = rest;
+    }
+
+    // Start token followed by the accepted tokens; the last one is not fed yet
+    let mut tokens = vec![special.decoder_start];
+    let mut log_prob = 0.0f32;
+    let mut fed = 0;
+
+    'decode: while tokens.len() <= max_steps {
+        let remaining = max_steps + 1 - tokens.len();
+        let draft = copy_draft(&tokens[1..], draft_source, config.draft_length.min(remaining.saturating_sub(1)));
+
+        let mut batch = vec![tokens[tokens.len() - 1]];
+        batch.extend_from_slice(&draft);
+        let all_logits = decoding.step_many(&batch)?;
+        fed += batch.len();
+
+        // Position i predicts the token after batch[i]; it counts while the draft holds
+        for (position, mut logits) in all_logits.into_iter().enumerate() {
+            if tokens.len() > max_steps {
+                break 'decode;
+            }
+            process_logits(&tokens, &mut logits);
+            let token = logits.iter().enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(token, _)| token as i64)
+                .unwrap_or(eos_tokens[0]);
+            log_softmax(&mut logits);
+            log_prob += logits[token as usize];
+
+            if eos_tokens.contains(&token) {
+                break 'decode;
+            }
+            tokens.push(token);
+
+            if draft.get(position) != Some(&token) {
+                // Disagreement: drop the cache for the unchecked draft tokens
+                let kept = fed - (batch.len() - position - 1);
+                decoding.rollback(kept)?;
+                fed = kept;
+                break;
+            }
+        }
+    }
+
+    let tokens = tokens[1..].to_vec();
+    let score = log_prob / tokens.len().max(1) as f32;
+    Ok(Hypothesis { tokens, score })
+}
+
+fn normalized(score: f32, length: usize, length_penalty: f32) -> f32 {
+    score / (length.max(1) as f32).powf(length_penalty)
+}
+
+struct Beam<'m> {
+    decoding: Decoding<'m>,
+    /// Start token followed by the generated tokens
+    tokens: Vec<i64>,
+    log_prob: f32,
+}
+
+/// Beam search. `process_logits` sees the tokens so far (start token first) and
+/// may adjust the raw logits before they are normalised, e.g. for repetition penalties.
+/// Returns finished hypotheses, best first.
+pub fn beam_search(
+    model: &Seq2SeqModel,
+    input_ids: &[i64],
+    config: &GenerationConfig,
+    special: &SpecialTokens,
+    max_steps: usize,
+    process_logits: impl Fn(&[i64], &mut [f32]),
+) -> Result<Vec<Hypothesis>> {
+    let eos_tokens = &special.eos;
+    let num_beams = config.num_beams.max(1);
+    let mut beams = vec![Beam {
+        decoding: model.start(input_ids, special.pad)?,
+        tokens: vec![special.decoder_start],
+        log_prob: 0.0,
+    }];
+    let mut finished: Vec<Hypothesis> = Vec::new();
+
+    for _ in 0..max_steps {
+        // (beam index, token, cumulative log-probability)
+        let mut candidates: Vec<(usize, i64, f32)> = Vec::new();
+
+        for (index, beam) in beams.iter_mut().enumerate() {
+            let last = *beam.tokens.last().unwrap();
+            let mut logits = beam.decoding.step(last)?;
+            process_logits(&beam.tokens, &mut logits);
+            log_softmax(&mut logits);
+
+            for banned in banned_ngram_tokens(&beam.tokens[1..], config.no_repeat_ngram_size) {
+                if let Some(logit) = logits.get_mut(banned as usize) {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+
+            // Only the best 2 * num_beams continuations of any beam can survive
+            let mut top: Vec<(usize, f32)> = logits.iter().cloned().enumerate().collect();
+            let keep = (2 * num_beams).min(top.len());
+            top.select_nth_unstable_by(keep - 1, |a, b| b.1.total_cmp(&a.1));
+            top.truncate(keep);
+
+            for (token, log_prob) in top {
+                if log_prob.is_finite() {
+                    candidates.push((index, token as i64, beam.log_prob + log_prob));
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let mut next: Vec<(usize, i64, f32)> = Vec::new();
+        for (rank, &(index, token, log_prob)) in candidates.iter().enumerate() {
+            if eos_tokens.contains(&token) {
+                // Like HF, an EOS outside the top `num_beams` does not finish a hypothesis
+                if rank < num_beams {
+                    let tokens = beams[index].tokens[1..].to_vec();
+                    let score = normalized(log_prob, tokens.len(), config.length_penalty);
+                    finished.push(Hypothesis { tokens, score });
+                }
+            } else {
+                next.push((index, token, log_prob));
+            }
+            if next.len() == num_beams {
+                break;
+            }
+        }
+
+        // The best running beam, with its length once `next` extends it
+        let best_running = next.first().map(|&(index, _, log_prob)| (log_prob, beams[index].tokens.len()));
+        if best_running.is_none_or(|best| is_done(&finished, best, config, num_beams)) {
+            beams.clear();
+            break;
+        }
+        beams = advance(beams, next)?;
+    }
+
+    // Hypotheses still running at the length limit count as finished
+    for beam in beams {
+        let tokens = beam.tokens[1..].to_vec();
+        let score = normalized(beam.log_prob, tokens.len(), config.length_penalty);
+        finished.push(Hypothesis { tokens, score });
+    }
+
+    finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(finished)
+}
+
+/// Whether beam search can stop: `num_beams` hypotheses have finished and,
+/// unless `early_stopping` is set, the best running beam (cumulative
+/// log-probability and length) can no longer beat the worst of them.
+fn is_done(finished: &[Hypothesis], (best_running, length): (f32, usize), config: &GenerationConfig, num_beams: usize) -> bool {
+    if finished.len() < num_beams {
+        return false;
+    }
+    if config.early_stopping {
+        return true;
+    }
+
+    // Done once no running beam can still beat the worst kept hypothesis
+    let mut scores: Vec<f32> = finished.iter().map(|h| h.score).collect();
+    scores.sort_by(|a, b| b.total_cmp(a));
+    let worst_kept = scores[num_beams - 1];
+    normalized(best_running, length, config.length_penalty) <= worst_kept
+}
+
+/// Build the next generation of beams, forking a parent's decoder cache for
+/// every child but the last, which takes the parent over.
+fn advance<'m>(beams: Vec<Beam<'m>>, next: Vec<(usize, i64, f32)>) -> Result<Vec<Beam<'m>>> {
+    let mut remaining_children = vec![0usize; beams.len()];
+    for &(index, _, _) in &next {
+        remaining_children[index] += 1;
+    }
+
+    let mut parents: Vec<Option<Beam<'m>>> = beams.into_iter().map(Some).collect();
+    let mut children = Vec::with_capacity(next.len());
+
+    for (index, token, log_prob) in next {
+        remaining_children[index] -= 1;
+        let (decoding, mut tokens) = if remaining_children[index] == 0 {
+            let parent = parents[index].take().unwrap();
+            (parent.decoding, parent.tokens)
+        } else {
+            let parent = parents[index].as_ref().unwrap();
+            (parent.decoding.fork()?, parent.tokens.clone())
+        };
+
+        tokens.push(token);
+        children.push(Beam { decoding, tokens, log_prob });
+    }
+
+    Ok(children)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EOS: i64 = 1;
+    const VOCAB: usize = 128;
+
+    fn special() -> SpecialTokens {
+        SpecialTokens { decoder_start: 0, eos: vec![EOS], pad: 0 }
+    }
+
+    fn hypothesis(score: f32) -> Hypothesis {
+        Hypothesis { tokens: vec![5], score }
+    }
+
+    /// A decoder that writes `target` then EOS, and only while everything fed
+    /// so far (start token first) is a prefix of it; anything else, such as a
+    /// rejected draft token left in the cache, derails it.
+    struct Scripted {
+        target: Vec<i64>,
+        fed: Vec<i64>,
+        calls: usize,
+    }
+
+    impl Scripted {
+        fn new(target: &[i64]) -> Self {
+            Self { target: target.to_vec(), fed: Vec::new(), calls: 0 }
+        }
+
+        fn next(&self, fed: &[i64]) -> i64 {
+            let generated = &fed[1..];
+            if !self.target.starts_with(generated) {
+                return 2;
+            }
+            self.target.get(generated.len()).copied().unwrap_or(EOS)
+        }
+
+        /// Plain greedy decoding, one token per call.
+        fn greedy(target: &[i64], max_steps: usize) -> Vec<i64> {
+            let decoder = Self::new(target);
+            let mut tokens = vec![0];
+            while tokens.len() <= max_steps {
+                match decoder.next(&tokens) {
+                    EOS => break,
+                    token => tokens.push(token),
+                }
+            }
+            tokens[1..].to_vec()
+        }
+    }
+
+    impl DraftDecoder for Scripted {
+        fn step_many(&mut self, tokens: &[i64]) -> Result<Vec<Vec<f32>>> {
+            self.calls += 1;
+            Ok(tokens.iter().map(|&token| {
+                self.fed.push(token);
+                let mut logits = vec![0.0; VOCAB];
+                logits[self.next(&self.fed) as usize] = 10.0;
+                logits
+            }).collect())
+        }
+
+        fn rollback(&mut self, len: usize) -> Result<()> {
+            self.fed.truncate(len);
+            Ok(())
+        }
+    }
+
+    fn speculative(input: &[i64], target: &[i64], draft_length: usize, max_steps: usize) -> (Vec<i64>, usize) {
+        let config = GenerationConfig { speculative: true, draft_length, ..GenerationConfig::default() };
+        let mut decoder = Scripted::new(target);
+        let hypothesis = speculate(&mut decoder, input, &config, &special(), max_steps, |_, _| {}).unwrap();
+        (hypothesis.tokens, decoder.calls)
+    }
+
+    #[test]
+    fn speculation_matches_greedy() {
+        let input = [10, 11, 12, 13, 14, 15, 16, 17, EOS];
+        let targets: [&[i64]; 5] = [
+            &[10, 11, 12, 13, 14, 15, 16, 17],
+            // A replaced, an inserted and a deleted token
+            &[10, 11, 40, 13, 14, 41, 15, 17],
+            &[50, 51, 52],
+            &[],
+            &[10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20],
+        ];
+        for target in targets {
+            for draft_length in [1, 3, 16] {
+                for max_steps in [0, 4, 50] {
+                    let (tokens, _) = speculative(&input, target, draft_length, max_steps);
+                    assert_eq!(tokens, Scripted::greedy(target, max_steps), "{:?} draft {} steps {}", target, draft_length, max_steps);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn copied_text_takes_few_decoder_calls() {
+        let input: Vec<i64> = (10..40).chain([EOS]).collect();
+        let target: Vec<i64> = (10..40).collect();
+        let (tokens, calls) = speculative(&input, &target, 16, 100);
+        assert_eq!(tokens, target);
+        assert!(calls <= 3, "{} calls", calls);
+    }
+
+    #[test]
+    fn drafts_continue_from_the_nearest_match() {
+        // Nothing generated yet: the start of the source
+        assert_eq!(copy_draft(&[], &[5, 6, 7], 2), [5, 6]);
+        assert_eq!(copy_draft(&[5, 6], &[5, 6, 7, 8, 9], 2), [7, 8]);
+        // Of two matches, the one at the same position
+        assert_eq!(copy_draft(&[7, 7, 3], &[3, 4, 3, 9], 4), [9]);
+        // A longer matching suffix beats a nearer single token
+        assert_eq!(copy_draft(&[3, 2], &[1, 2, 9, 3, 2, 8], 4), [8]);
+        assert!(copy_draft(&[42], &[1, 2, 3], 4).is_empty());
+        assert!(copy_draft(&[3], &[1, 2, 3], 4).is_empty());
+    }
+
+    #[test]
+    fn repeated_ngrams_are_banned() {
+        assert_eq!(banned_ngram_tokens(&[1, 2, 3, 1, 2], 3), [3]);
+        assert_eq!(banned_ngram_tokens(&[4, 5, 4, 6, 4], 2), [5, 6]);
+        assert!(banned_ngram_tokens(&[1, 2, 3, 1, 2], 0).is_empty());
+        assert!(banned_ngram_tokens(&[1], 3).is_empty());
+        assert!(banned_ngram_tokens(&[1, 2, 3], 3).is_empty());
+    }
+
+    #[test]
+    fn beam_search_stops_when_no_beam_can_win() {
+        let config = GenerationConfig { num_beams: 2, ..GenerationConfig::default() };
+        let finished = [hypothesis(-1.0), hypothesis(-2.0)];
+
+        assert!(!is_done(&finished[..1], (-100.0, 5), &config, 2));
+        // -10 over 5 tokens ties the worst kept score of -2
+        assert!(is_done(&finished, (-10.0, 5), &config, 2));
+        assert!(!is_done(&finished, (-5.0, 5), &config, 2));
+
+        let early = GenerationConfig { early_stopping: true, ..config };
+        assert!(is_done(&finished, (-5.0, 5), &early, 2));
+    }
+
+    #[test]
+    fn requests_above_the_caps_are_rejected() {
+        assert!(GenerationConfig::default().validate().is_ok());
+        let beams = |num_beams, num_return_sequences| GenerationConfig { num_beams, num_return_sequences, ..GenerationConfig::default() };
+        assert!(beams(MAX_NUM_BEAMS, MAX_NUM_BEAMS).validate().is_ok());
+        assert!(beams(MAX_NUM_BEAMS + 1, 1).validate().is_err());
+        assert!(beams(2, 3).validate().is_err());
+        assert!(beams(0, 1).validate().is_ok());
+        let draft = GenerationConfig { draft_length: MAX_DRAFT_LENGTH + 1, ..GenerationConfig::default() };
+        assert!(draft.validate().is_err());
+    }
+}
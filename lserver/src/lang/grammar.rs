@@ -1,285 +1,205 @@
 // lang/grammar.rs - T5 ONNX grammar correction
 use anyhow::{Error as E, Result};
-use ort::session::{builder::GraphOptimizationLevel, Session};
-use ort::value::Tensor;
-use std::sync::RwLock;
+use crate::config::{Config, ModelConfig};
+use crate::lang::batch::{BatchCorrect, Batcher, BatchConfig};
+use crate::lang::generation::{beam_search, greedy_batch, speculative_greedy, Candidate, GenerationConfig, Hypothesis};
+use crate::lang::manifest::ModelManifest;
+use crate::lang::pool::{InferencePool, PoolStats};
+use crate::lang::seq2seq::Seq2SeqModel;
+use std::path::Path;
 use tokenizers::Tokenizer;
-use tracing::info;
+use tracing::{info, warn};
 
 pub struct GrammarCorrector {
-    encoder_session: RwLock<Session>,
-    decoder_session: RwLock<Session>,
+    model: Seq2SeqModel,
     tokenizer: Tokenizer,
+    manifest: ModelManifest,
 }
 
 pub struct FlanT5Corrector {
-    session: RwLock<Session>,
+    model: Seq2SeqModel,
     tokenizer: Tokenizer,
+    manifest: ModelManifest,
 }
 
 impl FlanT5Corrector {
-    pub async fn new() -> Result<Self> {
-        let model_dir = std::path::Path::new("./flan_t5_onnx");
-        
-        // Check if files exist locally first
-        let tokenizer_file = model_dir.join("tokenizer.json");
-        let model_file = model_dir.join("onnx/model.onnx");
-        
-        let tokenizer = if tokenizer_file.exists() {
-            info!("Loading FLAN-T5 tokenizer from local cache...");
-            Tokenizer::from_file(&tokenizer_file)
-                .map_err(|e| E::msg(format!("Failed to load FLAN-T5 tokenizer: {}", e)))?
-        } else {
-            info!("Downloading FLAN-T5 tokenizer from Hugging Face to ./flan_t5_onnx/...");
-            let api = hf_hub::api::tokio::Api::new()?;
-            let repo = api.model("pszemraj/flan-t5-large-grammar-synthesis".to_string());
-            
-            let tokenizer_path = repo.get("tokenizer.json").await
-                .map_err(|e| E::msg(format!("Failed to download tokenizer: {}", e)))?;
-            
-            // Copy to our local directory
-            std::fs::create_dir_all(&model_dir)?;
-            std::fs::copy(&tokenizer_path, &tokenizer_file)?;
-            
-            Tokenizer::from_file(&tokenizer_file)
-                .map_err(|e| E::msg(format!("Failed to load FLAN-T5 tokenizer: {}", e)))?
-        };
-        
-        let session = if model_file.exists() {
-            info!("Loading FLAN-T5 ONNX model from local cache...");
-            Session::builder()?
-                .with_optimization_level(GraphOptimizationLevel::Level3)?
-                .commit_from_file(&model_file)?
-        } else {
-            // Try the alternative GEC model first (more compatible)
-            info!("Trying alternative GEC ONNX model (more compatible)...");
-            let api = hf_hub::api::tokio::Api::new()?;
-            let gec_repo = api.model("onnx-community/grammar_error_correcter_v1-ONNX".to_string());
-            
-            match gec_repo.get("model.onnx").await {
-                Ok(gec_model_path) => {
-                    info!("Using GEC ONNX model instead of FLAN-T5");
-                    std::fs::create_dir_all(model_dir.join("onnx"))?;
-                    std::fs::copy(&gec_model_path, &model_file)?;
-                    
-                    Session::builder()?
-                        .with_optimization_level(GraphOptimizationLevel::Level3)?
-                        .commit_from_file(&model_file)?
-                }
-                Err(_) => {
-                    info!("Downloading FLAN-T5 ONNX model from Hugging Face to ../flan_t5_onnx/...");
-                    let repo = api.model("pszemraj/flan-t5-large-grammar-synthesis".to_string());
-                    
-                    let model_path = repo.get("onnx/model.onnx").await
-                        .map_err(|e| E::msg(format!("Failed to download ONNX model: {}", e)))?;
-                    
-                    // Copy to our local directory
-                    std::fs::create_dir_all(model_dir.join("onnx"))?;
-                    std::fs::copy(&model_path, &model_file)?;
-                    
-                    Session::builder()?
-                        .with_optimization_level(GraphOptimizationLevel::Level3)?
-                        .commit_from_file(&model_file)?
-                }
-            }
-        };
+    /// Load one instance from `config.flan_t5_dir`, as described by `manifest`
+    /// (see [`prepare_flan_t5`]).
+    pub fn new(config: &ModelConfig, manifest: &ModelManifest) -> Result<Self> {
+        let (tokenizer, model) = load_model("FLAN-T5", &config.flan_t5_dir, manifest)?;
 
-        info!("FLAN-T5 model loaded successfully!");
+        info!("FLAN-T5 model loaded successfully ({}, {})", manifest.identity(), model.decoding_mode());
         Ok(Self {
-            session: RwLock::new(session),
+            model,
             tokenizer,
+            manifest: manifest.clone(),
         })
     }
 
-    pub async fn correct_grammar(&self, text: &str) -> Result<(String, bool)> {
+    /// Up to `config.num_return_sequences` distinct rewrites, best first.
+    pub fn correct_grammar_candidates(&self, text: &str, config: &GenerationConfig) -> Result<Vec<Candidate>> {
         info!("FLAN-T5 processing: '{}'", text);
+        let input_ids = self.encode(text)?;
         
-        let encoding = self.tokenizer.encode(text, true)
-            .map_err(|e| E::msg(format!("FLAN-T5 tokenization failed: {}", e)))?;
-        let input_ids: Vec<i64> = encoding.get_ids().iter().map(|&x| x as i64).collect();
-        
-        if input_ids.len() > 256 { 
-            return Err(E::msg("Input too long for FLAN-T5")); 
-        }
-        
-        // T5 models need decoder_input_ids for generation - start with start token (0)
-        let mut generated_tokens = vec![0i64]; // Start with pad/start token
-        let mut session = self.session.write().unwrap();
+        let (tokens, max_steps) = (&self.manifest.special_tokens, config.max_steps(self.manifest.max_output_tokens));
+        let hypotheses = if config.is_speculative() {
+            vec![speculative_greedy(&self.model, &input_ids, config, tokens, max_steps, |_, _| {})?]
+        } else if config.is_greedy() {
+            self.greedy_batch(&[input_ids], max_steps)?
+        } else {
+            beam_search(&self.model, &input_ids, config, tokens, max_steps, |_, _| {})?
+        };
         
-        // Generate tokens iteratively (autoregressive generation)
-        for step in 0..50 { // Max 50 tokens
-            info!("FLAN-T5 generation step {}, current tokens: {:?}", step, generated_tokens);
-            
-            let input_tensor = Tensor::from_array(([1, input_ids.len()], input_ids.clone().into_boxed_slice()))?;
-            let attention_tensor = Tensor::from_array(([1, encoding.len()], vec![1i64; encoding.len()].into_boxed_slice()))?;
-            let decoder_tensor = Tensor::from_array(([1, generated_tokens.len()], generated_tokens.clone().into_boxed_slice()))?;
-            
-            let outputs = session.run(ort::inputs![
-                "input_ids" => input_tensor,
-                "attention_mask" => attention_tensor,
-                "decoder_input_ids" => decoder_tensor
-            ])?;
-            
-            // Get logits and convert to tokens
-            let logits = outputs["logits"].try_extract_array::<f32>()?;
-            let vocab_size = logits.shape()[2];
-            
-            // Get the logits for the last generated position
-            let last_position = generated_tokens.len() - 1;
-            let start_idx = last_position * vocab_size;
-            let end_idx = start_idx + vocab_size;
-            let last_logits: Vec<f32> = logits.as_slice().unwrap()[start_idx..end_idx].to_vec();
-            
-            // Get the most likely next token
-            let next_token = last_logits.iter().enumerate()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                .unwrap().0 as i64;
+        self.candidates(hypotheses, config)
+    }
+
+    fn encode(&self, text: &str) -> Result<Vec<i64>> {
+        encode(&self.tokenizer, &self.manifest, text)
+            .map_err(|e| E::msg(format!("FLAN-T5: {}", e)))
+    }
+
+    fn candidates(&self, hypotheses: Vec<Hypothesis>, config: &GenerationConfig) -> Result<Vec<Candidate>> {
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for hypothesis in hypotheses {
+            let output_tokens: Vec<u32> = hypothesis.tokens.iter().map(|&x| x as u32).collect();
+            info!("FLAN-T5 final generated tokens: {:?}", output_tokens);
             
-            info!("FLAN-T5 predicted next token: {}", next_token);
+            let raw_result = self.tokenizer.decode(&output_tokens, true)
+                .map_err(|e| E::msg(format!("FLAN-T5 decode failed: {}", e)))?;
             
-            // Stop if we hit EOS token (1) or pad token (0)
-            if next_token == 1 || next_token == 0 {
-                info!("FLAN-T5 stopping generation at EOS/PAD token");
-                break;
+            let result = strip_output_prefix(&raw_result, &self.manifest).trim().to_string();
+            if !candidates.iter().any(|c| c.text == result) {
+                candidates.push(Candidate { text: result, score: hypothesis.score });
             }
-            
-            generated_tokens.push(next_token);
         }
+        candidates.truncate(config.num_return_sequences.max(1));
         
-        // Decode the generated tokens (skip the initial start token)
-        let output_tokens: Vec<u32> = generated_tokens.iter().skip(1).map(|&x| x as u32).collect();
-        info!("FLAN-T5 final generated tokens: {:?}", output_tokens);
-        
-        let raw_result = self.tokenizer.decode(&output_tokens, true)
-            .map_err(|e| E::msg(format!("FLAN-T5 decode failed: {}", e)))?;
-        
-        let result = raw_result.trim().to_string();
-        let changed = result != text.trim() && !result.is_empty();
-        
-        info!("FLAN-T5 result: '{}' -> '{}', changed: {}", text, result, changed);
-        Ok((result, changed))
+        Ok(candidates)
+    }
+
+    /// Greedy decoding, one hypothesis per input.
+    fn greedy_batch(&self, inputs: &[Vec<i64>], max_steps: usize) -> Result<Vec<Hypothesis>> {
+        greedy_batch(&self.model, inputs, &self.manifest.special_tokens, max_steps, |_, _| {})
+    }
+}
+
+impl BatchCorrect for FlanT5Corrector {
+    fn correct_batch(&self, texts: &[String]) -> Vec<Result<Vec<Candidate>>> {
+        info!("FLAN-T5 processing a batch of {}", texts.len());
+        correct_in_batch(
+            texts,
+            |text| self.encode(text),
+            |inputs| self.greedy_batch(inputs, self.manifest.max_output_tokens),
+            |hypothesis| self.candidates(vec![hypothesis], &GenerationConfig::default()),
+        )
     }
 }
 
 
 impl GrammarCorrector {
-    pub async fn new() -> Result<Self> {
-        let model_dir = std::path::Path::new("./gramformer_onnx");
-        let tokenizer = Tokenizer::from_file(model_dir.join("tokenizer.json"))
-            .map_err(|e| E::msg(format!("Failed to load tokenizer: {}", e)))?;
-        
-        let encoder_session = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .commit_from_file(model_dir.join("encoder_model.onnx"))?;
-            
-        let decoder_session = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .commit_from_file(model_dir.join("decoder_model.onnx"))?;
+    /// Load one instance from `config.gramformer_dir`, as described by `manifest`
+    /// (see [`prepare_gramformer`]).
+    pub fn new(config: &ModelConfig, manifest: &ModelManifest) -> Result<Self> {
+        let (tokenizer, model) = load_model("Gramformer", &config.gramformer_dir, manifest)?;
 
         Ok(Self {
-            encoder_session: RwLock::new(encoder_session),
-            decoder_session: RwLock::new(decoder_session),
+            model,
             tokenizer,
+            manifest: manifest.clone(),
         })
     }
 
-    pub async fn correct_grammar(&self, text: &str) -> Result<(String, bool)> {
-        let encoding = self.tokenizer.encode(text, true)
-            .map_err(|e| E::msg(format!("Tokenization failed: {}", e)))?;
-        let input_ids: Vec<i64> = encoding.get_ids().iter().map(|&x| x as i64).collect();
+    /// Up to `config.num_return_sequences` distinct corrections, best first.
+    pub fn correct_grammar_candidates(&self, text: &str, config: &GenerationConfig) -> Result<Vec<Candidate>> {
+        let input_ids = self.encode(text)?;
+        
+        let (special, max_steps) = (&self.manifest.special_tokens, config.max_steps(self.manifest.max_output_tokens));
+        let hypotheses = if config.is_speculative() {
+            vec![speculative_greedy(&self.model, &input_ids, config, special, max_steps, |tokens, logits| {
+                self.apply_repetition_penalties(tokens, logits)
+            })?]
+        } else if config.is_greedy() {
+            self.greedy_batch(&[input_ids], max_steps)?
+        } else {
+            beam_search(&self.model, &input_ids, config, special, max_steps, |tokens, logits| {
+                self.apply_repetition_penalties(tokens, logits)
+            })?
+        };
         
-        if input_ids.len() > 256 { 
-            return Err(E::msg("Input too long")); 
+        // Note: Advanced FUDGE-inspired prevention during generation eliminates need for post-processing
+        self.candidates(hypotheses, config)
+    }
+
+    fn encode(&self, text: &str) -> Result<Vec<i64>> {
+        encode(&self.tokenizer, &self.manifest, text)
+    }
+
+    fn candidates(&self, hypotheses: Vec<Hypothesis>, config: &GenerationConfig) -> Result<Vec<Candidate>> {
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for hypothesis in hypotheses {
+            let raw_result = self.tokenizer.decode(&hypothesis.tokens.iter().map(|&x| x as u32).collect::<Vec<_>>(), true)
+                .map_err(|e| E::msg(format!("Decode failed: {}", e)))?;
+            
+            // Remove the echoed prefix (Gramformer's "grammar: ") if present
+            let result = strip_output_prefix(&raw_result, &self.manifest).to_string();
+            
+            if !candidates.iter().any(|c| c.text.trim() == result.trim()) {
+                candidates.push(Candidate { text: result, score: hypothesis.score });
+            }
         }
+        candidates.truncate(config.num_return_sequences.max(1));
         
-        // Run encoder
-        let mut encoder_session = self.encoder_session.write().unwrap();
-        let encoder_outputs = encoder_session.run(ort::inputs![
-            "input_ids" => Tensor::from_array(([1, input_ids.len()], input_ids.into_boxed_slice()))?,
-            "attention_mask" => Tensor::from_array(([1, encoding.len()], vec![1i64; encoding.len()].into_boxed_slice()))?
-        ])?;
-        
-        let encoder_hidden_states = &encoder_outputs["last_hidden_state"];
-        
-        // Greedy decoding with advanced repetition prevention
-        let mut generated_tokens = vec![0i64]; // start token
-        let mut decoder_session = self.decoder_session.write().unwrap();
-        
+        Ok(candidates)
+    }
+
+    /// Greedy decoding with advanced repetition prevention, one hypothesis per input
+    fn greedy_batch(&self, inputs: &[Vec<i64>], max_steps: usize) -> Result<Vec<Hypothesis>> {
+        greedy_batch(&self.model, inputs, &self.manifest.special_tokens, max_steps, |tokens, logits| {
+            self.apply_repetition_penalties(tokens, logits)
+        })
+    }
+
+    /// Repetition penalty plus a FUDGE-inspired discriminator, applied to raw
+    /// logits given the tokens generated so far (start token first).
+    fn apply_repetition_penalties(&self, generated_tokens: &[i64], last_logits: &mut [f32]) {
         const REPETITION_PENALTY: f32 = 1.2; // Research-backed value
         const RISK_THRESHOLD: f32 = 0.1; // Only penalize significant risk
         const PENALTY_SCALE: f32 = 3.0; // FUDGE-style penalty scaling
         
-        for _ in 0..80 {
-            let decoder_outputs = decoder_session.run(ort::inputs![
-                "input_ids" => Tensor::from_array(([1, generated_tokens.len()], generated_tokens.clone().into_boxed_slice()))?,
-                "encoder_hidden_states" => encoder_hidden_states,
-                "encoder_attention_mask" => Tensor::from_array(([1, encoding.len()], vec![1i64; encoding.len()].into_boxed_slice()))?
-            ])?;
-            
-            let logits = decoder_outputs["logits"].try_extract_array::<f32>()?;
-            let shape = logits.shape();
-            let vocab_size = shape[2];
-            let last_step_start = (generated_tokens.len() - 1) * vocab_size;
-            let mut last_logits: Vec<f32> = logits.as_slice().unwrap()[last_step_start..last_step_start + vocab_size].to_vec();
-            
-            // Apply repetition penalty to already generated tokens
-            for &token in &generated_tokens[1..] { // Skip start token
-                if token < vocab_size as i64 {
-                    let token_idx = token as usize;
-                    if last_logits[token_idx] > 0.0 {
-                        last_logits[token_idx] /= REPETITION_PENALTY;
-                    } else {
-                        last_logits[token_idx] *= REPETITION_PENALTY;
-                    }
+        let vocab_size = last_logits.len();
+        
+        // Apply repetition penalty to already generated tokens
+        for &token in &generated_tokens[1..] { // Skip start token
+            if token < vocab_size as i64 {
+                let token_idx = token as usize;
+                if last_logits[token_idx] > 0.0 {
+                    last_logits[token_idx] /= REPETITION_PENALTY;
+                } else {
+                    last_logits[token_idx] *= REPETITION_PENALTY;
                 }
             }
+        }
+        
+        // Apply FUDGE-inspired future repetition discriminator
+        // This predicts if adding a token will lead to repetitive final text
+        if generated_tokens.len() >= 2 {
+            // Optimize: Only check top-k candidates to avoid expensive full vocab loop
+            let top_k = 100.min(vocab_size); // Reasonable limit for performance
             
-            // Apply FUDGE-inspired future repetition discriminator
-            // This predicts if adding a token will lead to repetitive final text
-            if generated_tokens.len() >= 2 {
-                // Optimize: Only check top-k candidates to avoid expensive full vocab loop
-                let top_k = 100.min(vocab_size); // Reasonable limit for performance
+            for (candidate_token, logit) in last_logits.iter_mut().enumerate().take(top_k) {
+                let mut test_sequence = generated_tokens.to_vec();
+                test_sequence.push(candidate_token as i64);
+                
+                // Future repetition risk assessment
+                let repetition_risk = self.assess_future_repetition_risk(&test_sequence);
                 
-                for candidate_token in 0..top_k {
-                    let mut test_sequence = generated_tokens.clone();
-                    test_sequence.push(candidate_token as i64);
-                    
-                    // Future repetition risk assessment
-                    let repetition_risk = self.assess_future_repetition_risk(&test_sequence);
-                    
-                    // Apply FUDGE-style penalty: P(token|context) *= (1 - repetition_risk)
-                    if repetition_risk > RISK_THRESHOLD {
-                        let penalty = repetition_risk * PENALTY_SCALE;
-                        last_logits[candidate_token] -= penalty;
-                    }
+                // Apply FUDGE-style penalty: P(token|context) *= (1 - repetition_risk)
+                if repetition_risk > RISK_THRESHOLD {
+                    let penalty = repetition_risk * PENALTY_SCALE;
+                    *logit -= penalty;
                 }
             }
-            
-            
-            let next_token = last_logits.iter().enumerate()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                .unwrap().0 as i64;
-            
-            if next_token == 1 { break; } // EOS
-            generated_tokens.push(next_token);
         }
-        
-        let best_tokens = generated_tokens;
-        
-        let raw_result = self.tokenizer.decode(&best_tokens[1..].iter().map(|&x| x as u32).collect::<Vec<_>>(), true)
-            .map_err(|e| E::msg(format!("Decode failed: {}", e)))?;
-        
-        // Remove the "grammar: " prefix from the result if present
-        let result = if raw_result.starts_with("grammar: ") {
-            raw_result[9..].to_string()
-        } else {
-            raw_result
-        };
-        
-        // Note: Advanced FUDGE-inspired prevention during generation eliminates need for post-processing
-        
-        let changed = result.trim() != text.trim();
-        Ok((result, changed))
     }
 
 
@@ -359,53 +279,238 @@ impl GrammarCorrector {
 
 }
 
+impl BatchCorrect for GrammarCorrector {
+    fn correct_batch(&self, texts: &[String]) -> Vec<Result<Vec<Candidate>>> {
+        correct_in_batch(
+            texts,
+            |text| self.encode(text),
+            |inputs| self.greedy_batch(inputs, self.manifest.max_output_tokens),
+            |hypothesis| self.candidates(vec![hypothesis], &GenerationConfig::default()),
+        )
+    }
+}
+
+/// Encode every text, decode the ones that encoded as a single batch and turn
+/// each hypothesis into candidates. Failures stay in the slot of their text.
+fn correct_in_batch(
+    texts: &[String],
+    encode: impl Fn(&str) -> Result<Vec<i64>>,
+    decode: impl FnOnce(&[Vec<i64>]) -> Result<Vec<Hypothesis>>,
+    candidates: impl Fn(Hypothesis) -> Result<Vec<Candidate>>,
+) -> Vec<Result<Vec<Candidate>>> {
+    let encoded: Vec<Result<Vec<i64>>> = texts.iter().map(|text| encode(text)).collect();
+    let inputs: Vec<Vec<i64>> = encoded.iter().filter_map(|ids| ids.as_ref().ok().cloned()).collect();
+    if inputs.is_empty() {
+        return encoded.into_iter().map(|ids| ids.map(|_| Vec::new())).collect();
+    }
+
+    match decode(&inputs) {
+        Ok(hypotheses) => {
+            let mut hypotheses = hypotheses.into_iter();
+            encoded.into_iter()
+                .map(|ids| ids.and_then(|_| {
+                    let hypothesis = hypotheses.next().ok_or_else(|| E::msg("Batch returned too few outputs"))?;
+                    candidates(hypothesis)
+                }))
+                .collect()
+        }
+        Err(e) => encoded.into_iter()
+            .map(|ids| ids.and_then(|_| Err(E::msg(format!("Batch decoding failed: {}", e)))))
+            .collect(),
+    }
+}
+
+/// Token ids for `text` with the manifest's input prefix, within its length limit.
+fn encode(tokenizer: &Tokenizer, manifest: &ModelManifest, text: &str) -> Result<Vec<i64>> {
+    let encoding = tokenizer.encode(format!("{}{}", manifest.input_prefix, text), true)
+        .map_err(|e| E::msg(format!("Tokenization failed: {}", e)))?;
+    let input_ids: Vec<i64> = encoding.get_ids().iter().map(|&x| x as i64).collect();
+
+    if input_ids.len() > manifest.max_input_tokens {
+        return Err(E::msg(format!("Input too long ({} tokens, at most {})", input_ids.len(), manifest.max_input_tokens)));
+    }
+    Ok(input_ids)
+}
+
+fn strip_output_prefix<'a>(output: &'a str, manifest: &ModelManifest) -> &'a str {
+    match manifest.output_prefix.as_str() {
+        "" => output,
+        prefix => output.strip_prefix(prefix).unwrap_or(output),
+    }
+}
+
+/// Tokenizer and graphs from `dir`, checked against the manifest.
+fn load_model(name: &str, dir: &Path, manifest: &ModelManifest) -> Result<(Tokenizer, Seq2SeqModel)> {
+    let tokenizer_file = dir.join("tokenizer.json");
+    let tokenizer = Tokenizer::from_file(&tokenizer_file)
+        .map_err(|e| E::msg(format!("Failed to load {} tokenizer from {}: {}", name, tokenizer_file.display(), e)))?;
+    manifest.check_tokenizer(&tokenizer)?;
+
+    let model = Seq2SeqModel::load(&dir.join(&manifest.onnx_dir), &manifest.tensors)?;
+    Ok((tokenizer, model))
+}
+
+fn model_present(dir: &Path, manifest: &ModelManifest) -> bool {
+    dir.join("tokenizer.json").exists() && Seq2SeqModel::exists_in(&dir.join(&manifest.onnx_dir))
+}
+
+/// The Gramformer manifest, once its files are known to be present and intact.
+/// There is no download source for Gramformer.
+pub fn prepare_gramformer(config: &ModelConfig) -> Result<ModelManifest> {
+    let dir = config.gramformer_dir.as_path();
+    let manifest = ModelManifest::load(dir, ModelManifest::gramformer)?;
+    if !model_present(dir, &manifest) {
+        return Err(missing_model("Gramformer", dir, &manifest, config));
+    }
+    manifest.verify_checksums(dir)?;
+    Ok(manifest)
+}
+
+/// The FLAN-T5 manifest, once its files are known to be present and intact.
+/// Missing files are fetched from `config.flan_t5_repo` only when downloads
+/// are enabled and not offline.
+pub async fn prepare_flan_t5(config: &ModelConfig) -> Result<ModelManifest> {
+    let dir = config.flan_t5_dir.as_path();
+    let manifest = ModelManifest::load(dir, ModelManifest::flan_t5)?;
+    if !model_present(dir, &manifest) {
+        if !config.download || config.offline {
+            return Err(missing_model("FLAN-T5", dir, &manifest, config));
+        }
+        download_flan_t5(config, &dir.join("tokenizer.json"), &dir.join(&manifest.onnx_dir)).await?;
+    }
+    manifest.verify_checksums(dir)?;
+    Ok(manifest)
+}
+
+/// Startup error for a model directory that is missing files, saying what
+/// was expected and how to provide it.
+fn missing_model(name: &str, dir: &Path, manifest: &ModelManifest, config: &ModelConfig) -> E {
+    let graphs = "encoder_model.onnx + decoder graphs, or model.onnx";
+    let expected = if manifest.onnx_dir == Path::new(".") {
+        format!("tokenizer.json and {}", graphs)
+    } else {
+        format!("tokenizer.json and {}/ ({})", manifest.onnx_dir.display(), graphs)
+    };
+    let hint = if name != "FLAN-T5" {
+        "export the model there or point the config at another directory"
+    } else if config.offline {
+        "offline mode is on, so copy the export there or point the config at another directory"
+    } else {
+        "copy the export there, or set models.download = true (QUILLGUARD_DOWNLOAD_MODELS=1) to fetch it"
+    };
+    E::msg(format!("{} model not found in {}: expected {}; {}", name, dir.display(), expected, hint))
+}
+
+/// Fetch the FLAN-T5 tokenizer and single-graph ONNX export into the model directory.
+async fn download_flan_t5(config: &ModelConfig, tokenizer_file: &Path, onnx_dir: &Path) -> Result<()> {
+    info!("Downloading FLAN-T5 from Hugging Face ({}) to {}...", config.flan_t5_repo, config.flan_t5_dir.display());
+    let api = hf_hub::api::tokio::Api::new()?;
+    let repo = api.model(config.flan_t5_repo.clone());
+
+    let tokenizer_path = repo.get("tokenizer.json").await
+        .map_err(|e| E::msg(format!("Failed to download tokenizer from {}: {}", config.flan_t5_repo, e)))?;
+    let model_path = repo.get("onnx/model.onnx").await
+        .map_err(|e| E::msg(format!("Failed to download ONNX model from {}: {}", config.flan_t5_repo, e)))?;
+
+    // Copy to the configured directory
+    std::fs::create_dir_all(onnx_dir)?;
+    std::fs::copy(&tokenizer_path, tokenizer_file)?;
+    std::fs::copy(&model_path, onnx_dir.join("model.onnx"))?;
+    Ok(())
+}
+
+fn load_pool<T: Send + 'static>(name: &str, sessions: usize, load: impl Fn() -> Result<T>) -> Result<InferencePool<T>> {
+    let instances = (0..sessions).map(|_| load()).collect::<Result<Vec<T>>>()?;
+    InferencePool::new(name, instances)
+}
+
+/// Both correction models, each behind its own pool of inference threads.
+/// Greedy requests are batched across callers; beam searches run alone.
 pub struct Corrector {
-    pub gramformer: Option<GrammarCorrector>,
-    pub flan_t5: Option<FlanT5Corrector>,
+    pub gramformer: Option<Batcher<GrammarCorrector>>,
+    pub flan_t5: Option<Batcher<FlanT5Corrector>>,
 }
 
 impl Corrector {
-    pub async fn new() -> Self {
-        let gramformer = match GrammarCorrector::new().await {
-            Ok(corrector) => {
+    /// Load every configured model. A model that fails to load is disabled
+    /// with a warning, or fails startup when `models.required` is set.
+    pub async fn new(config: &Config) -> Result<Self> {
+        let (models, inference) = (&config.models, &config.inference);
+        let batching = BatchConfig {
+            max_batch_size: inference.batch_size,
+            window: inference.batch_window(),
+        };
+
+        let gramformer = prepare_gramformer(models).and_then(|manifest| {
+            load_pool("gramformer", inference.sessions, || GrammarCorrector::new(models, &manifest))
+        });
+        let gramformer = match gramformer {
+            Ok(pool) => {
                 info!("Successfully loaded Gramformer ONNX model");
-                Some(corrector)
+                Some(Batcher::new(pool, batching))
             }
+            Err(e) if models.required => return Err(e.context("Gramformer is required (models.required)")),
             Err(e) => {
-                info!("Failed to load Gramformer ONNX model: {}. Gramformer corrections will be disabled.", e);
+                warn!("Failed to load Gramformer ONNX model: {}. Gramformer corrections will be disabled.", e);
                 None
             }
         };
 
-        let flan_t5 = match FlanT5Corrector::new().await {
-            Ok(corrector) => {
+        let flan_t5 = prepare_flan_t5(models).await.and_then(|manifest| {
+            load_pool("flan_t5", inference.sessions, || FlanT5Corrector::new(models, &manifest))
+        });
+        let flan_t5 = match flan_t5 {
+            Ok(pool) => {
                 info!("Successfully loaded FLAN-T5 ONNX model");
-                Some(corrector)
+                Some(Batcher::new(pool, batching))
             }
+            Err(e) if models.required => return Err(e.context("FLAN-T5 is required (models.required)")),
             Err(e) => {
-                info!("Failed to load FLAN-T5 ONNX model: {}. FLAN-T5 corrections will be disabled.", e);
+                warn!("Failed to load FLAN-T5 ONNX model: {}. FLAN-T5 corrections will be disabled.", e);
                 None
             }
         };
 
-        Self { gramformer, flan_t5 }
+        Ok(Self { gramformer, flan_t5 })
+    }
+
+    /// No models; every AI stage passes text through unchanged.
+    pub fn disabled() -> Self {
+        Self { gramformer: None, flan_t5: None }
     }
 
-    pub async fn correct_grammar(&self, text: &str) -> Result<(String, bool)> {
+    /// Gramformer n-best; the unchanged text when the model is not loaded.
+    pub async fn correct_grammar_candidates(&self, text: &str, config: &GenerationConfig) -> Result<Vec<Candidate>> {
         if let Some(gramformer) = &self.gramformer {
-            gramformer.correct_grammar(text).await
+            if config.is_batchable() {
+                return gramformer.correct(text).await;
+            }
+            let (text, config) = (text.to_string(), config.clone());
+            gramformer.pool().run(move |model| model.correct_grammar_candidates(&text, &config)).await
         } else {
-            Ok((text.to_string(), false))
+            Ok(vec![Candidate { text: text.to_string(), score: 0.0 }])
         }
     }
 
-    pub async fn correct_grammar_with_flan_t5(&self, text: &str) -> Result<(String, bool)> {
+    /// FLAN-T5 n-best; the unchanged text when the model is not loaded.
+    pub async fn correct_grammar_with_flan_t5_candidates(&self, text: &str, config: &GenerationConfig) -> Result<Vec<Candidate>> {
         if let Some(flan_t5) = &self.flan_t5 {
-            flan_t5.correct_grammar(text).await
+            if config.is_batchable() {
+                return flan_t5.correct(text).await;
+            }
+            let (text, config) = (text.to_string(), config.clone());
+            flan_t5.pool().run(move |model| model.correct_grammar_candidates(&text, &config)).await
         } else {
-            Ok((text.to_string(), false))
+            Ok(vec![Candidate { text: text.to_string(), score: 0.0 }])
         }
     }
+
+    /// Load of every model pool that is running.
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        self.gramformer.iter().map(|batcher| batcher.pool().stats())
+            .chain(self.flan_t5.iter().map(|batcher| batcher.pool().stats()))
+            .collect()
+    }
 }
 
 impl std::fmt::Debug for Corrector {
@@ -0,0 +1,322 @@
+// lang/html.rs - Offset-preserving HTML scanning
+//
+// A small scanner rather than a full HTML parser: it only has to tell text
+// nodes from markup, keep byte offsets into the original string, and know
+// which elements hold no prose. Tags, comments, attributes and character
+// references are markup; `<script>`, `<style>`, `<pre>`, `<code>` and friends
+// are skipped whole; block-level tags end a prose block.
+use harper_core::parsers::{Parser, PlainEnglish};
+use harper_core::{Span, Token, TokenKind};
+use std::ops::Range;
+
+/// Elements whose content is never prose.
+const SKIPPED_ELEMENTS: [&str; 10] = [
+    "script", "style", "pre", "code", "textarea", "template", "kbd", "samp", "svg", "math",
+];
+
+/// Skipped elements that also end a block of prose; the others sit inside sentences.
+const SKIPPED_BLOCKS: [&str; 5] = ["script", "style", "pre", "textarea", "template"];
+
+/// Elements that start or end a block of prose.
+const BLOCK_ELEMENTS: [&str; 35] = [
+    "address", "article", "aside", "blockquote", "body", "br", "caption", "dd", "div", "dl", "dt",
+    "figcaption", "figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hr",
+    "li", "main", "nav", "ol", "p", "section", "table", "td", "th", "title", "tr", "ul",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceKind {
+    /// Character data of a text node
+    Text,
+    /// A tag, comment, declaration or character reference
+    Markup,
+    /// Markup that also ends a block of prose, or a skipped element
+    Break,
+}
+
+#[derive(Debug, Clone)]
+pub struct Piece {
+    pub kind: PieceKind,
+    pub range: Range<usize>,
+}
+
+/// Split `html` into text and markup, covering it in order.
+pub fn scan(html: &str) -> Vec<Piece> {
+    let bytes = html.as_bytes();
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    let mut at = 0;
+
+    while at < bytes.len() {
+        let markup = match bytes[at] {
+            b'<' => markup_at(html, at),
+            b'&' => reference_at(html, at).map(|end| (end, PieceKind::Markup)),
+            _ => None,
+        };
+        let Some((end, kind)) = markup else {
+            at += 1;
+            continue;
+        };
+
+        if text_start < at {
+            pieces.push(Piece { kind: PieceKind::Text, range: text_start..at });
+        }
+        pieces.push(Piece { kind, range: at..end });
+        at = end;
+        text_start = end;
+    }
+
+    if text_start < bytes.len() {
+        pieces.push(Piece { kind: PieceKind::Text, range: text_start..bytes.len() });
+    }
+    pieces
+}
+
+/// End and kind of the markup starting at the `<` at `start`, if it is markup.
+fn markup_at(html: &str, start: usize) -> Option<(usize, PieceKind)> {
+    let rest = &html[start..];
+    if let Some(comment) = rest.strip_prefix("<!--") {
+        let end = comment.find("-->").map_or(html.len(), |i| start + 4 + i + 3);
+        return Some((end, PieceKind::Markup));
+    }
+    if rest.starts_with("<!") || rest.starts_with("<?") {
+        let end = rest.find('>').map_or(html.len(), |i| start + i + 1);
+        return Some((end, PieceKind::Markup));
+    }
+
+    let closing = rest.starts_with("</");
+    let name_start = start + if closing { 2 } else { 1 };
+    let name_len = html[name_start..]
+        .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '-'))
+        .unwrap_or(html.len() - name_start);
+    // "a < b" is text, not a tag
+    if name_len == 0 || !html.as_bytes()[name_start].is_ascii_alphabetic() {
+        return None;
+    }
+    let name = html[name_start..name_start + name_len].to_ascii_lowercase();
+    let end = tag_end(html, name_start + name_len);
+
+    let skipped = !closing && SKIPPED_ELEMENTS.contains(&name.as_str()) && !html[..end].ends_with("/>");
+    let end = if skipped { element_end(html, end, &name) } else { end };
+    let block = if skipped { SKIPPED_BLOCKS.contains(&name.as_str()) } else { BLOCK_ELEMENTS.contains(&name.as_str()) };
+    Some((end, if block { PieceKind::Break } else { PieceKind::Markup }))
+}
+
+/// Just past the `>` closing a tag, skipping `>` inside quoted attribute values.
+fn tag_end(html: &str, from: usize) -> usize {
+    let mut quote = None;
+    for (i, ch) in html[from..].char_indices() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(q), _) if ch == q => quote = None,
+            (None, '>') => return from + i + 1,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+/// Just past the close tag matching an open `name` element whose start tag ends at `from`.
+fn element_end(html: &str, from: usize, name: &str) -> usize {
+    let bytes = html.as_bytes();
+    let raw_text = matches!(name, "script" | "style" | "textarea");
+    // Whether the tag name starting at `at` is `name` in any case, and not longer
+    let is_name = |at: usize| {
+        let end = at + name.len();
+        bytes.get(at..end).is_some_and(|tag| tag.eq_ignore_ascii_case(name.as_bytes()))
+            && !bytes.get(end).is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    let mut depth = 1;
+    let mut at = from;
+
+    while let Some(i) = bytes[at..].iter().position(|&b| b == b'<') {
+        let tag = at + i;
+        if bytes.get(tag + 1) == Some(&b'/') && is_name(tag + 2) {
+            depth -= 1;
+            if depth == 0 {
+                return tag_end(html, tag + 2 + name.len());
+            }
+        } else if !raw_text && is_name(tag + 1) {
+            depth += 1;
+        }
+        at = tag + 1;
+    }
+    html.len()
+}
+
+/// Longest reference name looked for, e.g. `CounterClockwiseContourIntegral`.
+const MAX_REFERENCE_LEN: usize = 32;
+
+/// End of a character reference like `&amp;`, `&#8217;` or `&#x2019;` at `start`.
+fn reference_at(html: &str, start: usize) -> Option<usize> {
+    // Only look a reference's length ahead, so text full of bare `&`s stays linear
+    let body = &html.as_bytes()[start + 1..];
+    let len = body.iter().take(MAX_REFERENCE_LEN + 1).position(|&b| b == b';')?;
+    let name = &html[start + 1..start + 1 + len];
+    let valid = match name.strip_prefix('#') {
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|ch| ch.is_ascii_hexdigit()),
+            None => !number.is_empty() && number.chars().all(|ch| ch.is_ascii_digit()),
+        },
+        None => !name.is_empty() && name.chars().all(|ch| ch.is_ascii_alphanumeric()),
+    };
+    valid.then_some(start + 1 + len + 1)
+}
+
+/// Harper parser for HTML: text nodes are parsed as English, everything else is unlintable.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Html;
+
+impl Parser for Html {
+    fn parse(&self, source: &[char]) -> Vec<Token> {
+        let html: String = source.iter().collect();
+
+        // Byte offsets from the scanner, char offsets for Harper
+        let mut char_at = vec![0; html.len() + 1];
+        for (index, (byte, ch)) in html.char_indices().enumerate() {
+            char_at[byte..byte + ch.len_utf8()].fill(index);
+        }
+        char_at[html.len()] = source.len();
+
+        let mut tokens = Vec::new();
+        for piece in scan(&html) {
+            let (start, end) = (char_at[piece.range.start], char_at[piece.range.end]);
+            match piece.kind {
+                PieceKind::Text => {
+                    let mut text_tokens = PlainEnglish.parse(&source[start..end]);
+                    text_tokens.iter_mut().for_each(|token| token.span.push_by(start));
+                    tokens.append(&mut text_tokens);
+                }
+                PieceKind::Markup => tokens.push(Token {
+                    span: Span::new(start, end),
+                    kind: TokenKind::Unlintable,
+                }),
+                PieceKind::Break => {
+                    tokens.push(Token {
+                        span: Span::new(start, end),
+                        kind: TokenKind::Unlintable,
+                    });
+                    tokens.push(Token {
+                        span: Span::new_with_len(end, 0),
+                        kind: TokenKind::ParagraphBreak,
+                    });
+                }
+            }
+        }
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PieceKind::{Break, Markup, Text};
+
+    /// Scan, check the pieces cover `html` in order, and return them with their text.
+    fn pieces(html: &str) -> Vec<(PieceKind, &str)> {
+        let pieces = scan(html);
+        let mut at = 0;
+        for piece in &pieces {
+            assert_eq!(piece.range.start, at, "pieces of {:?} leave a gap", html);
+            assert!(piece.range.end > piece.range.start);
+            at = piece.range.end;
+        }
+        assert_eq!(at, html.len());
+        pieces.into_iter().map(|piece| (piece.kind, &html[piece.range])).collect()
+    }
+
+    #[test]
+    fn splits_text_from_inline_and_block_markup() {
+        assert_eq!(pieces("<p>Héllo <b>wörld</b></p>"), [
+            (Break, "<p>"),
+            (Text, "Héllo "),
+            (Markup, "<b>"),
+            (Text, "wörld"),
+            (Markup, "</b>"),
+            (Break, "</p>"),
+        ]);
+        assert_eq!(pieces("one<br/>two"), [(Text, "one"), (Break, "<br/>"), (Text, "two")]);
+    }
+
+    #[test]
+    fn comparisons_and_bare_ampersands_are_text() {
+        assert_eq!(pieces("a < b && c <3 d"), [(Text, "a < b && c <3 d")]);
+        assert_eq!(pieces("AT&T and &nope y"), [(Text, "AT&T and &nope y")]);
+    }
+
+    #[test]
+    fn character_references_are_markup() {
+        assert_eq!(pieces("a&amp;b&#8217;c&#x2019;"), [
+            (Text, "a"),
+            (Markup, "&amp;"),
+            (Text, "b"),
+            (Markup, "&#8217;"),
+            (Text, "c"),
+            (Markup, "&#x2019;"),
+        ]);
+        // Not a valid number
+        assert_eq!(pieces("&#x;&#12a;"), [(Text, "&#x;&#12a;")]);
+        // Names longer than any reference are not looked through
+        assert_eq!(pieces("&CounterClockwiseContourIntegral;"), [(Markup, "&CounterClockwiseContourIntegral;")]);
+        let long = format!("&{};", "a".repeat(MAX_REFERENCE_LEN + 1));
+        assert_eq!(pieces(&long), [(Text, long.as_str())]);
+    }
+
+    #[test]
+    fn quoted_attributes_may_hold_angle_brackets() {
+        assert_eq!(pieces(r#"<a title="x > y" data-q='>'>link</a>"#), [
+            (Markup, r#"<a title="x > y" data-q='>'>"#),
+            (Text, "link"),
+            (Markup, "</a>"),
+        ]);
+    }
+
+    #[test]
+    fn skipped_elements_are_one_piece() {
+        assert_eq!(pieces("x<SCRIPT>if (a < b) { '</p>' }</script>y"), [
+            (Text, "x"),
+            (Break, "<SCRIPT>if (a < b) { '</p>' }</script>"),
+            (Text, "y"),
+        ]);
+        // Inline code sits inside a sentence
+        assert_eq!(pieces("Run <code>teh --fix</code> now"), [
+            (Text, "Run "),
+            (Markup, "<code>teh --fix</code>"),
+            (Text, " now"),
+        ]);
+        assert_eq!(pieces("<pre>a<pre>b</pre>c</pre>d"), [(Break, "<pre>a<pre>b</pre>c</pre>"), (Text, "d")]);
+        assert_eq!(pieces("<Pre>a<PRE>b</pre>c</PrE>d<prefix>"), [
+            (Break, "<Pre>a<PRE>b</pre>c</PrE>"),
+            (Text, "d"),
+            (Markup, "<prefix>"),
+        ]);
+        // Self-closing: nothing to skip
+        assert_eq!(pieces("<svg/>text"), [(Markup, "<svg/>"), (Text, "text")]);
+    }
+
+    #[test]
+    fn unterminated_markup_runs_to_the_end() {
+        assert_eq!(pieces("text <p class='x"), [(Text, "text "), (Break, "<p class='x")]);
+        assert_eq!(pieces("a<!-- never closed"), [(Text, "a"), (Markup, "<!-- never closed")]);
+        assert_eq!(pieces("a<script>alert(1)"), [(Text, "a"), (Break, "<script>alert(1)")]);
+        assert_eq!(pieces("<!DOCTYPE html"), [(Markup, "<!DOCTYPE html")]);
+    }
+
+    #[test]
+    fn comments_and_declarations_are_markup() {
+        assert_eq!(pieces("<!DOCTYPE html><!-- <p> -->hi<?xml x?>"), [
+            (Markup, "<!DOCTYPE html>"),
+            (Markup, "<!-- <p> -->"),
+            (Text, "hi"),
+            (Markup, "<?xml x?>"),
+        ]);
+    }
+
+    #[test]
+    fn stray_close_tags_and_empty_input() {
+        assert_eq!(pieces("</div>text</span>"), [(Break, "</div>"), (Text, "text"), (Markup, "</span>")]);
+        assert!(pieces("").is_empty());
+        assert_eq!(pieces("<"), [(Text, "<")]);
+        assert_eq!(pieces("a&"), [(Text, "a&")]);
+    }
+}
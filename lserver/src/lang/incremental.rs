@@ -0,0 +1,402 @@
+// lang/incremental.rs - Re-checking a document as it is edited
+//
+// A document is checked paragraph by paragraph. The corrections found for a
+// paragraph are kept by its text, so after an edit only paragraphs whose text
+// changed go through the pipeline again; the rest keep their corrections,
+// moved to where the paragraph now starts. Inside a changed paragraph the
+// model stages only see sentences they have not corrected before, through the
+// pipeline's [`SentenceCache`].
+//
+// Checks take time, so by the time corrections arrive the document has often
+// moved on. [`rebase`] moves corrections through the edits made since, and
+// drops only those whose text was edited.
+use anyhow::{Error as E, Result};
+use harper_core::Dialect;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use crate::lang::edits::Edit;
+use crate::lang::generation::GenerationConfig;
+use crate::lang::lint::{calculate_stats, check_grammar_professional, GrammarCorrection, GrammarResponse};
+use crate::lang::offsets::{OffsetConverter, OffsetEncoding};
+use crate::lang::pipeline::{Pipeline, SentenceCache};
+use crate::lang::rules::RuleConfig;
+use crate::lang::state::HarperConfig;
+
+/// The edits that turned one document version into the next.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeBatch {
+    pub version: i64,
+    /// Applied in order, each against the text the previous one produced
+    pub changes: Vec<Edit>,
+}
+
+/// Apply `changes` one after the other, each against the text the previous
+/// one produced, with offsets and lengths in `encoding`. Nothing is applied
+/// if any change falls outside the text.
+pub fn apply_changes(text: &mut String, changes: &[Edit], encoding: OffsetEncoding) -> Result<()> {
+    let mut changed = text.clone();
+    for change in changes {
+        apply_change(&mut changed, change, encoding)?;
+    }
+    *text = changed;
+    Ok(())
+}
+
+/// Apply one change; returns the byte range it replaced.
+fn apply_change(text: &mut String, change: &Edit, encoding: OffsetEncoding) -> Result<Range<usize>> {
+    let converter = OffsetConverter::new(text);
+    let text_len = converter.encode(text.len(), encoding);
+    if change.end() > text_len {
+        return Err(E::msg(format!(
+            "Change at {}..{} is outside the text ({} long)",
+            change.offset, change.end(), text_len
+        )));
+    }
+    let start = converter.decode(change.offset, encoding);
+    let end = converter.decode(change.end(), encoding);
+    // Decoding snaps offsets inside a char to its start, which would edit text the client did not mean to
+    if converter.encode(start, encoding) != change.offset || converter.encode(end, encoding) != change.end() {
+        return Err(E::msg(format!(
+            "Change at {}..{} does not fall on character boundaries",
+            change.offset, change.end()
+        )));
+    }
+    text.replace_range(start..end, &change.replacement);
+    Ok(start..end)
+}
+
+/// Corrections moved onto a newer version of their document.
+#[derive(Debug)]
+pub struct Rebased {
+    /// Version the corrections now apply to
+    pub version: Option<i64>,
+    pub corrections: Vec<GrammarCorrection>,
+    /// Corrections whose text was edited, as they were
+    pub invalidated: Vec<GrammarCorrection>,
+}
+
+/// Move `corrections`, computed for `text` at `version`, through the edits in
+/// `batches`. Offsets in and out are in `encoding`. A correction is dropped
+/// when an edit changes its `original_text` or adds to the word at either end
+/// of it; edits elsewhere only shift it.
+pub fn rebase(
+    text: &str,
+    version: Option<i64>,
+    corrections: Vec<GrammarCorrection>,
+    batches: &[ChangeBatch],
+    encoding: OffsetEncoding,
+) -> Result<Rebased> {
+    let mut last = version;
+    for batch in batches {
+        if last.is_some_and(|last| batch.version <= last) {
+            return Err(E::msg(format!("Changes for version {} follow version {}", batch.version, last.unwrap_or_default())));
+        }
+        last = Some(batch.version);
+    }
+
+    // Work in bytes of the text as it is edited
+    let converter = OffsetConverter::new(text);
+    let mut spans: Vec<Option<Range<usize>>> = corrections
+        .iter()
+        .map(|correction| {
+            let start = converter.decode(correction.offset, encoding);
+            Some(start..converter.decode(correction.offset + correction.length, encoding))
+        })
+        .collect();
+    let mut current = text.to_string();
+
+    for change in batches.iter().flat_map(|batch| &batch.changes) {
+        let replaced = apply_change(&mut current, change, encoding)?;
+        for span in &mut spans {
+            *span = span.take().and_then(|span| move_span(span, &replaced, change.replacement.len(), &current));
+        }
+    }
+
+    let converter = OffsetConverter::new(&current);
+    let mut rebased = Rebased { version: last, corrections: Vec::new(), invalidated: Vec::new() };
+    for (mut correction, span) in corrections.into_iter().zip(spans) {
+        match span {
+            // A span whose text no longer matches was edited in a way we could not see
+            Some(span) if current.get(span.clone()) == Some(correction.original_text.as_str()) => {
+                (correction.offset, correction.length) = converter.encode_span(span.start, span.len(), encoding);
+                rebased.corrections.push(correction);
+            }
+            _ => rebased.invalidated.push(correction),
+        }
+    }
+    Ok(rebased)
+}
+
+/// Where `span` is after `replaced` became `inserted` bytes of `edited`, the
+/// text after the edit, or `None` if the edit touched it.
+fn move_span(span: Range<usize>, replaced: &Range<usize>, inserted: usize, edited: &str) -> Option<Range<usize>> {
+    let shift = inserted as isize - replaced.len() as isize;
+    let moved = if replaced.end <= span.start {
+        let start = (span.start as isize + shift) as usize;
+        start..start + span.len()
+    } else {
+        span.clone()
+    };
+
+    // Next to the span, an edit only counts when a word now runs into it: typing
+    // onto its word, or deleting the space between it and the word beside it
+    let joins_word = |ch: Option<char>| ch.is_some_and(char::is_alphanumeric);
+    let touched = if replaced.end < span.start || replaced.start > span.end {
+        false
+    } else if replaced.end == span.start && !(replaced.is_empty() && span.is_empty()) {
+        joins_word(edited[..moved.start].chars().next_back())
+    } else if replaced.start == span.end && !span.is_empty() {
+        joins_word(edited[moved.end..].chars().next())
+    } else {
+        true
+    };
+    (!touched).then_some(moved)
+}
+
+/// One incremental check of a document.
+#[derive(Debug)]
+pub struct IncrementalCheck {
+    /// Corrections for the whole document, offsets in bytes
+    pub response: GrammarResponse,
+    /// Paragraphs that went through the pipeline
+    pub rechecked: usize,
+    /// Paragraphs whose corrections were reused
+    pub reused: usize,
+}
+
+/// What an edited document's earlier checks found, by paragraph and sentence.
+#[derive(Debug, Default)]
+pub struct DocumentCache {
+    // Paragraph text -> corrections relative to the paragraph
+    paragraphs: HashMap<String, Arc<Vec<GrammarCorrection>>>,
+    sentences: Arc<SentenceCache>,
+}
+
+impl DocumentCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `pipeline` with this document's sentence cache, for [`DocumentCache::check`].
+    pub fn attach<'a>(&self, pipeline: Pipeline<'a>) -> Pipeline<'a> {
+        pipeline.with_cache(self.sentences.clone())
+    }
+
+    /// Check `text`, running `pipeline` only on paragraphs not seen in the
+    /// previous check. The options must be the same for every check, and
+    /// sentences are only reused when `pipeline` came from [`DocumentCache::attach`].
+    pub async fn check(
+        &mut self,
+        harper: &HarperConfig,
+        text: &str,
+        dialect: Dialect,
+        pipeline: &Pipeline<'_>,
+        generation: &GenerationConfig,
+        rules: &RuleConfig,
+    ) -> IncrementalCheck {
+        let paragraphs = harper.paragraphs(text);
+
+        // Each paragraph text that is new, once; they are checked concurrently
+        let mut changed: Vec<&str> = Vec::new();
+        for range in &paragraphs {
+            let paragraph = &text[range.clone()];
+            if !self.paragraphs.contains_key(paragraph) && !changed.contains(&paragraph) {
+                changed.push(paragraph);
+            }
+        }
+        let checks = changed.iter().map(|paragraph| {
+            check_grammar_professional(harper, paragraph, dialect, pipeline, generation, rules)
+        });
+        let responses = futures::future::join_all(checks).await;
+
+        let mut found: HashMap<String, Arc<Vec<GrammarCorrection>>> = changed
+            .iter()
+            .zip(responses)
+            .map(|(paragraph, response)| {
+                // The whole-text summary only makes sense for a whole document
+                let corrections = response.corrections.into_iter().filter(|c| c.category != "summary").collect();
+                (paragraph.to_string(), Arc::new(corrections))
+            })
+            .collect();
+        for range in &paragraphs {
+            let paragraph = &text[range.clone()];
+            if let Some(corrections) = self.paragraphs.get(paragraph) {
+                found.insert(paragraph.to_string(), corrections.clone());
+            }
+        }
+
+        let corrections = place_corrections(text, &paragraphs, &found);
+        let rechecked = paragraphs.iter().filter(|range| changed.contains(&&text[(*range).clone()])).count();
+        let reused = paragraphs.len() - rechecked;
+
+        // Keep only what the current text has, so the cache follows the document
+        self.paragraphs = found;
+
+        IncrementalCheck {
+            response: GrammarResponse {
+                stats: calculate_stats(&corrections),
+                corrections,
+                offset_encoding: OffsetEncoding::Bytes,
+                stages: Vec::new(),
+                version: None,
+            },
+            rechecked,
+            reused,
+        }
+    }
+}
+
+/// Every paragraph's corrections at the paragraph's place in `text`, with
+/// ids unique across the document.
+fn place_corrections(
+    text: &str,
+    paragraphs: &[Range<usize>],
+    found: &HashMap<String, Arc<Vec<GrammarCorrection>>>,
+) -> Vec<GrammarCorrection> {
+    let mut corrections = Vec::new();
+    for range in paragraphs {
+        for correction in found[&text[range.clone()]].iter() {
+            let mut correction = correction.clone();
+            correction.offset += range.start;
+            correction.id = format!("{}_{}", correction.source_stage, corrections.len() + 1);
+            corrections.push(correction);
+        }
+    }
+    corrections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A correction of the first `original` in `text`, offsets in `encoding`.
+    fn correction(text: &str, original: &str, encoding: OffsetEncoding) -> GrammarCorrection {
+        let start = text.find(original).expect("original is in the text");
+        let (offset, length) = OffsetConverter::new(text).encode_span(start, original.len(), encoding);
+        GrammarCorrection {
+            id: format!("harper_{}", original),
+            category: "correctness".to_string(),
+            subcategory: "spelling".to_string(),
+            severity: "critical".to_string(),
+            confidence: 0.9,
+            visual_treatment: "highlight".to_string(),
+            offset,
+            length,
+            original_text: original.to_string(),
+            suggestions: vec!["the".to_string()],
+            primary_suggestion: "the".to_string(),
+            explanation: String::new(),
+            source_stage: "harper".to_string(),
+            auto_apply: true,
+            rule: None,
+        }
+    }
+
+    fn batch(version: i64, changes: Vec<Edit>) -> ChangeBatch {
+        ChangeBatch { version, changes }
+    }
+
+    /// Rebase a correction of "teh" in `text` through `changes` (bytes);
+    /// its new offset, or `None` when it was invalidated.
+    fn rebase_teh(text: &str, changes: Vec<Edit>) -> Option<usize> {
+        let corrections = vec![correction(text, "teh", OffsetEncoding::Bytes)];
+        let rebased = rebase(text, Some(1), corrections, &[batch(2, changes)], OffsetEncoding::Bytes).unwrap();
+        assert_eq!(rebased.corrections.len() + rebased.invalidated.len(), 1);
+        rebased.corrections.first().map(|correction| correction.offset)
+    }
+
+    #[test]
+    fn edits_before_a_span_shift_it() {
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(0, 1, "We")]), Some(7));
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(2, 4, "")]), Some(2));
+    }
+
+    #[test]
+    fn edits_after_a_span_leave_it() {
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(10, 3, "dog")]), Some(6));
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(13, 0, " today")]), Some(6));
+    }
+
+    #[test]
+    fn edits_inside_a_span_invalidate_it() {
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(7, 1, "h")]), None);
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(4, 4, "")]), None);
+    }
+
+    #[test]
+    fn spacing_and_punctuation_next_to_a_span_keep_it() {
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(6, 0, "\"")]), Some(7));
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(9, 0, ",")]), Some(6));
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(5, 1, "  ")]), Some(7));
+    }
+
+    #[test]
+    fn words_joining_a_span_invalidate_it() {
+        // Typing onto either end of the word
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(6, 0, "x")]), None);
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(9, 0, "e")]), None);
+        // Deleting the space between it and the word beside it: "sawteh", "tehcat"
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(5, 1, "")]), None);
+        assert_eq!(rebase_teh("I saw teh cat", vec![Edit::new(9, 1, "")]), None);
+    }
+
+    #[test]
+    fn rebases_through_several_batches() {
+        let text = "I saw teh cat";
+        let corrections = vec![correction(text, "teh", OffsetEncoding::Bytes), correction(text, "cat", OffsetEncoding::Bytes)];
+        let batches = [
+            batch(2, vec![Edit::new(0, 0, "Yesterday ")]),
+            batch(5, vec![Edit::new(23, 0, "s"), Edit::new(0, 10, "")]),
+        ];
+
+        let rebased = rebase(text, Some(1), corrections, &batches, OffsetEncoding::Bytes).unwrap();
+        assert_eq!(rebased.version, Some(5));
+        assert_eq!(rebased.corrections.iter().map(|c| c.offset).collect::<Vec<_>>(), [6]);
+        assert_eq!(rebased.invalidated[0].original_text, "cat");
+    }
+
+    #[test]
+    fn offsets_stay_in_the_clients_unit() {
+        let text = "😀 saw teh cat";
+        let corrections = vec![correction(text, "teh", OffsetEncoding::Utf16)];
+        assert_eq!(corrections[0].offset, 7);
+
+        // Insert another emoji at the start, in UTF-16 units
+        let batches = [batch(2, vec![Edit::new(0, 0, "👍")])];
+        let rebased = rebase(text, Some(1), corrections, &batches, OffsetEncoding::Utf16).unwrap();
+        assert_eq!((rebased.corrections[0].offset, rebased.corrections[0].length), (9, 3));
+    }
+
+    #[test]
+    fn versions_must_increase() {
+        let error = rebase("text", Some(3), Vec::new(), &[batch(3, Vec::new())], OffsetEncoding::Bytes).unwrap_err();
+        assert!(error.to_string().contains("follow version 3"));
+        assert!(rebase("text", None, Vec::new(), &[batch(2, Vec::new()), batch(1, Vec::new())], OffsetEncoding::Bytes).is_err());
+
+        let rebased = rebase("text", None, Vec::new(), &[], OffsetEncoding::Bytes).unwrap();
+        assert_eq!(rebased.version, None);
+    }
+
+    #[test]
+    fn changes_apply_in_order_or_not_at_all() {
+        let mut text = "I saw teh cat".to_string();
+        apply_changes(&mut text, &[Edit::new(6, 3, "the"), Edit::new(0, 1, "We")], OffsetEncoding::Bytes).unwrap();
+        assert_eq!(text, "We saw the cat");
+
+        // The second change is out of range, so neither applies
+        assert!(apply_changes(&mut text, &[Edit::new(0, 2, "I"), Edit::new(20, 0, "!")], OffsetEncoding::Bytes).is_err());
+        assert_eq!(text, "We saw the cat");
+    }
+
+    #[test]
+    fn changes_off_char_boundaries_are_rejected() {
+        let mut text = "café 😀".to_string();
+        assert!(apply_changes(&mut text, &[Edit::new(4, 0, "x")], OffsetEncoding::Bytes).is_err());
+        // Between the halves of the emoji's surrogate pair
+        assert!(apply_changes(&mut text, &[Edit::new(6, 0, "x")], OffsetEncoding::Utf16).is_err());
+        apply_changes(&mut text, &[Edit::new(5, 2, "!")], OffsetEncoding::Utf16).unwrap();
+        assert_eq!(text, "café !");
+    }
+}
@@ -4,7 +4,13 @@ use harper_core::{
     linting::{Lint,Suggestion},
 };
 use serde::{Deserialize, Serialize};
-use crate::lang::state::HarperConfig;
+use crate::lang::align::{align, tokenize};
+use crate::lang::errant::classify_edit;
+use crate::lang::generation::{Candidate, GenerationConfig};
+use crate::lang::offsets::{OffsetConverter, OffsetEncoding};
+use crate::lang::pipeline::{Pipeline, StageContext, StageReport, GRAMFORMER};
+use crate::lang::rules::RuleConfig;
+use crate::lang::state::{HarperConfig, RuleLint};
 
 // Legacy JSONSuggestion for backward compatibility
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -16,13 +22,6 @@ pub struct JSONSuggestion {
     pub replacements: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GrammarRequest {
-    pub text: String,
-    pub dialect: Option<String>,
-    pub use_t5: Option<bool>,
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GrammarCorrection {
     pub id: String,
@@ -39,9 +38,11 @@ pub struct GrammarCorrection {
     pub explanation: String,
     pub source_stage: String,    // "harper", "gramformer", "flan_t5"
     pub auto_apply: bool,
+    #[serde(default)]
+    pub rule: Option<String>,    // Harper rule that raised it, e.g. "SpellCheck"
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GrammarStats {
     pub total_issues: usize,
     pub critical: usize,
@@ -49,18 +50,102 @@ pub struct GrammarStats {
     pub enhancement: usize,
 }
 
+impl GrammarStats {
+    /// Add another check's counts to these.
+    pub fn add(&mut self, other: &GrammarStats) {
+        self.total_issues += other.total_issues;
+        self.critical += other.critical;
+        self.important += other.important;
+        self.enhancement += other.enhancement;
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GrammarResponse {
     pub corrections: Vec<GrammarCorrection>,
     pub stats: GrammarStats,
+    /// Unit of every `offset`/`length` in `corrections`
+    pub offset_encoding: OffsetEncoding,
+    /// How each pipeline stage went, in the order they ran
+    #[serde(default)]
+    pub stages: Vec<StageReport>,
+    /// Document version the corrections are for, when the request gave one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
 }
 
-// Legacy format for backward compatibility
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GrammarSuggestion {
-    pub kind: String,
-    pub message: String,
-    pub replacements: Vec<String>,
+impl GrammarResponse {
+    /// Re-express offsets, which the pipeline produces in bytes, in the client's unit.
+    pub fn with_offset_encoding(mut self, text: &str, encoding: OffsetEncoding) -> Self {
+        if encoding == self.offset_encoding {
+            return self;
+        }
+
+        let converter = OffsetConverter::new(text);
+        encode_corrections(&mut self.corrections, &converter, self.offset_encoding, encoding);
+        self.offset_encoding = encoding;
+        self
+    }
+}
+
+/// Re-express offsets and lengths given in `from` units in `to` units.
+fn encode_corrections(
+    corrections: &mut [GrammarCorrection],
+    converter: &OffsetConverter,
+    from: OffsetEncoding,
+    to: OffsetEncoding,
+) {
+    if from == to {
+        return;
+    }
+    for correction in corrections {
+        let offset = converter.decode(correction.offset, from);
+        let end = converter.decode(correction.offset + correction.length, from);
+        (correction.offset, correction.length) = converter.encode_span(offset, end - offset, to);
+    }
+}
+
+/// One stage's corrections, sent as soon as the stage finishes.
+#[derive(Debug, Serialize)]
+pub struct StageEvent {
+    pub stage: StageReport,
+    pub corrections: Vec<GrammarCorrection>,
+    pub offset_encoding: OffsetEncoding,
+}
+
+/// Sent once every stage has finished.
+#[derive(Debug, Serialize)]
+pub struct StatsEvent {
+    /// Over every correction streamed
+    pub stats: GrammarStats,
+    pub stages: Vec<StageReport>,
+}
+
+/// What a streamed check sends: a `stage` event per stage, then `stats`.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum StreamEvent {
+    Stage(StageEvent),
+    Stats(StatsEvent),
+}
+
+impl StreamEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamEvent::Stage(_) => "stage",
+            StreamEvent::Stats(_) => "stats",
+        }
+    }
+
+    /// Re-express offsets, which the pipeline produces in bytes, in the client's unit.
+    pub fn with_offset_encoding(mut self, text: &str, encoding: OffsetEncoding) -> Self {
+        if let StreamEvent::Stage(event) = &mut self {
+            let converter = OffsetConverter::new(text);
+            encode_corrections(&mut event.corrections, &converter, event.offset_encoding, encoding);
+            event.offset_encoding = encoding;
+        }
+        self
+    }
 }
 
 /// Professional UX-focused grammar checking with proper categorization
@@ -68,85 +153,35 @@ pub async fn check_grammar_professional(
     state: &HarperConfig,
     text: &str,
     dialect: Dialect,
-    t5_corrector: Option<&crate::lang::Corrector>
+    pipeline: &Pipeline<'_>,
+    generation: &GenerationConfig,
+    rules: &RuleConfig,
 ) -> GrammarResponse {
-    let mut corrections = Vec::new();
-    let mut id_counter = 0;
-    
-    // Stage 1: Harper (Rule-based precision)
-    let harper_lints = state.run_lints(text, dialect);
-    for lint in &harper_lints {
-        let correction = GrammarCorrection::from_harper_lint(text, lint, &mut id_counter);
-        corrections.push(correction);
-    }
+    let context = StageContext { harper: state, dialect, generation, rules };
+    let run = pipeline.run(text, &context).await;
+    let mut corrections = run.corrections;
     
-    // Stage 2 & 3: AI corrections if T5 is available
-    if let Some(corrector) = t5_corrector {
-        // Apply Harper corrections first
-        let mut harper_corrected = text.to_string();
-        let mut offset_adj = 0i32;
-        
-        // Sort by offset and apply Harper corrections
-        let mut sorted_corrections = corrections.clone();
-        sorted_corrections.sort_by_key(|c| c.offset);
-        
-        for correction in &sorted_corrections {
-            if !correction.primary_suggestion.is_empty() {
-                let adj_offset = (correction.offset as i32 + offset_adj) as usize;
-                let end_pos = adj_offset + correction.length;
-                
-                if end_pos <= harper_corrected.len() {
-                    harper_corrected.replace_range(
-                        adj_offset..end_pos,
-                        &correction.primary_suggestion
-                    );
-                    offset_adj += correction.primary_suggestion.len() as i32 - correction.length as i32;
-                }
-            }
-        }
-        
-        // Stage 2: Gramformer
-        if let Ok((gramformer_result, _)) = corrector.correct_grammar(&harper_corrected).await {
-            let gramformer_corrections = GrammarCorrection::from_ai_correction(
-                &harper_corrected, 
-                &gramformer_result, 
-                "gramformer", 
-                &mut id_counter
-            );
-            corrections.extend(gramformer_corrections);
-            
-            // Stage 3: FLAN-T5
-            if let Ok((flan_result, _)) = corrector.correct_grammar_with_flan_t5(&gramformer_result).await {
-                let flan_corrections = GrammarCorrection::from_ai_correction(
-                    &gramformer_result, 
-                    &flan_result, 
-                    "flan_t5", 
-                    &mut id_counter
-                );
-                corrections.extend(flan_corrections);
-                
-                // Add three-stage summary if we have significant changes
-                if !corrections.is_empty() {
-                    id_counter += 1;
-                    corrections.push(GrammarCorrection {
-                        id: format!("three_stage_{}", id_counter),
-                        category: "summary".to_string(),
-                        subcategory: "three_stage".to_string(),
-                        severity: "enhancement".to_string(),
-                        confidence: 0.85,
-                        visual_treatment: "none".to_string(),
-                        offset: 0,
-                        length: text.len(),
-                        original_text: text.to_string(),
-                        suggestions: vec![harper_corrected.clone(), gramformer_result, flan_result],
-                        primary_suggestion: corrections.last().map(|c| c.primary_suggestion.clone()).unwrap_or_default(),
-                        explanation: "Complete writing improvement:\nSpelling → Grammar → Style".to_string(),
-                        source_stage: "three_stage".to_string(),
-                        auto_apply: false,
-                    });
-                }
-            }
-        }
+    // Add a summary of the document after each stage when the whole pipeline ran
+    let completed = run.stages.last().is_some_and(|stage| stage.status.ran());
+    if run.stages.len() > 1 && completed && !corrections.is_empty() {
+        let stages: Vec<&str> = run.stages.iter().map(|stage| stage.description.as_str()).collect();
+        corrections.push(GrammarCorrection {
+            id: format!("three_stage_{}", corrections.len() + 1),
+            category: "summary".to_string(),
+            subcategory: "three_stage".to_string(),
+            severity: "enhancement".to_string(),
+            confidence: 0.85,
+            visual_treatment: "none".to_string(),
+            offset: 0,
+            length: text.len(),
+            original_text: text.to_string(),
+            suggestions: run.stages.iter().map(|stage| stage.text.clone()).collect(),
+            primary_suggestion: corrections.last().map(|c| c.primary_suggestion.clone()).unwrap_or_default(),
+            explanation: format!("Complete writing improvement:\n{}", stages.join(" → ")),
+            source_stage: "three_stage".to_string(),
+            auto_apply: false,
+            rule: None,
+        });
     }
     
     // Deduplicate overlapping corrections
@@ -158,9 +193,47 @@ pub async fn check_grammar_professional(
     GrammarResponse {
         corrections,
         stats,
+        offset_encoding: OffsetEncoding::Bytes,
+        stages: run.stages.iter().map(StageReport::from).collect(),
+        version: None,
     }
 }
 
+/// Like [`check_grammar_professional`], but hands each stage's corrections to
+/// `send` as soon as that stage finishes. Corrections sent cannot be taken
+/// back, so a correction overlapping one already sent is dropped: earlier
+/// stages win rather than the most confident. There is no whole-document summary.
+pub async fn stream_grammar_professional(
+    state: &HarperConfig,
+    text: &str,
+    dialect: Dialect,
+    pipeline: &Pipeline<'_>,
+    generation: &GenerationConfig,
+    rules: &RuleConfig,
+    mut send: impl FnMut(StreamEvent),
+) {
+    let context = StageContext { harper: state, dialect, generation, rules };
+    let mut sent: Vec<GrammarCorrection> = Vec::new();
+
+    let run = pipeline.run_with(text, &context, |stage, found| {
+        let fresh: Vec<GrammarCorrection> = deduplicate_corrections(found.to_vec())
+            .into_iter()
+            .filter(|correction| !sent.iter().any(|existing| overlaps(existing, correction)))
+            .collect();
+        sent.extend(fresh.iter().cloned());
+        send(StreamEvent::Stage(StageEvent {
+            stage: StageReport::from(stage),
+            corrections: fresh,
+            offset_encoding: OffsetEncoding::Bytes,
+        }));
+    }).await;
+
+    send(StreamEvent::Stats(StatsEvent {
+        stats: calculate_stats(&sent),
+        stages: run.stages.iter().map(StageReport::from).collect(),
+    }));
+}
+
 /// Deduplicate overlapping corrections, keeping highest confidence
 fn deduplicate_corrections(mut corrections: Vec<GrammarCorrection>) -> Vec<GrammarCorrection> {
     corrections.sort_by(|a, b| {
@@ -171,15 +244,7 @@ fn deduplicate_corrections(mut corrections: Vec<GrammarCorrection>) -> Vec<Gramm
     let mut deduplicated = Vec::new();
     
     for correction in corrections {
-        let overlaps = deduplicated.iter().any(|existing: &GrammarCorrection| {
-            let existing_end = existing.offset + existing.length;
-            let correction_end = correction.offset + correction.length;
-            
-            // Check for overlap
-            correction.offset < existing_end && existing.offset < correction_end
-        });
-        
-        if !overlaps {
+        if !deduplicated.iter().any(|existing| overlaps(existing, &correction)) {
             deduplicated.push(correction);
         }
     }
@@ -187,8 +252,13 @@ fn deduplicate_corrections(mut corrections: Vec<GrammarCorrection>) -> Vec<Gramm
     deduplicated
 }
 
+/// Whether two corrections touch any of the same text.
+fn overlaps(a: &GrammarCorrection, b: &GrammarCorrection) -> bool {
+    a.offset < b.offset + b.length && b.offset < a.offset + a.length
+}
+
 /// Calculate statistics for the response
-fn calculate_stats(corrections: &[GrammarCorrection]) -> GrammarStats {
+pub fn calculate_stats(corrections: &[GrammarCorrection]) -> GrammarStats {
     let mut critical = 0;
     let mut important = 0;
     let mut enhancement = 0;
@@ -215,19 +285,21 @@ fn calculate_stats(corrections: &[GrammarCorrection]) -> GrammarStats {
 // focuses on converting lints into simpler suggestion structures.
 
 impl GrammarCorrection {
-    /// Convert from Harper lint with professional UX categorization
-    fn from_harper_lint(text: &str, lint: &Lint, id_counter: &mut usize) -> Self {
-        let start = lint.span.start;
-        let end = lint.span.end.min(text.len());
+    /// Convert from Harper lint with professional UX categorization. The id is
+    /// left for the pipeline to assign.
+    pub(crate) fn from_harper_lint(text: &str, converter: &OffsetConverter, RuleLint { rule, lint }: &RuleLint) -> Self {
+        // Harper spans count chars; everything downstream works in bytes
+        let start = converter.byte_from_char(lint.span.start);
+        let end = converter.byte_from_char(lint.span.end).max(start);
         let original_text = text[start..end].to_string();
         
         let replacements: Vec<String> = lint
             .suggestions
             .iter()
-            .filter_map(|s| match s {
-                Suggestion::ReplaceWith(chars) => Some(chars.iter().collect::<String>()),
-                Suggestion::Remove => Some("".to_string()),
-                Suggestion::InsertAfter(chars) => Some(chars.iter().collect::<String>()),
+            .map(|s| match s {
+                Suggestion::ReplaceWith(chars) => chars.iter().collect::<String>(),
+                Suggestion::Remove => "".to_string(),
+                Suggestion::InsertAfter(chars) => chars.iter().collect::<String>(),
             })
             .collect();
         
@@ -242,10 +314,8 @@ impl GrammarCorrection {
             _ => ("correctness", "grammar", "important", "underline", 0.90, false),
         };
         
-        *id_counter += 1;
-        
         Self {
-            id: format!("harper_{}", id_counter),
+            id: String::new(),
             category: category.to_string(),
             subcategory: subcategory.to_string(),
             severity: severity.to_string(),
@@ -259,39 +329,57 @@ impl GrammarCorrection {
             explanation: lint.message.clone(),
             source_stage: "harper".to_string(),
             auto_apply,
+            rule: Some(rule.clone()),
         }
     }
     
     /// Convert from Gramformer/T5 correction with professional UX categorization
     fn from_ai_correction(
+        state: &HarperConfig,
         original: &str, 
         corrected: &str, 
         source: &str, 
-        id_counter: &mut usize
     ) -> Vec<Self> {
         if original.trim() == corrected.trim() { 
             return vec![]; 
         }
         
-        let corrections = Self::find_ai_diffs(original, corrected, source, id_counter);
-        corrections
+        Self::find_ai_diffs(state, original, corrected, source)
     }
     
-    fn find_ai_diffs(original: &str, corrected: &str, source: &str, id_counter: &mut usize) -> Vec<Self> {
-        let orig_words: Vec<&str> = original.split_whitespace().collect();
-        let corr_words: Vec<&str> = corrected.split_whitespace().collect();
+    /// Corrections for the best candidate, with the other candidates' rewrites
+    /// of the same span appended as alternative suggestions. Ids are left for
+    /// the pipeline to assign.
+    pub(crate) fn from_ai_candidates(
+        state: &HarperConfig,
+        original: &str,
+        candidates: &[Candidate],
+        source: &str,
+    ) -> Vec<Self> {
+        let Some(best) = candidates.first() else {
+            return vec![];
+        };
         
-        // Calculate change ratio for categorization
-        let mut changes = 0;
-        let min_len = orig_words.len().min(corr_words.len());
+        let mut corrections = Self::from_ai_correction(state, original, &best.text, source);
         
-        for i in 0..min_len {
-            if orig_words[i] != corr_words[i] {
-                changes += 1;
+        for alternative in &candidates[1..] {
+            for alt in Self::from_ai_correction(state, original, &alternative.text, source) {
+                if let Some(correction) = corrections.iter_mut()
+                    .find(|c| c.offset == alt.offset && c.length == alt.length)
+                {
+                    if !correction.suggestions.contains(&alt.primary_suggestion) {
+                        correction.suggestions.push(alt.primary_suggestion);
+                    }
+                }
             }
         }
-        changes += (orig_words.len() as i32 - corr_words.len() as i32).abs() as usize;
-        let change_ratio = changes as f32 / orig_words.len().max(1) as f32;
+        
+        corrections
+    }
+    
+    fn find_ai_diffs(state: &HarperConfig, original: &str, corrected: &str, source: &str) -> Vec<Self> {
+        let alignment = align(original, corrected);
+        let change_ratio = alignment.change_ratio;
         
         // Professional categorization based on change extent and source
         let (category, subcategory, severity, visual_treatment, confidence) = match source {
@@ -314,9 +402,8 @@ impl GrammarCorrection {
         
         // If major restructuring, create single rephrase suggestion
         if change_ratio > 0.5 {
-            *id_counter += 1;
             return vec![Self {
-                id: format!("{}_{}", source, id_counter),
+                id: String::new(),
                 category: category.to_string(),
                 subcategory: subcategory.to_string(),
                 severity: severity.to_string(),
@@ -334,51 +421,41 @@ impl GrammarCorrection {
                 },
                 source_stage: source.to_string(),
                 auto_apply: false,
+                rule: None,
             }];
         }
         
-        // Otherwise, create word-by-word corrections
-        let mut corrections = Vec::new();
-        let mut orig_pos = 0;
-        
-        for i in 0..min_len {
-            if i > 0 { orig_pos += 1; } // space before word
+        // Otherwise, one correction per aligned edit, typed ERRANT-style
+        alignment.edits.into_iter().map(|edit| {
+            let original_text = original[edit.offset..edit.end()].to_string();
+            let previous = tokenize(&original[..edit.offset]).last().map(|r| &original[r.clone()]);
+            let class = classify_edit(&*state.dictionary, &original_text, &edit.replacement, previous);
             
-            if orig_words[i] != corr_words[i] {
-                *id_counter += 1;
-                corrections.push(Self {
-                    id: format!("{}_{}", source, id_counter),
-                    category: category.to_string(),
-                    subcategory: subcategory.to_string(),
-                    severity: severity.to_string(),
-                    confidence,
-                    visual_treatment: visual_treatment.to_string(),
-                    offset: orig_pos,
-                    length: orig_words[i].len(),
-                    original_text: orig_words[i].to_string(),
-                    suggestions: vec![corr_words[i].to_string()],
-                    primary_suggestion: corr_words[i].to_string(),
-                    explanation: match source {
-                        "gramformer" => format!("Grammar: '{}' → '{}'", orig_words[i], corr_words[i]),
-                        "flan_t5" => format!("Style: '{}' → '{}'", orig_words[i], corr_words[i]),
-                        _ => format!("Correction: '{}' → '{}'", orig_words[i], corr_words[i]),
-                    },
-                    source_stage: source.to_string(),
-                    auto_apply: false,
-                });
+            Self {
+                id: String::new(),
+                category: category.to_string(),
+                subcategory: class.error_type.subcategory().to_string(),
+                severity: severity.to_string(),
+                confidence,
+                visual_treatment: visual_treatment.to_string(),
+                offset: edit.offset,
+                length: edit.length,
+                explanation: class.explain(&original_text, &edit.replacement),
+                original_text,
+                suggestions: vec![edit.replacement.clone()],
+                primary_suggestion: edit.replacement,
+                source_stage: source.to_string(),
+                auto_apply: false,
+                rule: None,
             }
-            
-            orig_pos += orig_words[i].len();
-        }
-        
-        corrections
+        }).collect()
     }
 }
 
 impl JSONSuggestion {
-    fn from_lint(text: &str, lint: &Lint) -> Self {
-        let start = lint.span.start;
-        let end = lint.span.end.min(text.len());
+    fn from_lint(converter: &OffsetConverter, lint: &Lint) -> Self {
+        let start = converter.byte_from_char(lint.span.start);
+        let end = converter.byte_from_char(lint.span.end).max(start);
 
         let replacements = lint
             .suggestions
@@ -402,277 +479,240 @@ impl JSONSuggestion {
         }
     }
 
-    fn many_from_lints(text: &str, lints: &[Lint]) -> Vec<Self> {
-        lints.iter().map(|lint| Self::from_lint(text, lint)).collect()
+    fn many_from_lints(text: &str, lints: &[RuleLint]) -> Vec<Self> {
+        let converter = OffsetConverter::new(text);
+        lints.iter().map(|l| Self::from_lint(&converter, &l.lint)).collect()
     }
 
-    pub fn new(state: &HarperConfig, text: &str, dialect: Dialect) -> Vec<Self> {
-        let lints = state.run_lints(text, dialect);
+    /// Re-express byte offsets produced by [`Self::new`] / [`Self::new_with_t5`] in `encoding`.
+    pub fn encode_offsets(suggestions: &mut [Self], text: &str, encoding: OffsetEncoding) {
+        let converter = OffsetConverter::new(text);
+        for suggestion in suggestions {
+            (suggestion.offset, suggestion.length) =
+                converter.encode_span(suggestion.offset, suggestion.length, encoding);
+        }
+    }
+
+    /// Harper's suggestions, with the request's `rules` over the server defaults.
+    pub fn new(state: &HarperConfig, text: &str, dialect: Dialect, rules: &RuleConfig) -> Vec<Self> {
+        let lints = state.run_lints(text, dialect, &state.effective_rules(rules));
         Self::many_from_lints(text, &lints)
     }
 
-    pub fn from_t5_correction(text: &str, corrected: &str) -> Vec<Self> {
-        if text.trim() == corrected.trim() { return vec![]; }
+    /// Legacy view of a model correction: whole-text rewrites become a
+    /// rephrase, everything else an insert/delete/replace suggestion.
+    fn from_ai_correction(correction: &GrammarCorrection) -> Self {
+        if correction.subcategory == "sentence_structure" {
+            return Self {
+                kind: "rephrase".to_string(),
+                message: "Suggested rephrase for clarity and style".to_string(),
+                offset: correction.offset,
+                length: correction.length,
+                replacements: vec![correction.primary_suggestion.clone()],
+            };
+        }
         
-        Self::find_multiple_diffs(text, corrected)
+        let message = if correction.length == 0 {
+            "Add missing text"
+        } else if correction.primary_suggestion.is_empty() {
+            "Remove extra word"
+        } else {
+            "Grammar correction"
+        };
+        
+        Self {
+            kind: "contextual".to_string(),
+            message: message.to_string(),
+            offset: correction.offset,
+            length: correction.length,
+            replacements: vec![correction.primary_suggestion.clone()],
+        }
     }
 
-    fn find_multiple_diffs(original: &str, corrected: &str) -> Vec<Self> {
-        let orig_words: Vec<&str> = original.split_whitespace().collect();
-        let corr_words: Vec<&str> = corrected.split_whitespace().collect();
+    pub async fn new_with_t5(
+        state: &HarperConfig, 
+        text: &str, 
+        dialect: Dialect,
+        pipeline: &Pipeline<'_>,
+        rules: &RuleConfig,
+    ) -> Vec<Self> {
+        let suggestions = Self::new(state, text, dialect, rules);
         
-        // Check if this is a major restructuring (more than 50% of words changed)
-        let mut changes = 0;
-        let min_len = orig_words.len().min(corr_words.len());
+        // INTELLIGENT PIPELINE: every stage reviews the text as corrected by the
+        // stages before it, while Harper's own suggestions keep precise highlighting
+        let generation = GenerationConfig::default();
+        let context = StageContext { harper: state, dialect, generation: &generation, rules };
+        let run = pipeline.run(text, &context).await;
         
-        for i in 0..min_len {
-            if orig_words[i] != corr_words[i] {
-                changes += 1;
-            }
-        }
+        let Some(gramformer) = run.stage(GRAMFORMER).filter(|stage| stage.status.ran()) else {
+            // No model reviewed the text - Harper's suggestions are all there is
+            return suggestions;
+        };
         
-        // Add length differences as changes
-        changes += (orig_words.len() as i32 - corr_words.len() as i32).abs() as usize;
+        let mut t5_suggestions: Vec<Self> = run.corrections.iter()
+            .filter(|c| c.source_stage == GRAMFORMER)
+            .map(Self::from_ai_correction)
+            .collect();
         
-        let change_ratio = changes as f32 / orig_words.len().max(1) as f32;
+        let with_content: Vec<Self> = suggestions.iter()
+            .filter(|s| !s.replacements.is_empty())
+            .cloned()
+            .collect();
         
-        // If more than 50% changed, classify as rephrase
-        if change_ratio > 0.5 {
-            return vec![Self {
-                kind: "rephrase".to_string(),
-                message: "Suggested rephrase for clarity and style".to_string(),
+        let mut enhanced_suggestions = if with_content.is_empty() {
+            // No Harper suggestions with content - keep originals
+            suggestions.clone()
+        } else {
+            // Three-stage collaborative suggestion showing the text after each stage,
+            // followed by the individual Harper suggestions
+            let mut enhanced = vec![Self {
+                kind: "three_stage".to_string(),
+                message: format!(
+                    "Writing improvement:\n{}",
+                    run.stages.iter().map(|stage| stage.description.as_str()).collect::<Vec<_>>().join(" → ")
+                ),
                 offset: 0,
-                length: original.len(),
-                replacements: vec![corrected.to_string()],
+                length: text.len(),
+                replacements: run.stages.iter().map(|stage| stage.text.clone()).collect(),
             }];
-        }
+            enhanced.extend(with_content);
+            enhanced
+        };
         
-        // Otherwise, do word-by-word diff for minor corrections
-        let mut suggestions = Vec::new();
-        let mut orig_pos = 0;
+        // Handle readability issues with intelligent splitting
+        let has_empty_readability = suggestions.iter()
+            .any(|s| s.kind == "readability" && s.replacements.is_empty());
         
-        // Handle word substitutions for matching positions
-        for i in 0..min_len {
-            if i > 0 { orig_pos += 1; } // space before word
-            
-            if orig_words[i] != corr_words[i] {
-                // Word differs - create substitution suggestion
-                suggestions.push(Self {
-                    kind: "contextual".to_string(),
-                    message: "Grammar correction".to_string(),
-                    offset: orig_pos,
-                    length: orig_words[i].len(),
-                    replacements: vec![corr_words[i].to_string()],
+        if has_empty_readability && t5_suggestions.is_empty() {
+            let words: Vec<&str> = text.split_whitespace().collect();
+            if gramformer.text.trim() != text.trim() {
+                t5_suggestions.push(Self {
+                    kind: "rephrase".to_string(),
+                    message: "Suggested rephrase for clarity and style".to_string(),
+                    offset: 0,
+                    length: text.len(),
+                    replacements: vec![gramformer.text.trim().to_string()],
+                });
+            } else if words.len() > 30 {
+                // Intelligent sentence splitting for long sentences
+                let mut split_point = words.len() / 2;
+                for (i, word) in words.iter().enumerate() {
+                    if i > 10 && i < words.len() - 10
+                        && (word.ends_with(',') || *word == "and" || *word == "but" || *word == "so")
+                    {
+                        split_point = i + 1;
+                        break;
+                    }
+                }
+                
+                let first_part: String = words[..split_point].join(" ");
+                let second_part: String = words[split_point..].join(" ");
+                // Split off the whole first char; it may be several bytes long
+                let mut rest = second_part.chars();
+                let first: String = rest.next().into_iter().flat_map(char::to_uppercase).collect();
+                let rephrase = format!("{}. {}{}", first_part.trim_end_matches(','), first, rest.as_str());
+                
+                t5_suggestions.push(Self {
+                    kind: "rephrase".to_string(),
+                    message: "Suggested rephrase for clarity and style".to_string(),
+                    offset: 0,
+                    length: text.len(),
+                    replacements: vec![rephrase],
                 });
             }
-            
-            orig_pos += orig_words[i].len();
         }
         
-        // Handle length differences for minor changes
-        if corr_words.len() > orig_words.len() {
-            let extra_words = &corr_words[orig_words.len()..];
-            suggestions.push(Self {
-                kind: "contextual".to_string(),
-                message: "Add missing text".to_string(),
-                offset: original.len(),
-                length: 0,
-                replacements: vec![format!(" {}", extra_words.join(" "))],
-            });
-        } else if orig_words.len() > corr_words.len() {
-            // Calculate position of extra words
-            let mut pos = 0;
-            for i in 0..corr_words.len() {
-                if i > 0 { pos += 1; }
-                pos += orig_words[i].len();
-            }
-            
-            // Mark extra words for deletion
-            for i in corr_words.len()..orig_words.len() {
-                if i > 0 { pos += 1; }
-                suggestions.push(Self {
-                    kind: "contextual".to_string(),
-                    message: "Remove extra word".to_string(),
-                    offset: pos,
-                    length: orig_words[i].len(),
-                    replacements: vec!["".to_string()],
-                });
-                pos += orig_words[i].len();
-            }
+        // Combine enhanced Harper suggestions with T5 suggestions
+        enhanced_suggestions.append(&mut t5_suggestions);
+        
+        // Filter out empty suggestions if we have enhanced ones
+        if !enhanced_suggestions.is_empty() {
+            enhanced_suggestions.retain(|s| !s.replacements.is_empty());
+            return enhanced_suggestions;
         }
         
         suggestions
     }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use crate::lang::pipeline::{CorrectionStage, HarperStage, StageInput, StageScope, StageStatus};
 
-    pub async fn new_with_t5(
-        state: &HarperConfig, 
-        text: &str, 
-        dialect: Dialect,
-        t5_corrector: Option<&crate::lang::Corrector>
-    ) -> Vec<Self> {
-        let suggestions = Self::new(state, text, dialect);
+    /// A stage that rewrites every `from` in the text it is given into `to`.
+    struct Rewrite {
+        from: &'static str,
+        to: &'static str,
+    }
 
-        if let Some(corrector) = t5_corrector {
-            // Always get T5's opinion on the text
-            if let Ok((corrected, _)) = corrector.correct_grammar(text).await {
-                let mut t5_suggestions = Self::from_t5_correction(text, &corrected);
-                
-                // INTELLIGENT PIPELINE: Apply ALL Harper suggestions, then let T5 review the result
-                // This creates a "Harper-corrected → T5-enhanced" pipeline while preserving highlighting
-                
-                let harper_suggestions_with_content: Vec<_> = suggestions.iter()
-                    .filter(|s| !s.replacements.is_empty())
-                    .collect();
-                
-                let mut enhanced_suggestions = Vec::new();
-                
-                if !harper_suggestions_with_content.is_empty() {
-                    // Step 1: Apply ALL Harper suggestions to create fully corrected text
-                    let mut fully_corrected_text = text.to_string();
-                    let mut offset_adjustments = 0i32;
-                    
-                    // Sort suggestions by offset to apply them in order
-                    let mut sorted_suggestions = harper_suggestions_with_content.clone();
-                    sorted_suggestions.sort_by_key(|s| s.offset);
-                    
-                    for harper_suggestion in &sorted_suggestions {
-                        if let Some(first_replacement) = harper_suggestion.replacements.first() {
-                            let adjusted_offset = (harper_suggestion.offset as i32 + offset_adjustments) as usize;
-                            let end_pos = adjusted_offset + harper_suggestion.length;
-                            
-                            if end_pos <= fully_corrected_text.len() {
-                                fully_corrected_text.replace_range(
-                                    adjusted_offset..end_pos,
-                                    first_replacement
-                                );
-                                
-                                // Track offset changes for subsequent replacements
-                                offset_adjustments += first_replacement.len() as i32 - harper_suggestion.length as i32;
-                            }
-                        }
-                    }
-                    
-                    // Step 2: Let T5 (Gramformer) review the fully Harper-corrected text
-                    if let Ok((gramformer_result, _)) = corrector.correct_grammar(&fully_corrected_text).await {
-                        let gramformer_enhanced = gramformer_result.trim() != fully_corrected_text.trim();
-                        let gramformer_text = if gramformer_enhanced {
-                            gramformer_result.trim().to_string()
-                        } else {
-                            fully_corrected_text.clone()
-                        };
-                        
-                        // Step 3: FLAN-T5 reviews Gramformer's result
-                        let flan_t5_text = if let Ok((flan_result, flan_changed)) = corrector.correct_grammar_with_flan_t5(&gramformer_text).await {
-                            if flan_changed {
-                                flan_result
-                            } else {
-                                gramformer_text.clone()
-                            }
-                        } else {
-                            gramformer_text.clone() // Fallback if FLAN-T5 fails
-                        };
-                        
-                        // Create three-stage collaborative suggestion - ALWAYS show all three stages
-                        let replacements = vec![
-                            fully_corrected_text.clone(),  // Stage 1: Harper corrections
-                            gramformer_text.clone(),       // Stage 2: Gramformer enhancements  
-                            flan_t5_text,                  // Stage 3: FLAN-T5 (placeholder)
-                        ];
-                        
-                        // Always create three-stage suggestion to show the full pipeline
-                        if replacements.len() >= 2 { // At least Harper + one enhancement
-                            enhanced_suggestions.push(Self {
-                                kind: "three_stage".to_string(),
-                                message: "Writing improvement:\nSpelling → Grammar → Style".to_string(),
-                                offset: 0,
-                                length: text.len(),
-                                replacements,
-                            });
-                            
-                            // Also keep individual Harper suggestions for precise highlighting
-                            for harper_suggestion in &suggestions {
-                                if !harper_suggestion.replacements.is_empty() {
-                                    enhanced_suggestions.push(harper_suggestion.clone());
-                                }
-                            }
-                        } else {
-                            // T5 approved Harper's corrections - keep Harper's individual suggestions
-                            for harper_suggestion in &suggestions {
-                                if !harper_suggestion.replacements.is_empty() {
-                                    enhanced_suggestions.push(harper_suggestion.clone());
-                                }
-                            }
-                        }
-                    } else {
-                        // T5 failed - keep Harper's suggestions
-                        for harper_suggestion in &suggestions {
-                            if !harper_suggestion.replacements.is_empty() {
-                                enhanced_suggestions.push(harper_suggestion.clone());
-                            }
-                        }
-                    }
-                } else {
-                    // No Harper suggestions with content - keep originals
-                    enhanced_suggestions = suggestions.clone();
-                }
-                
-                // Handle readability issues with intelligent splitting
-                let has_empty_readability = suggestions.iter()
-                    .any(|s| s.kind == "readability" && s.replacements.is_empty());
-                
-                if has_empty_readability && t5_suggestions.is_empty() {
-                    if corrected.trim() != text.trim() {
-                        t5_suggestions.push(Self {
-                            kind: "rephrase".to_string(),
-                            message: "Suggested rephrase for clarity and style".to_string(),
-                            offset: 0,
-                            length: text.len(),
-                            replacements: vec![corrected.trim().to_string()],
-                        });
-                    } else {
-                        // Intelligent sentence splitting for long sentences
-                        let words: Vec<&str> = text.split_whitespace().collect();
-                        if words.len() > 30 {
-                            let mut split_point = words.len() / 2;
-                            for (i, word) in words.iter().enumerate() {
-                                if i > 10 && i < words.len() - 10 {
-                                    if word.ends_with(',') || *word == "and" || *word == "but" || *word == "so" {
-                                        split_point = i + 1;
-                                        break;
-                                    }
-                                }
-                            }
-                            
-                            let first_part: String = words[..split_point].join(" ");
-                            let second_part: String = words[split_point..].join(" ");
-                            let rephrase = format!("{}. {}", 
-                                first_part.trim_end_matches(','), 
-                                second_part.chars().next().unwrap().to_uppercase().collect::<String>() + &second_part[1..]
-                            );
-                            
-                            t5_suggestions.push(Self {
-                                kind: "rephrase".to_string(),
-                                message: "Suggested rephrase for clarity and style".to_string(),
-                                offset: 0,
-                                length: text.len(),
-                                replacements: vec![rephrase],
-                            });
-                        }
-                    }
-                }
-                
-                // Combine enhanced Harper suggestions with T5 suggestions
-                enhanced_suggestions.append(&mut t5_suggestions);
-                
-                // Filter out empty suggestions if we have enhanced ones
-                if !enhanced_suggestions.is_empty() {
-                    enhanced_suggestions.retain(|s| !s.replacements.is_empty());
-                    return enhanced_suggestions;
-                }
-            }
+    #[async_trait]
+    impl CorrectionStage for Rewrite {
+        fn name(&self) -> &str {
+            "rewrite"
         }
 
-        suggestions
+        fn scope(&self) -> StageScope {
+            StageScope::Document
+        }
+
+        async fn correct(&self, input: StageInput<'_>, _context: &StageContext<'_>) -> Result<Vec<GrammarCorrection>> {
+            Ok(input.text.match_indices(self.from).map(|(offset, from)| GrammarCorrection {
+                id: String::new(),
+                category: "clarity".to_string(),
+                subcategory: "style".to_string(),
+                severity: "enhancement".to_string(),
+                confidence: 0.5,
+                visual_treatment: "subtle".to_string(),
+                offset,
+                length: from.len(),
+                original_text: from.to_string(),
+                suggestions: vec![self.to.to_string()],
+                primary_suggestion: self.to.to_string(),
+                explanation: String::new(),
+                source_stage: String::new(),
+                auto_apply: false,
+                rule: None,
+            }).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_each_stage_then_the_stats() {
+        let harper = HarperConfig::new();
+        // "teh" is Harper's; the rewrite then sees "the" and overlaps it
+        let text = "I saw teh cat 😀 and it looked fine.";
+        let pipeline = Pipeline::new()
+            .with_stage(HarperStage)
+            .with_stage(Rewrite { from: "the cat", to: "a cat" })
+            .with_stage(Rewrite { from: "fine", to: "well" });
+
+        let mut events = Vec::new();
+        stream_grammar_professional(&harper, text, Dialect::American, &pipeline, &GenerationConfig::default(), &RuleConfig::default(), |event| {
+            events.push(event.with_offset_encoding(text, OffsetEncoding::Utf16));
+        }).await;
+
+        let names: Vec<&str> = events.iter().map(StreamEvent::name).collect();
+        assert_eq!(names, ["stage", "stage", "stage", "stats"]);
+        let [StreamEvent::Stage(spelling), StreamEvent::Stage(overlapping), StreamEvent::Stage(style), StreamEvent::Stats(stats)] = &events[..] else {
+            panic!("unexpected events");
+        };
+
+        assert_eq!(spelling.stage.name, "harper");
+        assert!(spelling.corrections.iter().any(|c| c.original_text == "teh"));
+        // Sent corrections are final, so the later overlapping one is dropped
+        assert!(overlapping.corrections.is_empty());
+        assert_eq!(style.corrections.len(), 1);
+        let fine = &style.corrections[0];
+        assert_eq!(style.offset_encoding, OffsetEncoding::Utf16);
+        // The emoji is four bytes but two UTF-16 units
+        assert_eq!((fine.offset, fine.length), (text.find("fine").unwrap() - 2, 4));
+
+        let streamed = spelling.corrections.len() + style.corrections.len();
+        assert_eq!(stats.stats.total_issues, streamed);
+        assert!(stats.stages.iter().all(|stage| stage.status == StageStatus::Completed));
+        assert_eq!(stats.stages.len(), 3);
     }
 }
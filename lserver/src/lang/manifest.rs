@@ -0,0 +1,237 @@
+// lang/manifest.rs - Per-model manifest describing an ONNX seq2seq export
+//
+// A `manifest.toml` (or `manifest.json`) next to the model files declares
+// everything the correctors would otherwise assume about the export, so other
+// T5/BART GEC models can be dropped in without code changes:
+//
+//   name = "prithivida/grammar_error_correcter_v1"
+//   revision = "main"
+//   onnx_dir = "."               # graphs, relative to the manifest
+//   input_prefix = "gec: "
+//   output_prefix = "grammar: "
+//   max_input_tokens = 256
+//   max_output_tokens = 80
+//
+//   [special_tokens]
+//   decoder_start = 0
+//   eos = [1]
+//   pad = 0
+//
+//   [tensors]                    # only the names that differ
+//   logits = "lm_logits"
+//
+//   [checksums]                  # SHA-256, relative to the manifest
+//   "encoder_model.onnx" = "9f86d081884c7d65..."
+//
+// Without a manifest the built-in defaults for each model apply.
+use anyhow::{Error as E, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokenizers::Tokenizer;
+use tracing::info;
+
+const MANIFEST_FILES: [&str; 2] = ["manifest.toml", "manifest.json"];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelManifest {
+    /// Model identity, e.g. the Hugging Face repository it was exported from
+    pub name: String,
+    pub revision: Option<String>,
+    /// Directory holding the ONNX graphs, relative to the manifest
+    pub onnx_dir: PathBuf,
+    /// Prepended to every input before tokenization
+    pub input_prefix: String,
+    /// Stripped from every output the model echoes it in
+    pub output_prefix: String,
+    pub max_input_tokens: usize,
+    pub max_output_tokens: usize,
+    pub special_tokens: SpecialTokens,
+    pub tensors: TensorNames,
+    /// Expected SHA-256 (hex) of files relative to the manifest, checked at load
+    pub checksums: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpecialTokens {
+    /// First decoder token (T5 uses pad, BART `</s>`)
+    pub decoder_start: i64,
+    /// Any of these ends a hypothesis
+    pub eos: Vec<i64>,
+    /// Pads batched encoder inputs and is fed to rows that have finished
+    /// (T5 uses 0, BART 1)
+    pub pad: i64,
+}
+
+/// Input and output names of the encoder and decoder graphs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TensorNames {
+    pub input_ids: String,
+    pub attention_mask: String,
+    pub decoder_input_ids: String,
+    pub encoder_hidden_states: String,
+    pub encoder_attention_mask: String,
+    pub last_hidden_state: String,
+    pub logits: String,
+    pub use_cache_branch: String,
+    /// Prefix of the past key/value inputs (`past_key_values.N.decoder.key`)
+    pub past_prefix: String,
+    /// Prefix of the matching outputs (`present.N.decoder.key`)
+    pub present_prefix: String,
+}
+
+impl Default for ModelManifest {
+    fn default() -> Self {
+        Self {
+            name: "unknown".to_string(),
+            revision: None,
+            onnx_dir: PathBuf::from("."),
+            input_prefix: String::new(),
+            output_prefix: String::new(),
+            max_input_tokens: 256,
+            max_output_tokens: 128,
+            special_tokens: SpecialTokens::default(),
+            tensors: TensorNames::default(),
+            checksums: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for SpecialTokens {
+    fn default() -> Self {
+        Self {
+            decoder_start: 0,
+            eos: vec![1],
+            pad: 0,
+        }
+    }
+}
+
+impl Default for TensorNames {
+    fn default() -> Self {
+        Self {
+            input_ids: "input_ids".to_string(),
+            attention_mask: "attention_mask".to_string(),
+            decoder_input_ids: "decoder_input_ids".to_string(),
+            encoder_hidden_states: "encoder_hidden_states".to_string(),
+            encoder_attention_mask: "encoder_attention_mask".to_string(),
+            last_hidden_state: "last_hidden_state".to_string(),
+            logits: "logits".to_string(),
+            use_cache_branch: "use_cache_branch".to_string(),
+            past_prefix: "past_key_values".to_string(),
+            present_prefix: "present".to_string(),
+        }
+    }
+}
+
+impl ModelManifest {
+    /// Built-in settings for the Gramformer export, which echoes a "grammar: " prefix.
+    pub fn gramformer() -> Self {
+        Self {
+            name: "prithivida/grammar_error_correcter_v1".to_string(),
+            output_prefix: "grammar: ".to_string(),
+            max_output_tokens: 80,
+            ..Self::default()
+        }
+    }
+
+    /// Built-in settings for FLAN-T5, whose graphs live in `onnx/`. Pad also ends a hypothesis.
+    pub fn flan_t5() -> Self {
+        Self {
+            name: "pszemraj/flan-t5-large-grammar-synthesis".to_string(),
+            onnx_dir: PathBuf::from("onnx"),
+            max_output_tokens: 50,
+            special_tokens: SpecialTokens {
+                eos: vec![1, 0],
+                ..SpecialTokens::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// The manifest in `dir`, or `fallback` when there is none.
+    pub fn load(dir: &Path, fallback: impl FnOnce() -> Self) -> Result<Self> {
+        let Some(path) = MANIFEST_FILES.iter().map(|file| dir.join(file)).find(|path| path.exists()) else {
+            return Ok(fallback());
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| E::msg(format!("Cannot read model manifest {}: {}", path.display(), e)))?;
+        let manifest: Self = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents).map_err(|e| E::msg(e.to_string()))
+        } else {
+            toml::from_str(&contents).map_err(|e| E::msg(e.to_string()))
+        }
+        .map_err(|e| E::msg(format!("Invalid model manifest {}: {}", path.display(), e)))?;
+
+        if manifest.special_tokens.eos.is_empty() {
+            return Err(E::msg(format!("{}: special_tokens.eos must not be empty", path.display())));
+        }
+        if manifest.max_input_tokens == 0 || manifest.max_output_tokens == 0 {
+            return Err(E::msg(format!("{}: max_input_tokens and max_output_tokens must be positive", path.display())));
+        }
+        info!("Loaded model manifest {} ({})", path.display(), manifest.identity());
+        Ok(manifest)
+    }
+
+    /// `name@revision`, for logs.
+    pub fn identity(&self) -> String {
+        match &self.revision {
+            Some(revision) => format!("{}@{}", self.name, revision),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Check every listed file in `dir` against its SHA-256.
+    pub fn verify_checksums(&self, dir: &Path) -> Result<()> {
+        for (file, expected) in &self.checksums {
+            let path = dir.join(file);
+            let actual = sha256_file(&path)
+                .map_err(|e| E::msg(format!("Cannot checksum {}: {}", path.display(), e)))?;
+            if !actual.eq_ignore_ascii_case(expected.trim()) {
+                return Err(E::msg(format!(
+                    "Checksum mismatch for {}: manifest expects {}, file is {}",
+                    path.display(), expected, actual
+                )));
+            }
+        }
+        if !self.checksums.is_empty() {
+            info!("Verified {} checksum(s) for {}", self.checksums.len(), self.identity());
+        }
+        Ok(())
+    }
+
+    /// Special token ids must exist in the tokenizer's vocabulary.
+    pub fn check_tokenizer(&self, tokenizer: &Tokenizer) -> Result<()> {
+        let vocab_size = tokenizer.get_vocab_size(true) as i64;
+        let tokens = &self.special_tokens;
+        let ids = tokens.eos.iter().chain([&tokens.decoder_start, &tokens.pad]);
+
+        if let Some(id) = ids.into_iter().find(|&&id| id < 0 || id >= vocab_size) {
+            return Err(E::msg(format!(
+                "Special token {} of {} is outside the tokenizer vocabulary ({} tokens)",
+                id, self.identity(), vocab_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
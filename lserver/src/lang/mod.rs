@@ -2,7 +2,25 @@
 pub mod state;
 pub mod lint;
 pub mod grammar;
+pub mod generation;
+pub mod offsets;
+pub mod edits;
+pub mod align;
+pub mod errant;
+pub mod seq2seq;
+pub mod pool;
+pub mod batch;
+pub mod manifest;
+pub mod pipeline;
+pub mod rules;
+pub mod dictionary;
+pub mod format;
+pub mod html;
+pub mod blocks;
+pub mod incremental;
 
 pub use state::HarperConfig;
 pub use lint::JSONSuggestion;
 pub use grammar::{GrammarCorrector, Corrector};
+pub use offsets::{OffsetConverter, OffsetEncoding};
+pub use pipeline::{CorrectionStage, Pipeline};
@@ -0,0 +1,185 @@
+// lang/offsets.rs - Offset units and conversion between them
+//
+// Everything inside the pipeline works in byte offsets so `text[start..end]`
+// is always valid. Harper reports char (Unicode scalar) spans, and clients may
+// want bytes, scalars or UTF-16 code units (JavaScript string indices), so
+// conversions happen here and nowhere else.
+use serde::{Deserialize, Serialize};
+
+/// The unit `offset` and `length` are expressed in when sent to a client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OffsetEncoding {
+    /// UTF-8 bytes, what Rust string slicing uses
+    #[serde(rename = "bytes", alias = "utf-8", alias = "utf8")]
+    Bytes,
+    /// Unicode scalar values (Rust `char`s, Python `str` indices)
+    #[serde(rename = "chars", alias = "utf-32", alias = "utf32")]
+    Chars,
+    /// UTF-16 code units (JavaScript and LSP indices)
+    #[default]
+    #[serde(rename = "utf16", alias = "utf-16")]
+    Utf16,
+}
+
+impl OffsetEncoding {
+    /// Length of `text` in this unit.
+    pub fn measure(self, text: &str) -> usize {
+        match self {
+            OffsetEncoding::Bytes => text.len(),
+            OffsetEncoding::Chars => text.chars().count(),
+            OffsetEncoding::Utf16 => text.encode_utf16().count(),
+        }
+    }
+}
+
+/// Precomputed char boundaries of a text for fast offset conversion.
+#[derive(Debug, Clone)]
+pub struct OffsetConverter {
+    /// Byte and UTF-16 offsets of every char start, plus one entry for the end of the text
+    boundaries: Vec<(usize, usize)>,
+}
+
+impl OffsetConverter {
+    pub fn new(text: &str) -> Self {
+        let mut boundaries = Vec::with_capacity(text.len() + 1);
+        let mut utf16 = 0;
+
+        for (byte, ch) in text.char_indices() {
+            boundaries.push((byte, utf16));
+            utf16 += ch.len_utf16();
+        }
+        boundaries.push((text.len(), utf16));
+
+        Self { boundaries }
+    }
+
+    fn text_len(&self) -> usize {
+        self.boundaries.last().map(|b| b.0).unwrap_or(0)
+    }
+
+    /// Index of the char containing `byte` (the char it starts if on a boundary).
+    fn char_index(&self, byte: usize) -> usize {
+        match self.boundaries.binary_search_by_key(&byte, |b| b.0) {
+            Ok(index) => index,
+            Err(index) => index.saturating_sub(1),
+        }
+    }
+
+    /// Byte offset of a char index such as a Harper span bound, clamped to the text.
+    pub fn byte_from_char(&self, index: usize) -> usize {
+        self.boundaries
+            .get(index)
+            .map(|b| b.0)
+            .unwrap_or_else(|| self.text_len())
+    }
+
+    /// Byte offset for an offset expressed in `encoding`, clamped to the text.
+    pub fn decode(&self, offset: usize, encoding: OffsetEncoding) -> usize {
+        match encoding {
+            OffsetEncoding::Bytes => self.boundaries[self.char_index(offset.min(self.text_len()))].0,
+            OffsetEncoding::Chars => self.byte_from_char(offset),
+            OffsetEncoding::Utf16 => {
+                let index = match self.boundaries.binary_search_by_key(&offset, |b| b.1) {
+                    Ok(index) => index,
+                    Err(index) => index.saturating_sub(1),
+                };
+                self.boundaries[index].0
+            }
+        }
+    }
+
+    /// Express a byte offset in `encoding`. Offsets inside a char snap to its start.
+    pub fn encode(&self, byte: usize, encoding: OffsetEncoding) -> usize {
+        let byte = byte.min(self.text_len());
+        let (start, utf16) = self.boundaries[self.char_index(byte)];
+
+        match encoding {
+            OffsetEncoding::Bytes => start,
+            OffsetEncoding::Chars => self.char_index(byte),
+            OffsetEncoding::Utf16 => utf16,
+        }
+    }
+
+    /// Convert a byte `(offset, length)` pair into `encoding`.
+    pub fn encode_span(&self, offset: usize, length: usize, encoding: OffsetEncoding) -> (usize, usize) {
+        let start = self.encode(offset, encoding);
+        let end = self.encode(offset + length, encoding);
+        (start, end.saturating_sub(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: [OffsetEncoding; 3] = [OffsetEncoding::Bytes, OffsetEncoding::Chars, OffsetEncoding::Utf16];
+
+    #[test]
+    fn every_char_boundary_round_trips() {
+        let text = "a😀b 中文 é👍🏽!";
+        let converter = OffsetConverter::new(text);
+        let boundaries = text.char_indices().map(|(byte, _)| byte).chain([text.len()]);
+
+        for byte in boundaries {
+            for encoding in ENCODINGS {
+                let encoded = converter.encode(byte, encoding);
+                assert_eq!(converter.decode(encoded, encoding), byte, "{:?} at byte {}", encoding, byte);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_emoji_and_cjk_per_unit() {
+        let text = "😀中x";
+        let converter = OffsetConverter::new(text);
+
+        // After the emoji: 4 bytes, 1 char, a surrogate pair in UTF-16
+        assert_eq!(converter.encode(4, OffsetEncoding::Bytes), 4);
+        assert_eq!(converter.encode(4, OffsetEncoding::Chars), 1);
+        assert_eq!(converter.encode(4, OffsetEncoding::Utf16), 2);
+        // After the CJK char: 3 more bytes, but 1 char and 1 UTF-16 unit
+        assert_eq!(converter.encode(7, OffsetEncoding::Bytes), 7);
+        assert_eq!(converter.encode(7, OffsetEncoding::Chars), 2);
+        assert_eq!(converter.encode(7, OffsetEncoding::Utf16), 3);
+
+        for encoding in ENCODINGS {
+            assert_eq!(converter.encode(text.len(), encoding), encoding.measure(text));
+        }
+    }
+
+    #[test]
+    fn offsets_inside_a_char_snap_to_its_start() {
+        let converter = OffsetConverter::new("a😀b");
+
+        assert_eq!(converter.encode(3, OffsetEncoding::Utf16), 1);
+        assert_eq!(converter.decode(3, OffsetEncoding::Bytes), 1);
+        // Between the two halves of the surrogate pair
+        assert_eq!(converter.decode(2, OffsetEncoding::Utf16), 1);
+    }
+
+    #[test]
+    fn offsets_past_the_end_are_clamped() {
+        let text = "中文";
+        let converter = OffsetConverter::new(text);
+
+        for encoding in ENCODINGS {
+            assert_eq!(converter.decode(100, encoding), text.len());
+        }
+        assert_eq!(converter.encode(100, OffsetEncoding::Chars), 2);
+        assert_eq!(converter.byte_from_char(100), text.len());
+    }
+
+    #[test]
+    fn spans_convert_to_start_and_length() {
+        let text = "I 😀 teh cat";
+        let converter = OffsetConverter::new(text);
+        let start = text.find("teh").unwrap();
+
+        assert_eq!(converter.encode_span(start, 3, OffsetEncoding::Bytes), (7, 3));
+        assert_eq!(converter.encode_span(start, 3, OffsetEncoding::Chars), (4, 3));
+        assert_eq!(converter.encode_span(start, 3, OffsetEncoding::Utf16), (5, 3));
+        // The emoji itself: one char, two UTF-16 units
+        assert_eq!(converter.encode_span(2, 4, OffsetEncoding::Chars), (2, 1));
+        assert_eq!(converter.encode_span(2, 4, OffsetEncoding::Utf16), (2, 2));
+    }
+}
@@ -0,0 +1,573 @@
+// lang/pipeline.rs - Ordered correction stages over a document
+//
+// Each stage sees the text as corrected by every stage before it and returns
+// corrections against that text. The pipeline applies them for the next stage
+// and maps them back through all earlier edits, so every correction points
+// into the original document and records the stage that made it.
+//
+// The default order is Harper → Gramformer → FLAN-T5; it is configurable, and
+// custom stages can be added with [`Pipeline::with_stage`]. Requests can pick
+// their own stages, tune each one and bound the whole run by a time budget.
+use anyhow::{Error as E, Result};
+use async_trait::async_trait;
+use harper_core::Dialect;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+use tracing::warn;
+use crate::lang::dictionary::rewrites_custom_word;
+use crate::lang::edits::{apply_edits, Edit, EditMap};
+use crate::lang::generation::{GenerationConfig, MAX_NUM_BEAMS};
+use crate::lang::grammar::Corrector;
+use crate::lang::lint::GrammarCorrection;
+use crate::lang::offsets::OffsetConverter;
+use crate::lang::rules::RuleConfig;
+use crate::lang::state::HarperConfig;
+
+pub const HARPER: &str = "harper";
+pub const GRAMFORMER: &str = "gramformer";
+pub const FLAN_T5: &str = "flan_t5";
+
+/// Built-in stages, in their default order.
+pub const BUILTIN_STAGES: [&str; 3] = [HARPER, GRAMFORMER, FLAN_T5];
+
+/// Sentences a [`SentenceCache`] remembers before forgetting the least recently used.
+const SENTENCE_CACHE_ENTRIES: usize = 4096;
+
+/// How much text a stage is given at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageScope {
+    /// The whole document at once
+    Document,
+    /// One sentence-sized chunk at a time, surrounding whitespace trimmed
+    Sentence,
+}
+
+/// Per-request settings for one stage.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StageOptions {
+    /// Cap on generated tokens (model stages)
+    pub max_tokens: Option<usize>,
+    /// Beam width, overriding the request's `generation.num_beams` (model stages)
+    pub num_beams: Option<usize>,
+    /// Corrections below this confidence are dropped and not applied for later stages
+    pub min_confidence: Option<f32>,
+}
+
+impl StageOptions {
+    /// Reject overrides beyond what the server allows a request.
+    fn validate(&self, stage: &str) -> Result<()> {
+        match self.num_beams {
+            Some(num_beams) if num_beams > MAX_NUM_BEAMS => Err(E::msg(format!(
+                "num_beams for stage '{}' is {}, at most {} is allowed",
+                stage, num_beams, MAX_NUM_BEAMS
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn generation(&self, base: &GenerationConfig) -> GenerationConfig {
+        let mut generation = base.clone();
+        if let Some(max_tokens) = self.max_tokens {
+            generation.max_new_tokens = Some(max_tokens);
+        }
+        if let Some(num_beams) = self.num_beams {
+            generation.num_beams = num_beams.max(1);
+        }
+        generation
+    }
+}
+
+/// Request-wide settings every stage can read.
+pub struct StageContext<'a> {
+    pub harper: &'a HarperConfig,
+    pub dialect: Dialect,
+    pub generation: &'a GenerationConfig,
+    /// The request's Harper rule settings; the server defaults apply beneath them
+    pub rules: &'a RuleConfig,
+}
+
+/// What a stage is asked to correct.
+pub struct StageInput<'a> {
+    /// The document or chunk as corrected by the earlier stages
+    pub text: &'a str,
+    /// Corrections the earlier stages made, with offsets into the original document
+    pub prior: &'a [GrammarCorrection],
+}
+
+#[async_trait]
+pub trait CorrectionStage: Send + Sync {
+    /// Identifier used in configuration and as `source_stage` of its corrections.
+    fn name(&self) -> &str;
+
+    /// Short label for the summary correction, e.g. "Grammar".
+    fn description(&self) -> &str {
+        self.name()
+    }
+
+    fn scope(&self) -> StageScope {
+        StageScope::Sentence
+    }
+
+    /// Corrections with offsets into `input.text`. Each correction's primary
+    /// suggestion is applied before the next stage runs; ids and `source_stage`
+    /// are assigned by the pipeline.
+    async fn correct(&self, input: StageInput<'_>, context: &StageContext<'_>) -> Result<Vec<GrammarCorrection>>;
+}
+
+/// Harper's rule-based lints over the whole document.
+pub struct HarperStage;
+
+#[async_trait]
+impl CorrectionStage for HarperStage {
+    fn name(&self) -> &str {
+        HARPER
+    }
+
+    fn description(&self) -> &str {
+        "Spelling"
+    }
+
+    fn scope(&self) -> StageScope {
+        StageScope::Document
+    }
+
+    async fn correct(&self, input: StageInput<'_>, context: &StageContext<'_>) -> Result<Vec<GrammarCorrection>> {
+        let converter = OffsetConverter::new(input.text);
+        let rules = context.harper.effective_rules(context.rules);
+        let lints = context.harper.run_lints(input.text, context.dialect, &rules);
+        Ok(lints.iter().map(|lint| {
+            let mut correction = GrammarCorrection::from_harper_lint(input.text, &converter, lint);
+            rules.options(&lint.rule).apply(&mut correction);
+            correction
+        }).collect())
+    }
+}
+
+/// Gramformer sentence corrections; with beam search, alternatives become extra suggestions.
+pub struct GramformerStage<'a>(pub &'a Corrector);
+
+#[async_trait]
+impl CorrectionStage for GramformerStage<'_> {
+    fn name(&self) -> &str {
+        GRAMFORMER
+    }
+
+    fn description(&self) -> &str {
+        "Grammar"
+    }
+
+    async fn correct(&self, input: StageInput<'_>, context: &StageContext<'_>) -> Result<Vec<GrammarCorrection>> {
+        let candidates = self.0.correct_grammar_candidates(input.text, context.generation).await?;
+        Ok(GrammarCorrection::from_ai_candidates(context.harper, input.text, &candidates, GRAMFORMER))
+    }
+}
+
+/// FLAN-T5 rewrites for clarity and flow.
+pub struct FlanT5Stage<'a>(pub &'a Corrector);
+
+#[async_trait]
+impl CorrectionStage for FlanT5Stage<'_> {
+    fn name(&self) -> &str {
+        FLAN_T5
+    }
+
+    fn description(&self) -> &str {
+        "Style"
+    }
+
+    async fn correct(&self, input: StageInput<'_>, context: &StageContext<'_>) -> Result<Vec<GrammarCorrection>> {
+        let candidates = self.0.correct_grammar_with_flan_t5_candidates(input.text, context.generation).await?;
+        Ok(GrammarCorrection::from_ai_candidates(context.harper, input.text, &candidates, FLAN_T5))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Completed,
+    /// The time budget ran out after part of the document was corrected
+    Partial,
+    /// The time budget ran out before the stage started
+    Skipped,
+    /// The stage failed on every part of the document it was given
+    Failed,
+}
+
+impl StageStatus {
+    /// Whether the stage contributed to the output.
+    pub fn ran(self) -> bool {
+        matches!(self, StageStatus::Completed | StageStatus::Partial)
+    }
+}
+
+/// The document after one stage.
+#[derive(Debug, Clone)]
+pub struct StageRun {
+    pub name: String,
+    pub description: String,
+    /// Whole document with this and every earlier stage's corrections applied
+    pub text: String,
+    pub status: StageStatus,
+    pub elapsed: Duration,
+}
+
+/// How one stage went, as reported to clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageReport {
+    pub name: String,
+    pub status: StageStatus,
+    pub elapsed_ms: u64,
+}
+
+impl From<&StageRun> for StageReport {
+    fn from(run: &StageRun) -> Self {
+        Self {
+            name: run.name.clone(),
+            status: run.status,
+            elapsed_ms: run.elapsed.as_millis() as u64,
+        }
+    }
+}
+
+/// Result of a pipeline run.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineRun {
+    /// Every stage's corrections in stage order, offsets in bytes of the original document
+    pub corrections: Vec<GrammarCorrection>,
+    pub stages: Vec<StageRun>,
+}
+
+impl PipelineRun {
+    pub fn stage(&self, name: &str) -> Option<&StageRun> {
+        self.stages.iter().find(|stage| stage.name == name)
+    }
+}
+
+/// Sentence-scoped stages' corrections by stage and sentence, so that
+/// re-checking an edited document only sends changed sentences to the models.
+/// Meant for one document checked with the same options throughout: the
+/// options are not part of the key.
+#[derive(Debug, Default)]
+pub struct SentenceCache {
+    entries: Mutex<SentenceEntries>,
+}
+
+#[derive(Debug, Default)]
+struct SentenceEntries {
+    // (stage, sentence) -> corrections relative to the sentence, and when last used
+    found: HashMap<(String, String), (Vec<GrammarCorrection>, u64)>,
+    clock: u64,
+}
+
+impl SentenceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, stage: &str, sentence: &str) -> Option<Vec<GrammarCorrection>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        let (found, used) = entries.found.get_mut(&(stage.to_string(), sentence.to_string()))?;
+        *used = clock;
+        Some(found.clone())
+    }
+
+    fn insert(&self, stage: &str, sentence: &str, found: &[GrammarCorrection]) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        entries.found.insert((stage.to_string(), sentence.to_string()), (found.to_vec(), clock));
+
+        if entries.found.len() > SENTENCE_CACHE_ENTRIES {
+            // Forget the older half in one go rather than one entry per insert
+            let mut used: Vec<u64> = entries.found.values().map(|(_, used)| *used).collect();
+            used.sort_unstable();
+            let cutoff = used[used.len() / 2];
+            entries.found.retain(|_, (_, used)| *used >= cutoff);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Pipeline<'a> {
+    stages: Vec<(Box<dyn CorrectionStage + 'a>, StageOptions)>,
+    budget: Option<Duration>,
+    cache: Option<Arc<SentenceCache>>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a stage; it runs after every stage added before it.
+    pub fn with_stage(mut self, stage: impl CorrectionStage + 'a) -> Self {
+        self.stages.push((Box::new(stage), StageOptions::default()));
+        self
+    }
+
+    /// Apply per-stage options by stage name. Options for a built-in stage
+    /// that is not in this pipeline are ignored; any other name is an error.
+    pub fn with_options(mut self, options: &HashMap<String, StageOptions>) -> Result<Self> {
+        for (name, stage_options) in options {
+            stage_options.validate(name)?;
+            match self.stages.iter_mut().find(|(stage, _)| stage.name() == name) {
+                Some((_, current)) => *current = stage_options.clone(),
+                None if BUILTIN_STAGES.contains(&name.as_str()) => {}
+                None => return Err(E::msg(format!("Options given for unknown stage '{}'", name))),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Stop starting stage work once `budget` has passed since the run began.
+    /// Work in flight at that point is abandoned; model work still queued for
+    /// it is skipped, while a decode already running finishes.
+    pub fn with_budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Reuse sentence-scoped stages' corrections for sentences they have seen before.
+    pub fn with_cache(mut self, cache: Arc<SentenceCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Built-in stages by name, in the given order. Model stages are left out
+    /// when there is no `corrector`; an unknown name is an error.
+    pub fn from_names(names: &[String], corrector: Option<&'a Corrector>) -> Result<Self> {
+        let mut pipeline = Self::new();
+        for (index, name) in names.iter().enumerate() {
+            if names[..index].contains(name) {
+                return Err(E::msg(format!("Correction stage '{}' is listed twice", name)));
+            }
+            pipeline = match (name.as_str(), corrector) {
+                (HARPER, _) => pipeline.with_stage(HarperStage),
+                (GRAMFORMER, Some(corrector)) => pipeline.with_stage(GramformerStage(corrector)),
+                (FLAN_T5, Some(corrector)) => pipeline.with_stage(FlanT5Stage(corrector)),
+                (GRAMFORMER | FLAN_T5, None) => pipeline,
+                _ => return Err(E::msg(format!(
+                    "Unknown correction stage '{}' (expected one of {})",
+                    name, BUILTIN_STAGES.join(", ")
+                ))),
+            };
+        }
+        Ok(pipeline)
+    }
+
+    /// This pipeline without the stages named in `names`.
+    pub fn without(mut self, names: &[&str]) -> Self {
+        self.stages.retain(|(stage, _)| !names.contains(&stage.name()));
+        self
+    }
+
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|(stage, _)| stage.name()).collect()
+    }
+
+    /// Run every stage over `text` in order. A stage that fails is logged and
+    /// contributes nothing; later stages still run. Once the budget is spent
+    /// the remaining stages are skipped.
+    pub async fn run(&self, text: &str, context: &StageContext<'_>) -> PipelineRun {
+        self.run_with(text, context, |_, _| {}).await
+    }
+
+    /// [`Pipeline::run`], handing each stage's run and corrections to
+    /// `on_stage` as soon as the stage finishes.
+    pub async fn run_with(
+        &self,
+        text: &str,
+        context: &StageContext<'_>,
+        mut on_stage: impl FnMut(&StageRun, &[GrammarCorrection]),
+    ) -> PipelineRun {
+        let started = Instant::now();
+        let deadline = self.budget.map(|budget| started + budget);
+        let mut run = PipelineRun::default();
+        let mut current = text.to_string();
+        // maps[i] takes stage i's output back to its input
+        let mut maps: Vec<EditMap> = Vec::new();
+
+        for (stage, options) in &self.stages {
+            let stage_started = Instant::now();
+            let generation = options.generation(context.generation);
+            let context = StageContext { generation: &generation, ..*context };
+
+            let (mut found, status) = if deadline.is_some_and(|deadline| stage_started >= deadline) {
+                (Vec::new(), StageStatus::Skipped)
+            } else {
+                match stage.scope() {
+                    StageScope::Document => {
+                        let input = StageInput { text: &current, prior: &run.corrections };
+                        match within(deadline, stage.correct(input, &context)).await {
+                            Some(Ok(found)) => (found, StageStatus::Completed),
+                            Some(Err(e)) => {
+                                warn!("Stage {} failed: {}", stage.name(), e);
+                                (Vec::new(), StageStatus::Failed)
+                            }
+                            None => (Vec::new(), StageStatus::Skipped),
+                        }
+                    }
+                    StageScope::Sentence => {
+                        let cache = self.cache.as_deref();
+                        run_by_sentence(stage.as_ref(), &current, &run.corrections, &context, deadline, cache).await
+                    }
+                }
+            };
+
+            // Markup inside a prose block is not the chunk-level stages' to change
+            if stage.scope() == StageScope::Sentence {
+                if let Some(prose) = context.harper.prose_map(&current) {
+                    found.retain(|c| prose.is_prose(c.offset..c.offset + c.length));
+                }
+            }
+
+            // Custom words are right by definition, whatever a model thinks
+            let words = context.harper.custom_words();
+            found.retain(|c| !rewrites_custom_word(words, &c.original_text, &c.primary_suggestion));
+
+            if let Some(floor) = options.min_confidence {
+                found.retain(|correction| correction.confidence >= floor);
+            }
+
+            // Corrections without suggestions only flag; nothing to apply
+            let edits: Vec<Edit> = found.iter()
+                .filter(|c| !c.suggestions.is_empty())
+                .map(|c| Edit::new(c.offset, c.length, c.primary_suggestion.clone()))
+                .collect();
+            let (corrected, map) = apply_edits(&current, &edits);
+
+            let first = run.corrections.len();
+            for mut correction in found {
+                for map in maps.iter().rev() {
+                    relocate(&mut correction, map);
+                }
+                correction.original_text = text[correction.offset..correction.offset + correction.length].to_string();
+                correction.source_stage = stage.name().to_string();
+                correction.id = format!("{}_{}", stage.name(), run.corrections.len() + 1);
+                run.corrections.push(correction);
+            }
+
+            maps.push(map);
+            current = corrected;
+            run.stages.push(StageRun {
+                name: stage.name().to_string(),
+                description: stage.description().to_string(),
+                text: current.clone(),
+                status,
+                elapsed: stage_started.elapsed(),
+            });
+            on_stage(&run.stages[run.stages.len() - 1], &run.corrections[first..]);
+        }
+
+        run
+    }
+}
+
+/// `future`'s output, or `None` if `deadline` passes first.
+async fn within<T>(deadline: Option<Instant>, future: impl std::future::Future<Output = T>) -> Option<T> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Run a sentence-scoped stage chunk by chunk; offsets come back relative to `text`.
+/// Chunks still waiting when the deadline passes are left uncorrected; chunks
+/// in `cache` are not run again.
+async fn run_by_sentence(
+    stage: &(dyn CorrectionStage + '_),
+    text: &str,
+    prior: &[GrammarCorrection],
+    context: &StageContext<'_>,
+    deadline: Option<Instant>,
+    cache: Option<&SentenceCache>,
+) -> (Vec<GrammarCorrection>, StageStatus) {
+    let mut found = Vec::new();
+    let mut ran = false;
+    let mut failed = false;
+
+    // The models only take a few hundred tokens, so correct sentence by sentence
+    for chunk in context.harper.sentence_chunks(text) {
+        let (lead, body) = trim_chunk(&text[chunk.clone()]);
+        if body.is_empty() {
+            continue;
+        }
+
+        let result = match cache.and_then(|cache| cache.get(stage.name(), body)) {
+            Some(corrections) => Some(Ok(corrections)),
+            None => {
+                let result = within(deadline, stage.correct(StageInput { text: body, prior }, context)).await;
+                if let (Some(cache), Some(Ok(corrections))) = (cache, &result) {
+                    cache.insert(stage.name(), body, corrections);
+                }
+                result
+            }
+        };
+        match result {
+            Some(Ok(corrections)) => {
+                ran = true;
+                found.extend(corrections.into_iter().map(|mut correction| {
+                    correction.offset += chunk.start + lead;
+                    correction
+                }));
+            }
+            Some(Err(e)) => {
+                failed = true;
+                warn!("Stage {} failed on a chunk: {}", stage.name(), e);
+            }
+            None => {
+                let status = if ran { StageStatus::Partial } else { StageStatus::Skipped };
+                return (found, status);
+            }
+        }
+    }
+
+    // Only failed when every chunk that needed work errored; text without sentences needs none
+    (found, if failed && !ran { StageStatus::Failed } else { StageStatus::Completed })
+}
+
+/// Split off surrounding whitespace, which the models would drop anyway.
+/// Returns the byte offset of the trimmed body and the body itself.
+fn trim_chunk(chunk: &str) -> (usize, &str) {
+    let body = chunk.trim_start();
+    let lead = chunk.len() - body.len();
+    (lead, body.trim_end())
+}
+
+/// Move a correction's span from edited text back to the text `map` was built from.
+fn relocate(correction: &mut GrammarCorrection, map: &EditMap) {
+    let (start, end) = map.span_to_source(correction.offset, correction.offset + correction.length);
+    correction.offset = start;
+    correction.length = end - start;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(stage: &str, num_beams: usize) -> HashMap<String, StageOptions> {
+        let options = StageOptions { num_beams: Some(num_beams), ..StageOptions::default() };
+        HashMap::from([(stage.to_string(), options)])
+    }
+
+    #[test]
+    fn stage_beams_up_to_the_cap_are_accepted() {
+        let pipeline = Pipeline::new().with_stage(HarperStage);
+        assert!(pipeline.with_options(&options(HARPER, MAX_NUM_BEAMS)).is_ok());
+    }
+
+    #[test]
+    fn stage_beams_over_the_cap_are_rejected() {
+        let pipeline = Pipeline::new().with_stage(HarperStage);
+        let error = pipeline.with_options(&options(HARPER, MAX_NUM_BEAMS + 1)).err().expect("too many beams");
+        assert!(error.to_string().contains("num_beams"));
+        // Also for a built-in stage the pipeline does not run
+        assert!(Pipeline::new().with_options(&options(FLAN_T5, 10_000)).is_err());
+    }
+}
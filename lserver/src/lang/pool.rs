@@ -0,0 +1,157 @@
+// lang/pool.rs - Dedicated inference threads, each owning one model instance
+//
+// ONNX inference is CPU-bound and blocking, so it must not run on the tokio
+// workers. Each pool owns N copies of a model (N sets of sessions) on N
+// threads; requests queue up and are served by whichever thread is free.
+use anyhow::{Error as E, Result};
+use serde::Serialize;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tracing::{error, info};
+
+type Job<T> = Box<dyn FnOnce(&T) + Send>;
+
+/// Snapshot of a pool's load, reported by `/api/info`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub model: String,
+    /// Model instances, i.e. requests that can run at the same time
+    pub sessions: usize,
+    /// Requests currently running
+    pub busy: usize,
+    /// Requests waiting for a free session
+    pub queued: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+}
+
+pub struct InferencePool<T> {
+    name: String,
+    sessions: usize,
+    sender: Sender<Job<T>>,
+    counters: Arc<Counters>,
+}
+
+impl<T: Send + 'static> InferencePool<T> {
+    /// Start one worker thread per instance.
+    pub fn new(name: &str, instances: Vec<T>) -> Result<Self> {
+        let (sender, receiver) = channel::<Job<T>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let counters = Arc::new(Counters::default());
+        let sessions = instances.len();
+
+        for (index, model) in instances.into_iter().enumerate() {
+            let receiver = receiver.clone();
+            let counters = counters.clone();
+            std::thread::Builder::new()
+                .name(format!("{}-{}", name, index))
+                .spawn(move || worker(model, receiver, counters))?;
+        }
+
+        info!("Started {} inference worker(s) for {}", sessions, name);
+        Ok(Self {
+            name: name.to_string(),
+            sessions,
+            sender,
+            counters,
+        })
+    }
+
+    /// Run `job` on the next free instance and wait for its result without
+    /// blocking the async runtime. If the caller stops waiting, e.g. when its
+    /// time budget runs out, a job that has not started yet is skipped.
+    pub async fn run<R, F>(&self, job: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&T) -> Result<R> + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        self.counters.queued.fetch_add(1, Ordering::SeqCst);
+
+        let job: Job<T> = Box::new(move |model| {
+            if !reply.is_closed() {
+                let _ = reply.send(job(model));
+            }
+        });
+        if self.sender.send(job).is_err() {
+            self.counters.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(E::msg(format!("{} inference workers have stopped", self.name)));
+        }
+
+        result
+            .await
+            .map_err(|_| E::msg(format!("{} inference failed", self.name)))?
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            model: self.name.clone(),
+            sessions: self.sessions,
+            busy: self.counters.busy.load(Ordering::SeqCst),
+            queued: self.counters.queued.load(Ordering::SeqCst),
+        }
+    }
+}
+
+fn worker<T>(model: T, receiver: Arc<Mutex<Receiver<Job<T>>>>, counters: Arc<Counters>) {
+    loop {
+        // The lock is only held while waiting, so idle workers take turns receiving
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        counters.queued.fetch_sub(1, Ordering::SeqCst);
+        counters.busy.fetch_add(1, Ordering::SeqCst);
+        // A panicking job drops its reply channel, which the caller sees as an error;
+        // the worker and its sessions stay available
+        if catch_unwind(AssertUnwindSafe(|| job(&model))).is_err() {
+            error!("Inference job panicked on {:?}", std::thread::current().name());
+        }
+        counters.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn jobs_nobody_waits_for_are_skipped() {
+        let pool = InferencePool::new("test", vec![()]).unwrap();
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let ran = Arc::new(AtomicBool::new(false));
+
+        // Hold the only worker until the second job has been abandoned
+        let first = pool.run(move |_| {
+            blocked.recv().ok();
+            Ok(())
+        });
+        let abandoned = {
+            let ran = ran.clone();
+            pool.run(move |_| {
+                ran.store(true, Ordering::SeqCst);
+                Ok(())
+            })
+        };
+        let (first, abandoned) = tokio::join!(first, async {
+            let abandoned = tokio::time::timeout(Duration::from_millis(20), abandoned).await;
+            release.send(()).unwrap();
+            abandoned
+        });
+        assert!(abandoned.is_err());
+        first.unwrap();
+        // The worker takes jobs in order, so this one runs after the abandoned one
+        assert_eq!(pool.run(|_| Ok(7)).await.unwrap(), 7);
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,260 @@
+// lang/rules.rs - Enabling, disabling and tuning individual Harper rules
+//
+// Rules are keyed by Harper's rule names ("SpellCheck", "LongSentences", ...).
+// A setting is either a plain switch or a table of options:
+//
+//   [rules]
+//   LongSentences = false
+//   SpellCheck = { severity = "important", auto_apply = false }
+//
+// Server defaults come from the config file; a request's rules are layered on
+// top, option by option.
+use anyhow::{Error as E, Result};
+use harper_core::linting::LintGroupConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::lang::lint::GrammarCorrection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Critical,
+    Important,
+    Enhancement,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Critical => "critical",
+            Severity::Important => "important",
+            Severity::Enhancement => "enhancement",
+        }
+    }
+}
+
+/// Settings for one rule; unset fields keep Harper's curated behaviour.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RuleSetting")]
+pub struct RuleOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Severity reported for this rule's corrections
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    /// Whether clients may apply this rule's corrections without asking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_apply: Option<bool>,
+}
+
+/// What a rule may be set to: `false`, or `{ enabled = true, severity = "enhancement" }`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RuleSetting {
+    Enabled(bool),
+    Options(RuleTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleTable {
+    #[serde(default)]
+    enabled: Option<bool>,
+    #[serde(default)]
+    severity: Option<Severity>,
+    #[serde(default)]
+    auto_apply: Option<bool>,
+}
+
+impl From<RuleSetting> for RuleOptions {
+    fn from(setting: RuleSetting) -> Self {
+        match setting {
+            RuleSetting::Enabled(enabled) => Self { enabled: Some(enabled), ..Self::default() },
+            RuleSetting::Options(table) => Self {
+                enabled: table.enabled,
+                severity: table.severity,
+                auto_apply: table.auto_apply,
+            },
+        }
+    }
+}
+
+impl RuleOptions {
+    /// `self` with every option `other` sets taking precedence.
+    fn merged(&self, other: &Self) -> Self {
+        Self {
+            enabled: other.enabled.or(self.enabled),
+            severity: other.severity.or(self.severity),
+            auto_apply: other.auto_apply.or(self.auto_apply),
+        }
+    }
+
+    /// Apply the presentation overrides to one of this rule's corrections.
+    pub fn apply(&self, correction: &mut GrammarCorrection) {
+        if let Some(severity) = self.severity {
+            correction.severity = severity.as_str().to_string();
+        }
+        if let Some(auto_apply) = self.auto_apply {
+            correction.auto_apply = auto_apply;
+        }
+    }
+}
+
+/// Settings by rule name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RuleConfig {
+    rules: BTreeMap<String, RuleOptions>,
+}
+
+impl RuleConfig {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.rules.keys().map(String::as_str)
+    }
+
+    pub fn options(&self, rule: &str) -> RuleOptions {
+        self.rules.get(rule).cloned().unwrap_or_default()
+    }
+
+    /// These settings with `overrides` layered on top, option by option.
+    pub fn merged(&self, overrides: &RuleConfig) -> RuleConfig {
+        let mut rules = self.rules.clone();
+        for (name, options) in &overrides.rules {
+            let merged = rules.get(name).cloned().unwrap_or_default().merged(options);
+            rules.insert(name.clone(), merged);
+        }
+        RuleConfig { rules }
+    }
+
+    /// Switch rules on or off in a curated Harper config.
+    pub fn apply_to(&self, config: &mut LintGroupConfig) {
+        for (name, options) in &self.rules {
+            if let Some(enabled) = options.enabled {
+                config.set_rule_enabled(name, enabled);
+            }
+        }
+    }
+
+    /// Every rule named must be one of `known`.
+    pub fn check_names(&self, known: impl Fn(&str) -> bool) -> Result<()> {
+        match self.names().find(|name| !known(name)) {
+            Some(name) => Err(E::msg(format!("Unknown Harper rule '{}' (GET /api/rules lists them)", name))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A rule as listed to clients.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleInfo {
+    pub name: String,
+    pub description: String,
+    /// Whether the rule runs when a request does not mention it
+    pub enabled: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(toml: &str) -> RuleConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn correction() -> GrammarCorrection {
+        GrammarCorrection {
+            id: "harper_1".to_string(),
+            category: "correctness".to_string(),
+            subcategory: "spelling".to_string(),
+            severity: "critical".to_string(),
+            confidence: 0.9,
+            visual_treatment: "highlight".to_string(),
+            offset: 0,
+            length: 3,
+            original_text: "teh".to_string(),
+            suggestions: vec!["the".to_string()],
+            primary_suggestion: "the".to_string(),
+            explanation: String::new(),
+            source_stage: "harper".to_string(),
+            auto_apply: true,
+            rule: Some("SpellCheck".to_string()),
+        }
+    }
+
+    #[test]
+    fn settings_are_switches_or_tables() {
+        let config = rules(r#"
+            LongSentences = false
+            SpellCheck = { severity = "important", auto_apply = false }
+        "#);
+        assert_eq!(config.options("LongSentences"), RuleOptions { enabled: Some(false), ..RuleOptions::default() });
+        assert_eq!(config.options("SpellCheck"), RuleOptions {
+            enabled: None,
+            severity: Some(Severity::Important),
+            auto_apply: Some(false),
+        });
+        assert_eq!(config.options("Unmentioned"), RuleOptions::default());
+
+        assert!(toml::from_str::<RuleConfig>("SpellCheck = { colour = \"red\" }").is_err());
+        assert!(toml::from_str::<RuleConfig>("SpellCheck = { severity = \"fatal\" }").is_err());
+    }
+
+    #[test]
+    fn overrides_layer_option_by_option() {
+        let defaults = rules(r#"
+            SpellCheck = { severity = "important", auto_apply = false }
+            LongSentences = false
+        "#);
+        let request = rules(r#"
+            SpellCheck = { auto_apply = true }
+            LongSentences = true
+            RepeatedWords = false
+        "#);
+        let merged = defaults.merged(&request);
+
+        assert_eq!(merged.options("SpellCheck"), RuleOptions {
+            enabled: None,
+            severity: Some(Severity::Important),
+            auto_apply: Some(true),
+        });
+        assert_eq!(merged.options("LongSentences").enabled, Some(true));
+        assert_eq!(merged.options("RepeatedWords").enabled, Some(false));
+        assert_eq!(defaults.merged(&RuleConfig::default()), defaults);
+    }
+
+    #[test]
+    fn options_change_severity_and_auto_apply() {
+        let mut fixed = correction();
+        rules(r#"SpellCheck = { severity = "enhancement", auto_apply = false }"#).options("SpellCheck").apply(&mut fixed);
+        assert_eq!((fixed.severity.as_str(), fixed.auto_apply), ("enhancement", false));
+
+        let mut untouched = correction();
+        rules("SpellCheck = true").options("SpellCheck").apply(&mut untouched);
+        assert_eq!((untouched.severity.as_str(), untouched.auto_apply), ("critical", true));
+    }
+
+    #[test]
+    fn switches_reach_the_harper_config() {
+        let mut config = LintGroupConfig::new_curated();
+        assert!(config.is_rule_enabled("SpellCheck"));
+        rules(r#"
+            SpellCheck = false
+            LongSentences = { severity = "important" }
+        "#).apply_to(&mut config);
+
+        assert!(!config.is_rule_enabled("SpellCheck"));
+        assert_eq!(config.is_rule_enabled("LongSentences"), LintGroupConfig::new_curated().is_rule_enabled("LongSentences"));
+    }
+
+    #[test]
+    fn unknown_rule_names_are_errors() {
+        let config = rules("SpellCheck = false\nSpelCheck = false");
+        let error = config.check_names(|name| name == "SpellCheck").unwrap_err();
+        assert!(error.to_string().contains("'SpelCheck'"));
+        assert!(config.check_names(|_| true).is_ok());
+    }
+}
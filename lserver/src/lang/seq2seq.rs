@@ -0,0 +1,471 @@
+// lang/seq2seq.rs - Encoder/decoder ONNX graphs with optional key/value caching
+//
+// Supports the layouts produced by HF optimum and the older single-graph export:
+//
+//   encoder_model.onnx + decoder_model_merged.onnx              (cached, preferred)
+//   encoder_model.onnx + decoder_model.onnx
+//                      + decoder_with_past_model.onnx           (cached)
+//   encoder_model.onnx + decoder_model.onnx                     (uncached)
+//   model.onnx (input_ids + decoder_input_ids -> logits)         (uncached, re-encodes)
+//
+// With a cache each step feeds only the newest token, so generation is linear
+// in the output length instead of quadratic. A decoding can hold several
+// inputs at once (right-padded, masked); all rows then advance together.
+use anyhow::{Error as E, Result};
+use ort::session::{builder::GraphOptimizationLevel, Session, SessionInputValue};
+use ort::value::{DynValue, Tensor, ValueType};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tracing::info;
+use crate::lang::manifest::TensorNames;
+
+enum DecoderGraph {
+    /// Encoder and decoder in one graph; the whole input is re-encoded every step
+    Monolithic(RwLock<Session>),
+    /// Separate decoder without cache inputs; the whole prefix is re-decoded every step
+    Uncached(RwLock<Session>),
+    /// `decoder_model.onnx` for the first step, `decoder_with_past_model.onnx` afterwards
+    WithPast {
+        first: RwLock<Session>,
+        with_past: RwLock<Session>,
+    },
+    /// `decoder_model_merged.onnx`, switching branches with `use_cache_branch`
+    Merged(RwLock<Session>),
+}
+
+pub struct Seq2SeqModel {
+    encoder: Option<RwLock<Session>>,
+    decoder: DecoderGraph,
+    names: TensorNames,
+}
+
+fn load_session(path: &Path) -> Result<Session> {
+    Ok(Session::builder()?
+        .with_optimization_level(GraphOptimizationLevel::Level3)?
+        .commit_from_file(path)?)
+}
+
+/// Fail with the names a graph does declare when it lacks `expected`, which
+/// usually means the manifest's tensor names do not match the export.
+fn expect_name<'a>(path: &Path, kind: &str, declared: impl Iterator<Item = &'a String>, expected: &str) -> Result<()> {
+    let declared: Vec<&str> = declared.map(String::as_str).collect();
+    if declared.contains(&expected) {
+        return Ok(());
+    }
+    Err(E::msg(format!(
+        "{} has no {} named '{}' (declares {:?}); check the tensor names in the model manifest",
+        path.display(), kind, expected, declared
+    )))
+}
+
+impl DecoderGraph {
+    fn sessions(&self) -> Vec<&RwLock<Session>> {
+        match self {
+            DecoderGraph::Monolithic(session) | DecoderGraph::Uncached(session) | DecoderGraph::Merged(session) => vec![session],
+            DecoderGraph::WithPast { first, with_past } => vec![first, with_past],
+        }
+    }
+}
+
+impl Seq2SeqModel {
+    /// Whether `dir` holds any layout [`Self::load`] understands.
+    pub fn exists_in(dir: &Path) -> bool {
+        dir.join("encoder_model.onnx").exists() || dir.join("model.onnx").exists()
+    }
+
+    /// Load the best available layout from `dir`, whose graphs use `names`.
+    pub fn load(dir: &Path, names: &TensorNames) -> Result<Self> {
+        let encoder_file = dir.join("encoder_model.onnx");
+
+        if !encoder_file.exists() {
+            let model_file = dir.join("model.onnx");
+            if !model_file.exists() {
+                return Err(E::msg(format!("No ONNX model found in {}", dir.display())));
+            }
+            let session = load_session(&model_file)?;
+            expect_name(&model_file, "input", session.inputs.iter().map(|i| &i.name), &names.input_ids)?;
+            expect_name(&model_file, "output", session.outputs.iter().map(|o| &o.name), &names.logits)?;
+            return Ok(Self {
+                encoder: None,
+                decoder: DecoderGraph::Monolithic(RwLock::new(session)),
+                names: names.clone(),
+            });
+        }
+
+        let encoder = load_session(&encoder_file)?;
+        expect_name(&encoder_file, "input", encoder.inputs.iter().map(|i| &i.name), &names.input_ids)?;
+        expect_name(&encoder_file, "output", encoder.outputs.iter().map(|o| &o.name), &names.last_hidden_state)?;
+        let merged_file = dir.join("decoder_model_merged.onnx");
+        let decoder_file = dir.join("decoder_model.onnx");
+        let with_past_file = dir.join("decoder_with_past_model.onnx");
+
+        let decoder = if merged_file.exists() {
+            DecoderGraph::Merged(RwLock::new(load_session(&merged_file)?))
+        } else if decoder_file.exists() && with_past_file.exists() {
+            DecoderGraph::WithPast {
+                first: RwLock::new(load_session(&decoder_file)?),
+                with_past: RwLock::new(load_session(&with_past_file)?),
+            }
+        } else if decoder_file.exists() {
+            DecoderGraph::Uncached(RwLock::new(load_session(&decoder_file)?))
+        } else {
+            return Err(E::msg(format!("No decoder ONNX model found in {}", dir.display())));
+        };
+
+        for session in decoder.sessions() {
+            let session = session.read().unwrap();
+            expect_name(dir, "decoder output", session.outputs.iter().map(|o| &o.name), &names.logits)?;
+        }
+
+        let model = Self {
+            encoder: Some(RwLock::new(encoder)),
+            decoder,
+            names: names.clone(),
+        };
+        info!("Loaded seq2seq model from {} ({})", dir.display(), model.decoding_mode());
+        Ok(model)
+    }
+
+    /// Human-readable description of how decoding runs, for logs and `/api/info`.
+    pub fn decoding_mode(&self) -> &'static str {
+        match self.decoder {
+            DecoderGraph::Monolithic(_) => "single graph, no cache",
+            DecoderGraph::Uncached(_) => "split encoder/decoder, no cache",
+            DecoderGraph::WithPast { .. } => "split encoder/decoder with past key/values",
+            DecoderGraph::Merged(_) => "merged decoder with past key/values",
+        }
+    }
+
+    /// Encode `input_ids` once and return a decoding state ready for [`Decoding::step`].
+    pub fn start(&self, input_ids: &[i64], pad_token: i64) -> Result<Decoding<'_>> {
+        self.start_batch(&[input_ids.to_vec()], pad_token)
+    }
+
+    /// Encode several inputs as one batch, right-padding them with `pad_token`,
+    /// and return a state ready for [`Decoding::step_batch`].
+    pub fn start_batch(&self, inputs: &[Vec<i64>], pad_token: i64) -> Result<Decoding<'_>> {
+        if inputs.is_empty() {
+            return Err(E::msg("Cannot decode an empty batch"));
+        }
+        let width = inputs.iter().map(|ids| ids.len()).max().unwrap_or(0);
+        let mut input_ids = Vec::with_capacity(inputs.len() * width);
+        let mut attention_mask = Vec::with_capacity(inputs.len() * width);
+        for ids in inputs {
+            input_ids.extend_from_slice(ids);
+            input_ids.resize(input_ids.len() + width - ids.len(), pad_token);
+            attention_mask.extend(std::iter::repeat_n(1i64, ids.len()));
+            attention_mask.extend(std::iter::repeat_n(0i64, width - ids.len()));
+        }
+        let rows = inputs.len();
+
+        let names = &self.names;
+        let encoder_hidden_states = match &self.encoder {
+            Some(encoder) => {
+                let mut encoder = encoder.write().unwrap();
+                let mut outputs = encoder.run(ort::inputs![
+                    names.input_ids.as_str() => Tensor::from_array(([rows, width], input_ids.clone().into_boxed_slice()))?,
+                    names.attention_mask.as_str() => Tensor::from_array(([rows, width], attention_mask.clone().into_boxed_slice()))?
+                ])?;
+                Some(Arc::new(outputs
+                    .remove(&names.last_hidden_state)
+                    .ok_or_else(|| E::msg(format!("Encoder produced no {}", names.last_hidden_state)))?))
+            }
+            None => None,
+        };
+
+        Ok(Decoding {
+            model: self,
+            rows,
+            input_ids,
+            attention_mask,
+            encoder_hidden_states,
+            cache: HashMap::new(),
+            tokens: Vec::new(),
+        })
+    }
+}
+
+type Inputs<'a> = Vec<(Cow<'a, str>, SessionInputValue<'a>)>;
+
+/// `ids` (row-major) as a `[rows, ids.len() / rows]` tensor.
+fn ids_tensor(rows: usize, ids: &[i64]) -> Result<SessionInputValue<'static>> {
+    Ok(Tensor::from_array(([rows, ids.len() / rows], ids.to_vec().into_boxed_slice()))?.into_dyn().into())
+}
+
+/// Zero-length past key/values for the first (non-cache) branch of a merged decoder.
+fn empty_past_inputs(session: &RwLock<Session>, rows: usize, past_prefix: &str) -> Result<Inputs<'static>> {
+    let session = session.read().unwrap();
+    let mut inputs = Vec::new();
+
+    for input in session.inputs.iter().filter(|i| i.name.starts_with(past_prefix)) {
+        let ValueType::Tensor { shape, .. } = &input.input_type else {
+            return Err(E::msg(format!("{} is not a tensor input", input.name)));
+        };
+        // [batch, heads, sequence, head_dim]: heads and head_dim are fixed by the export
+        let dims: Vec<i64> = shape
+            .iter()
+            .enumerate()
+            .map(|(axis, &dim)| match axis {
+                0 => rows as i64,
+                2 => 0,
+                _ => dim,
+            })
+            .collect();
+        if dims.iter().any(|&dim| dim < 0) {
+            return Err(E::msg(format!("Cannot infer the cache shape of {} ({:?})", input.name, shape)));
+        }
+
+        let empty = Tensor::<f32>::from_array((dims, Vec::<f32>::new().into_boxed_slice()))?;
+        inputs.push((Cow::Owned(input.name.clone()), empty.into_dyn().into()));
+    }
+    Ok(inputs)
+}
+
+/// Keep the first `len` positions of a `[batch, heads, sequence, head_dim]` cache tensor.
+fn truncate_sequence(value: &DynValue, len: usize) -> Result<DynValue> {
+    let (shape, data) = value.try_extract_tensor::<f32>()?;
+    let dims = shape.to_vec();
+    if dims.len() != 4 || dims[2] as usize <= len {
+        return copy_tensor(value);
+    }
+
+    let (sequence, head_dim) = (dims[2] as usize, dims[3] as usize);
+    let kept: Vec<f32> = data
+        .chunks(sequence * head_dim)
+        .flat_map(|head| &head[..len * head_dim])
+        .cloned()
+        .collect();
+
+    let mut new_dims = dims;
+    new_dims[2] = len as i64;
+    Ok(Tensor::from_array((new_dims, kept.into_boxed_slice()))?.into_dyn())
+}
+
+fn copy_tensor(value: &DynValue) -> Result<DynValue> {
+    let (shape, data) = value.try_extract_tensor::<f32>()?;
+    Ok(Tensor::from_array((shape.to_vec(), data.to_vec().into_boxed_slice()))?.into_dyn())
+}
+
+struct DecoderOutput {
+    /// Logits for the last `positions` decoder positions of each row, row-major
+    logits: Vec<Vec<f32>>,
+    /// Every present key/value output, by output name
+    presents: Vec<(String, DynValue)>,
+}
+
+/// Run `session` with the subset of `inputs` it declares, keeping the logits
+/// of the last `last` positions.
+fn run_decoder(session: &RwLock<Session>, inputs: Inputs<'_>, last: usize, names: &TensorNames) -> Result<DecoderOutput> {
+    let mut session = session.write().unwrap();
+    let declared: Vec<String> = session.inputs.iter().map(|input| input.name.clone()).collect();
+    let inputs: Inputs<'_> = inputs
+        .into_iter()
+        .filter(|(name, _)| declared.iter().any(|declared| declared == name))
+        .collect();
+
+    let mut outputs = session.run(inputs)?;
+
+    let (shape, data) = outputs[names.logits.as_str()].try_extract_tensor::<f32>()?;
+    let (rows, positions, vocab_size) = (shape[0] as usize, shape[1] as usize, shape[2] as usize);
+    let last = last.min(positions);
+    let logits = (0..rows)
+        .flat_map(|row| (positions - last..positions).map(move |position| (row, position)))
+        .map(|(row, position)| {
+            let start = (row * positions + position) * vocab_size;
+            data[start..start + vocab_size].to_vec()
+        })
+        .collect();
+
+    let present_names: Vec<String> = outputs
+        .keys()
+        .filter(|name| name.starts_with(&names.present_prefix))
+        .map(|name| name.to_string())
+        .collect();
+    let presents = present_names
+        .into_iter()
+        .filter_map(|name| outputs.remove(&name).map(|value| (name, value)))
+        .collect();
+
+    Ok(DecoderOutput { logits, presents })
+}
+
+/// One in-progress generation: the encoder output, the decoder tokens so far
+/// and, for cached graphs, the past key/values. Holds `rows` sequences that
+/// advance together.
+pub struct Decoding<'m> {
+    model: &'m Seq2SeqModel,
+    rows: usize,
+    /// Row-major `[rows, width]` encoder inputs, right-padded
+    input_ids: Vec<i64>,
+    attention_mask: Vec<i64>,
+    /// Shared between forks; it never changes during decoding
+    encoder_hidden_states: Option<Arc<DynValue>>,
+    /// Keyed by decoder input name (`past_key_values.N.{decoder,encoder}.{key,value}`)
+    cache: HashMap<String, DynValue>,
+    /// Row-major `[rows, steps]` decoder tokens fed so far
+    tokens: Vec<i64>,
+}
+
+impl Decoding<'_> {
+    /// Decoder tokens fed so far to the first row, starting with the decoder start token.
+    pub fn tokens(&self) -> Vec<i64> {
+        self.tokens.iter().step_by(self.rows).cloned().collect()
+    }
+
+    /// An independent copy of this state, e.g. for a new beam. The cached
+    /// key/values are copied; the encoder output is shared.
+    pub fn fork(&self) -> Result<Self> {
+        let cache = self
+            .cache
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), copy_tensor(value)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(Self {
+            model: self.model,
+            rows: self.rows,
+            input_ids: self.input_ids.clone(),
+            attention_mask: self.attention_mask.clone(),
+            encoder_hidden_states: self.encoder_hidden_states.clone(),
+            cache,
+            tokens: self.tokens.clone(),
+        })
+    }
+
+    /// Feed the next decoder token and return the logits for the token after it.
+    pub fn step(&mut self, token: i64) -> Result<Vec<f32>> {
+        Ok(self.step_batch(&[token])?.swap_remove(0))
+    }
+
+    /// Feed the next decoder token of every row; returns each row's logits
+    /// for the token after it.
+    pub fn step_batch(&mut self, next: &[i64]) -> Result<Vec<Vec<f32>>> {
+        if next.len() != self.rows {
+            return Err(E::msg(format!("Expected {} decoder tokens, got {}", self.rows, next.len())));
+        }
+        self.feed(next, 1)
+    }
+
+    /// Feed several tokens to a single-row decoding in one pass and return the
+    /// logits after each of them, e.g. to verify a speculative draft.
+    pub fn step_many(&mut self, tokens: &[i64]) -> Result<Vec<Vec<f32>>> {
+        if self.rows != 1 {
+            return Err(E::msg("Multi-token steps need a single-row decoding"));
+        }
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.feed(tokens, tokens.len())
+    }
+
+    /// Forget every decoder token after the first `len` (single-row decodings),
+    /// trimming the cached self-attention key/values to match.
+    pub fn rollback(&mut self, len: usize) -> Result<()> {
+        if self.rows != 1 {
+            return Err(E::msg("Rollback needs a single-row decoding"));
+        }
+        if len >= self.tokens.len() {
+            return Ok(());
+        }
+        self.tokens.truncate(len);
+
+        for (name, value) in self.cache.iter_mut() {
+            if name.contains(".decoder.") {
+                *value = truncate_sequence(value, len)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append `count` tokens per row (`next` is row-major) and run the decoder.
+    fn feed(&mut self, next: &[i64], count: usize) -> Result<Vec<Vec<f32>>> {
+        let steps = self.tokens.len() / self.rows;
+        let mut tokens = Vec::with_capacity(self.tokens.len() + next.len());
+        for (row, new) in next.chunks(count).enumerate() {
+            tokens.extend_from_slice(&self.tokens[row * steps..(row + 1) * steps]);
+            tokens.extend_from_slice(new);
+        }
+        self.tokens = tokens;
+        let model = self.model;
+        let names = &model.names;
+
+        let output = match &model.decoder {
+            DecoderGraph::Monolithic(session) => {
+                let inputs = vec![
+                    (Cow::Borrowed(names.input_ids.as_str()), ids_tensor(self.rows, &self.input_ids)?),
+                    (Cow::Borrowed(names.attention_mask.as_str()), ids_tensor(self.rows, &self.attention_mask)?),
+                    (Cow::Borrowed(names.decoder_input_ids.as_str()), ids_tensor(self.rows, &self.tokens)?),
+                ];
+                run_decoder(session, inputs, count, names)?
+            }
+            DecoderGraph::Uncached(session) => run_decoder(session, self.decoder_inputs(&self.tokens)?, count, names)?,
+            DecoderGraph::WithPast { first, with_past } => {
+                if self.cache.is_empty() {
+                    run_decoder(first, self.decoder_inputs(&self.tokens)?, count, names)?
+                } else {
+                    let mut inputs = self.decoder_inputs(next)?;
+                    inputs.extend(self.past_inputs());
+                    run_decoder(with_past, inputs, count, names)?
+                }
+            }
+            DecoderGraph::Merged(session) => {
+                let use_cache = !self.cache.is_empty();
+                let ids = if use_cache { next } else { &self.tokens[..] };
+                let mut inputs = self.decoder_inputs(ids)?;
+                inputs.push((
+                    Cow::Borrowed(names.use_cache_branch.as_str()),
+                    Tensor::from_array(([1], vec![use_cache].into_boxed_slice()))?.into_dyn().into(),
+                ));
+
+                if use_cache {
+                    inputs.extend(self.past_inputs());
+                } else {
+                    inputs.extend(empty_past_inputs(session, self.rows, &names.past_prefix)?);
+                }
+                run_decoder(session, inputs, count, names)?
+            }
+        };
+
+        if !matches!(model.decoder, DecoderGraph::Monolithic(_) | DecoderGraph::Uncached(_)) {
+            self.store_presents(output.presents);
+        }
+        Ok(output.logits)
+    }
+
+    /// `input_ids` (row-major) plus whichever encoder inputs the decoder declares.
+    fn decoder_inputs(&self, ids: &[i64]) -> Result<Inputs<'_>> {
+        let names = &self.model.names;
+        let mut inputs = vec![
+            (Cow::Borrowed(names.input_ids.as_str()), ids_tensor(self.rows, ids)?),
+            (Cow::Borrowed(names.encoder_attention_mask.as_str()), ids_tensor(self.rows, &self.attention_mask)?),
+        ];
+        if let Some(hidden_states) = &self.encoder_hidden_states {
+            inputs.push((Cow::Borrowed(names.encoder_hidden_states.as_str()), hidden_states.as_ref().into()));
+        }
+        Ok(inputs)
+    }
+
+    fn past_inputs(&self) -> Inputs<'_> {
+        self.cache
+            .iter()
+            .map(|(name, value)| (Cow::Borrowed(name.as_str()), value.into()))
+            .collect()
+    }
+
+    fn store_presents(&mut self, presents: Vec<(String, DynValue)>) {
+        let populated = !self.cache.is_empty();
+        let names = &self.model.names;
+
+        for (name, value) in presents {
+            let past_name = format!("{}{}", names.past_prefix, &name[names.present_prefix.len()..]);
+            // Cross-attention key/values depend only on the encoder output, so
+            // later steps (which may not even emit them) keep the first ones.
+            if populated && past_name.contains(".encoder.") {
+                continue;
+            }
+            self.cache.insert(past_name, value);
+        }
+    }
+}
@@ -3,22 +3,117 @@ use harper_core::{
     Dialect,
     Document,
     Lrc,
+    TokenStringExt,
     language_detection,
-    linting::{Lint, Linter, LintGroup},
-    spell::FstDictionary,
+    linting::{Lint, LintGroup},
+    spell::{FstDictionary, MergedDictionary, MutableDictionary},
+    DictWordMetadata,
 };
+use anyhow::Result;
+use std::ops::Range;
+use std::sync::Arc;
+use crate::lang::dictionary::WordList;
+use crate::lang::format::{ProseMap, TextFormat};
+use crate::lang::html::Html;
+use crate::lang::offsets::OffsetConverter;
+use crate::lang::rules::{RuleConfig, RuleInfo};
+
+/// Longest chunk handed to the seq2seq models in one piece, in bytes. Keeps a
+/// chunk comfortably under their 256-token input limit.
+const MAX_CHUNK_BYTES: usize = 800;
+
+/// A lint together with the name of the rule that raised it.
+pub struct RuleLint {
+    pub rule: String,
+    pub lint: Lint,
+}
 
 #[derive(Clone)]
 pub struct HarperConfig {
-    // Shared curated dictionary instance reused across requests
-    pub dictionary: Lrc<FstDictionary>,
+    // Shared curated dictionary, plus the request's custom words when it has any
+    pub dictionary: Lrc<MergedDictionary>,
+    // Custom words the AI stages must leave alone
+    words: Arc<WordList>,
+    // How request text is parsed
+    format: TextFormat,
+    // Server-wide rule settings; requests layer their own on top
+    rules: Arc<RuleConfig>,
+    // Every curated rule, sorted by name
+    catalog: Arc<Vec<RuleInfo>>,
 }
 
 impl HarperConfig {
     /// Construct a new HarperConfig with a curated dictionary.
     pub fn new() -> Self {
-        let dictionary = FstDictionary::curated();
-        Self { dictionary }
+        let dictionary = Lrc::new(merged_dictionary(&WordList::new()));
+        let group = LintGroup::new_curated(dictionary.clone(), Dialect::American);
+        let mut catalog: Vec<RuleInfo> = group
+            .all_descriptions()
+            .into_iter()
+            .map(|(name, description)| RuleInfo {
+                name: name.to_string(),
+                description: description.to_string(),
+                enabled: group.config.is_rule_enabled(name),
+            })
+            .collect();
+        catalog.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            dictionary,
+            words: Arc::new(WordList::new()),
+            format: TextFormat::Plain,
+            rules: Arc::new(RuleConfig::default()),
+            catalog: Arc::new(catalog),
+        }
+    }
+
+    /// Use `rules` as the server-wide defaults; every rule named must exist.
+    pub fn with_rules(mut self, rules: RuleConfig) -> Result<Self> {
+        self.check_rules(&rules)?;
+        for info in Arc::make_mut(&mut self.catalog) {
+            if let Some(enabled) = rules.options(&info.name).enabled {
+                info.enabled = enabled;
+            }
+        }
+        self.rules = Arc::new(rules);
+        Ok(self)
+    }
+
+    /// This config with `words` accepted as correctly spelled, on top of the curated dictionary.
+    pub fn with_words(&self, words: WordList) -> Self {
+        if words.is_empty() {
+            return self.clone();
+        }
+        Self {
+            dictionary: Lrc::new(merged_dictionary(&words)),
+            words: Arc::new(words),
+            ..self.clone()
+        }
+    }
+
+    /// This config reading text as `format`.
+    pub fn with_format(&self, format: TextFormat) -> Self {
+        Self { format, ..self.clone() }
+    }
+
+    /// Custom words from [`Self::with_words`].
+    pub fn custom_words(&self) -> &WordList {
+        &self.words
+    }
+
+    /// Every available rule with the server's default for it.
+    pub fn rules(&self) -> &[RuleInfo] {
+        &self.catalog
+    }
+
+    /// Server defaults with a request's `overrides` layered on top.
+    pub fn effective_rules(&self, overrides: &RuleConfig) -> RuleConfig {
+        self.rules.merged(overrides)
+    }
+
+    /// Reject settings for rules Harper does not have.
+    pub fn check_rules(&self, rules: &RuleConfig) -> Result<()> {
+        rules.check_names(|name| self.catalog.iter().any(|info| info.name == name))
     }
 
     /// Helper: create a plain English document backed by this state's dictionary.
@@ -26,23 +121,146 @@ impl HarperConfig {
         Document::new_plain_english(text, &self.dictionary)
     }
 
+    /// Helper: create a document in this config's format, spans in the original source.
+    pub fn create_doc(&self, text: &str) -> Document {
+        match self.format {
+            TextFormat::Plain => self.create_plain_doc(text),
+            TextFormat::Markdown => Document::new_markdown_default(text, &self.dictionary),
+            TextFormat::Html => Document::new(text, &Html, &self.dictionary),
+        }
+    }
+
+    /// Where the prose is, for formats with markup; `None` when it all is.
+    pub fn prose_map(&self, text: &str) -> Option<ProseMap> {
+        match self.format {
+            TextFormat::Plain => None,
+            TextFormat::Markdown => Some(ProseMap::markdown(text)),
+            TextFormat::Html => Some(ProseMap::html(text)),
+        }
+    }
+
+    /// Paragraphs of `text` in this config's format, see [`TextFormat::paragraphs`].
+    pub fn paragraphs(&self, text: &str) -> Vec<Range<usize>> {
+        self.format.paragraphs(text)
+    }
+
     /// Helper: run language detection on the given text using the shared dictionary.
     pub fn detect_language(&self, text: &str) -> bool {
-        let doc = self.create_plain_doc(text);
+        let doc = self.create_doc(text);
         language_detection::is_doc_likely_english(&doc, &self.dictionary)
     }
 
-    /// Helper: construct a curated linter and run it on the given text.
-    pub fn run_lints(&self, text: &str, dialect: Dialect) -> Vec<Lint> {
-        let doc = self.create_plain_doc(text);
+    /// Helper: construct a curated linter with `rules` (see [`Self::effective_rules`])
+    /// switched on or off and run it on the given text. Lints come back in text order.
+    pub fn run_lints(&self, text: &str, dialect: Dialect, rules: &RuleConfig) -> Vec<RuleLint> {
+        let doc = self.create_doc(text);
 
         let mut linter = LintGroup::new_curated(self.dictionary.clone(), dialect);
-        let mut lints = linter.lint(&doc);
+        rules.apply_to(&mut linter.config);
+        let mut lints = linter.organized_lints(&doc);
+        harper_core::remove_overlaps_map(&mut lints);
 
-        harper_core::remove_overlaps(&mut lints);
+        let mut lints: Vec<RuleLint> = lints
+            .into_iter()
+            .flat_map(|(rule, lints)| lints.into_iter().map(move |lint| RuleLint { rule: rule.clone(), lint }))
+            .collect();
+        lints.sort_by_key(|l| (l.lint.span.start, usize::MAX - l.lint.span.end));
         lints
     }
 
+    /// Helper: split the text into sentence-sized byte ranges for the AI stages.
+    /// For plain text the ranges cover the whole text in order; with markup
+    /// they cover only the prose blocks. Sentences too long for the models are
+    /// further split at whitespace.
+    pub fn sentence_chunks(&self, text: &str) -> Vec<Range<usize>> {
+        let Some(prose) = self.prose_map(text) else {
+            return self.plain_sentence_chunks(text);
+        };
+
+        prose
+            .segments()
+            .iter()
+            .flat_map(|segment| {
+                self.plain_sentence_chunks(&text[segment.clone()])
+                    .into_iter()
+                    .map(move |chunk| chunk.start + segment.start..chunk.end + segment.start)
+            })
+            .collect()
+    }
+
+    fn plain_sentence_chunks(&self, text: &str) -> Vec<Range<usize>> {
+        let doc = self.create_plain_doc(text);
+        let converter = OffsetConverter::new(text);
+
+        // Every sentence start after the first opens a new chunk
+        let mut starts: Vec<usize> = doc
+            .iter_sentences()
+            .filter_map(|sentence| sentence.first())
+            .map(|token| converter.byte_from_char(token.span.start))
+            .filter(|&start| start > 0 && start < text.len())
+            .collect();
+        starts.dedup();
+
+        let mut chunks = Vec::new();
+        let mut chunk_start = 0;
+        for end in starts.into_iter().chain(std::iter::once(text.len())) {
+            if end > chunk_start {
+                split_long_chunk(text, chunk_start..end, &mut chunks);
+                chunk_start = end;
+            }
+        }
+        chunks
+    }
+
+}
+
+/// The curated dictionary followed by `words`; curated metadata wins for words in both.
+fn merged_dictionary(words: &WordList) -> MergedDictionary {
+    let mut dictionary = MergedDictionary::new();
+    dictionary.add_dictionary(FstDictionary::curated());
+    if !words.is_empty() {
+        let mut custom = MutableDictionary::new();
+        for word in words {
+            custom.append_word_str(word, DictWordMetadata::default());
+        }
+        dictionary.add_dictionary(Lrc::new(custom));
+    }
+    dictionary
+}
+
+/// Push `range`, cut at whitespace into pieces of at most `MAX_CHUNK_BYTES`
+/// where possible.
+fn split_long_chunk(text: &str, range: Range<usize>, chunks: &mut Vec<Range<usize>>) {
+    let mut start = range.start;
+
+    while range.end - start > MAX_CHUNK_BYTES {
+        let mut limit = start + MAX_CHUNK_BYTES;
+        while !text.is_char_boundary(limit) {
+            limit -= 1;
+        }
+
+        // Break after the last whitespace that fits; a single huge word stays whole
+        let after_space = |(index, ch): (usize, char)| ch.is_whitespace().then_some(index + ch.len_utf8());
+        let cut = match text[start..limit].char_indices().rev().find_map(after_space) {
+            Some(end) => start + end,
+            _ => match text[limit..range.end].char_indices().find_map(after_space) {
+                Some(end) => limit + end,
+                None => break,
+            },
+        };
+        chunks.push(start..cut);
+        start = cut;
+    }
+
+    if start < range.end {
+        chunks.push(start..range.end);
+    }
+}
+
+impl Default for HarperConfig {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // Optional, but handy for logging / debugging
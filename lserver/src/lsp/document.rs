@@ -0,0 +1,139 @@
+// lsp/document.rs - Open document text and LSP position mapping
+use crate::lsp::protocol::{Position, TextDocumentContentChangeEvent};
+
+/// An open editor buffer. LSP positions count UTF-16 code units per line.
+#[derive(Debug, Clone)]
+pub struct TextDocument {
+    pub text: String,
+    pub version: i32,
+    pub language_id: String,
+}
+
+impl TextDocument {
+    pub fn new(text: String, version: i32, language_id: String) -> Self {
+        Self { text, version, language_id }
+    }
+
+    /// Apply one `didChange` content change; a change without a range replaces everything.
+    pub fn apply_change(&mut self, change: &TextDocumentContentChangeEvent) {
+        match change.range {
+            Some(range) => {
+                let start = self.offset_at(range.start);
+                let end = self.offset_at(range.end).max(start);
+                self.text.replace_range(start..end, &change.text);
+            }
+            None => self.text = change.text.clone(),
+        }
+    }
+
+    /// Byte offset for an LSP position, clamped to the line and document end.
+    pub fn offset_at(&self, position: Position) -> usize {
+        let mut line_start = 0;
+        for _ in 0..position.line {
+            match self.next_line(line_start) {
+                Some(next) => line_start = next,
+                None => return self.text.len(),
+            }
+        }
+
+        let mut units = 0;
+        for (index, ch) in self.text[line_start..].char_indices() {
+            if matches!(ch, '\r' | '\n') || units >= position.character {
+                return line_start + index;
+            }
+            units += ch.len_utf16() as u32;
+        }
+        self.text.len()
+    }
+
+    /// LSP position for a byte offset.
+    pub fn position_at(&self, offset: usize) -> Position {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let (mut line, mut line_start) = (0, 0);
+        while let Some(next) = self.next_line(line_start).filter(|&next| next <= offset) {
+            line += 1;
+            line_start = next;
+        }
+        // An offset inside a line break belongs to the end of its line
+        let line_end = self.text[line_start..].find(['\r', '\n']).map_or(self.text.len(), |end| line_start + end);
+
+        Position {
+            line,
+            character: self.text[line_start..offset.min(line_end)].encode_utf16().count() as u32,
+        }
+    }
+
+    /// Start of the line after the one starting at `start`. Like LSP, `\n`,
+    /// `\r\n` and a lone `\r` all end a line.
+    fn next_line(&self, start: usize) -> Option<usize> {
+        let end = start + self.text[start..].find(['\r', '\n'])?;
+        let length = if self.text[end..].starts_with("\r\n") { 2 } else { 1 };
+        Some(end + length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::protocol::Range;
+
+    fn document(text: &str) -> TextDocument {
+        TextDocument::new(text.to_string(), 1, "markdown".to_string())
+    }
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        // 😀 is two UTF-16 units and four bytes, é one unit and two bytes
+        let doc = document("a😀b\né x");
+        let cases = [(0, position(0, 0)), (1, position(0, 1)), (5, position(0, 3)), (7, position(1, 0)), (10, position(1, 2))];
+        for (offset, expected) in cases {
+            assert_eq!(doc.position_at(offset), expected, "offset {}", offset);
+            assert_eq!(doc.offset_at(expected), offset, "{:?}", expected);
+        }
+        // Inside the emoji: back to its start
+        assert_eq!(doc.position_at(3), position(0, 1));
+    }
+
+    #[test]
+    fn every_line_ending_starts_a_line() {
+        for newline in ["\n", "\r\n", "\r"] {
+            let doc = document(&["one", "two 😀", "three"].join(newline));
+            let three = doc.text.find("three").unwrap();
+            assert_eq!(doc.position_at(three + 2), position(2, 2), "{:?}", newline);
+            assert_eq!(doc.offset_at(position(2, 2)), three + 2, "{:?}", newline);
+            assert_eq!(doc.offset_at(position(1, 6)), doc.text.find("😀").unwrap() + 4, "{:?}", newline);
+        }
+        // Mixed endings in one document
+        let doc = document("a\rb\nc");
+        assert_eq!(doc.position_at(4), position(2, 0));
+    }
+
+    #[test]
+    fn positions_past_a_line_or_the_end_are_clamped() {
+        let doc = document("ab\r\ncd");
+        assert_eq!(doc.offset_at(position(0, 10)), 2);
+        assert_eq!(doc.offset_at(position(5, 0)), doc.text.len());
+        assert_eq!(doc.position_at(100), position(1, 2));
+        // Between \r and \n: still the end of the first line
+        assert_eq!(doc.position_at(3), position(0, 2));
+    }
+
+    #[test]
+    fn changes_replace_ranges_in_utf16() {
+        let mut doc = document("hi 😀 teh\r\nend");
+        let range = Range { start: position(0, 6), end: position(0, 9) };
+        doc.apply_change(&TextDocumentContentChangeEvent { range: Some(range), text: "the".to_string() });
+        assert_eq!(doc.text, "hi 😀 the\r\nend");
+
+        doc.apply_change(&TextDocumentContentChangeEvent { range: None, text: "new".to_string() });
+        assert_eq!(doc.text, "new");
+    }
+}
@@ -0,0 +1,7 @@
+// lsp/mod.rs - Language Server Protocol mode over stdio
+pub mod document;
+pub mod protocol;
+pub mod server;
+pub mod transport;
+
+pub use server::run_stdio;
@@ -0,0 +1,213 @@
+// lsp/protocol.rs - The subset of LSP types QuillGuard speaks
+use harper_core::Dialect;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use crate::lang::rules::RuleConfig;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+pub const SEVERITY_ERROR: u8 = 1;
+pub const SEVERITY_WARNING: u8 = 2;
+pub const SEVERITY_INFORMATION: u8 = 3;
+pub const SEVERITY_HINT: u8 = 4;
+
+/// Incremental text document sync (`TextDocumentSyncKind.Incremental`).
+pub const SYNC_INCREMENTAL: u8 = 2;
+
+pub const APPLY_ALL_COMMAND: &str = "quillguard.applyAll";
+
+/// Any incoming JSON-RPC message: request, notification or response.
+#[derive(Debug, Deserialize)]
+pub struct Message {
+    #[serde(default)]
+    pub id: Option<Value>,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    pub fn intersects(&self, other: &Range) -> bool {
+        let before = |a: Position, b: Position| (a.line, a.character) <= (b.line, b.character);
+        before(self.start, other.end) && before(other.start, self.end)
+    }
+}
+
+fn default_dialect() -> Dialect {
+    Dialect::American
+}
+
+/// Client-supplied `initializationOptions`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializationOptions {
+    #[serde(default = "default_dialect")]
+    pub dialect: Dialect,
+    /// Run the Gramformer and FLAN-T5 stages in addition to Harper
+    #[serde(default)]
+    pub use_ai: bool,
+    /// Harper rule settings by rule name, over the server defaults
+    #[serde(default)]
+    pub rules: RuleConfig,
+    /// Word lists to accept as correctly spelled, from the server's dictionary directory
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub workspace: Option<String>,
+}
+
+impl Default for InitializationOptions {
+    fn default() -> Self {
+        Self {
+            dialect: default_dialect(),
+            use_ai: false,
+            rules: RuleConfig::default(),
+            user: None,
+            workspace: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeParams {
+    #[serde(default)]
+    pub initialization_options: Option<InitializationOptions>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TextDocumentIdentifier {
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionedTextDocumentIdentifier {
+    pub uri: String,
+    pub version: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentItem {
+    pub uri: String,
+    pub language_id: String,
+    pub version: i32,
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidOpenTextDocumentParams {
+    pub text_document: TextDocumentItem,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TextDocumentContentChangeEvent {
+    #[serde(default)]
+    pub range: Option<Range>,
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeTextDocumentParams {
+    pub text_document: VersionedTextDocumentIdentifier,
+    pub content_changes: Vec<TextDocumentContentChangeEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidCloseTextDocumentParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Diagnostic {
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublishDiagnosticsParams {
+    pub uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+    pub range: Range,
+    pub new_text: String,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct WorkspaceEdit {
+    pub changes: HashMap<String, Vec<TextEdit>>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+    pub arguments: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit: Option<WorkspaceEdit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+    pub is_preferred: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExecuteCommandParams {
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApplyWorkspaceEditParams {
+    pub label: String,
+    pub edit: WorkspaceEdit,
+}
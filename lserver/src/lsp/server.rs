@@ -0,0 +1,444 @@
+// lsp/server.rs - Request dispatch, diagnostics and code actions
+use crate::config::Config;
+use crate::lang::generation::GenerationConfig;
+use crate::lang::lint::{check_grammar_professional, GrammarCorrection};
+use crate::lang::dictionary::DictionaryStore;
+use crate::lang::format::TextFormat;
+use crate::lang::{Corrector, HarperConfig, Pipeline};
+use crate::lsp::document::TextDocument;
+use crate::lsp::protocol::*;
+use crate::lsp::transport::{read_message, write_message};
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::sync::{mpsc, OnceCell};
+use tracing::{info, warn};
+
+/// Wait this long after the last edit before checking, so bursts of typing
+/// only trigger one pass through the (slow) AI stages.
+const CHECK_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// A correction resolved to an LSP range in the document version it was computed for.
+#[derive(Debug, Clone)]
+struct LocatedCorrection {
+    range: Range,
+    correction: GrammarCorrection,
+}
+
+#[derive(Debug)]
+struct DocumentState {
+    document: TextDocument,
+    checked_version: Option<i32>,
+    corrections: Vec<LocatedCorrection>,
+}
+
+type Documents = Arc<Mutex<HashMap<String, DocumentState>>>;
+
+struct Server {
+    harper: HarperConfig,
+    config: Arc<Config>,
+    corrector: Arc<OnceCell<Corrector>>,
+    options: InitializationOptions,
+    documents: Documents,
+    outgoing: mpsc::UnboundedSender<Value>,
+    next_request_id: i64,
+}
+
+/// Serve LSP over stdin/stdout until the client sends `exit` or closes the stream.
+pub async fn run_stdio(harper: HarperConfig, config: Config) -> Result<()> {
+    let (outgoing, mut queue) = mpsc::unbounded_channel::<Value>();
+
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = queue.recv().await {
+            if let Err(e) = write_message(&mut stdout, &message).await {
+                warn!("Failed to write LSP message: {}", e);
+                break;
+            }
+        }
+    });
+
+    let mut server = Server {
+        harper,
+        config: Arc::new(config),
+        corrector: Arc::new(OnceCell::new()),
+        options: InitializationOptions::default(),
+        documents: Arc::new(Mutex::new(HashMap::new())),
+        outgoing,
+        next_request_id: 0,
+    };
+
+    let mut reader = BufReader::new(tokio::io::stdin());
+    loop {
+        match read_message(&mut reader).await {
+            Ok(Some(message)) => {
+                if !server.handle(message) {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                warn!("Malformed LSP message: {}", e);
+                server.send_error(Value::Null, PARSE_ERROR, &e.to_string());
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    drop(server);
+    writer.await?;
+    Ok(())
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> std::result::Result<T, String> {
+    serde_json::from_value(params).map_err(|e| format!("Invalid params: {}", e))
+}
+
+fn severity_for(correction: &GrammarCorrection) -> u8 {
+    match correction.severity.as_str() {
+        "critical" => SEVERITY_WARNING,
+        "important" => SEVERITY_INFORMATION,
+        _ => SEVERITY_HINT,
+    }
+}
+
+fn diagnostic_for(located: &LocatedCorrection) -> Diagnostic {
+    let correction = &located.correction;
+    Diagnostic {
+        range: located.range,
+        severity: Some(severity_for(correction)),
+        code: Some(correction.subcategory.clone()),
+        source: Some(format!("quillguard ({})", correction.source_stage)),
+        message: correction.explanation.clone(),
+        data: Some(json!({ "id": correction.id })),
+    }
+}
+
+impl Server {
+    /// Handle one message; returns `false` once the client asked us to exit.
+    fn handle(&mut self, message: Message) -> bool {
+        let Some(method) = message.method else {
+            // A response to one of our own requests (workspace/applyEdit)
+            return true;
+        };
+
+        match message.id {
+            Some(id) => self.handle_request(id, &method, message.params),
+            None => return self.handle_notification(&method, message.params),
+        }
+        true
+    }
+
+    fn handle_request(&mut self, id: Value, method: &str, params: Value) {
+        let result = match method {
+            "initialize" => parse_params(params).map(|p| self.initialize(p)),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/codeAction" => parse_params(params).map(|p| self.code_actions(p)),
+            "workspace/executeCommand" => parse_params(params).map(|p| self.execute_command(p)),
+            _ => {
+                self.send_error(id, METHOD_NOT_FOUND, &format!("Unhandled method {}", method));
+                return;
+            }
+        };
+
+        match result {
+            Ok(result) => self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+            Err(message) => self.send_error(id, INVALID_PARAMS, &message),
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: Value) -> bool {
+        let outcome = match method {
+            "exit" => return false,
+            "textDocument/didOpen" => parse_params(params).map(|p| self.did_open(p)),
+            "textDocument/didChange" => parse_params(params).map(|p| self.did_change(p)),
+            "textDocument/didClose" => parse_params(params).map(|p| self.did_close(p)),
+            // initialized, didSave, $/cancelRequest, ... need no action
+            _ => Ok(()),
+        };
+
+        if let Err(e) = outcome {
+            warn!("Ignoring {}: {}", method, e);
+        }
+        true
+    }
+
+    fn initialize(&mut self, params: InitializeParams) -> Value {
+        self.options = params.initialization_options.unwrap_or_default();
+        info!("LSP client initialized with {:?}", self.options);
+
+        if let Err(e) = self.harper.check_rules(&self.options.rules) {
+            warn!("Ignoring rule settings: {}", e);
+            self.options.rules = Default::default();
+        }
+
+        let dictionaries = DictionaryStore::new(&self.config.dictionaries.dir);
+        match dictionaries.merged(self.options.user.as_deref(), self.options.workspace.as_deref()) {
+            Ok(words) => self.harper = self.harper.with_words(words),
+            Err(e) => warn!("Ignoring custom dictionaries: {}", e),
+        }
+
+        if self.options.use_ai {
+            // Load the models in the background; checks use Harper alone until they are ready.
+            let (corrector, config) = (self.corrector.clone(), self.config.clone());
+            tokio::spawn(async move {
+                corrector.get_or_init(|| async move {
+                    Corrector::new(&config).await.unwrap_or_else(|e| {
+                        warn!("AI corrections disabled: {:#}", e);
+                        Corrector::disabled()
+                    })
+                }).await;
+            });
+        }
+
+        json!({
+            "capabilities": {
+                "textDocumentSync": { "openClose": true, "change": SYNC_INCREMENTAL },
+                "codeActionProvider": { "codeActionKinds": ["quickfix", "source.fixAll"] },
+                "executeCommandProvider": { "commands": [APPLY_ALL_COMMAND] },
+            },
+            "serverInfo": { "name": "quillguard", "version": env!("CARGO_PKG_VERSION") },
+        })
+    }
+
+    fn did_open(&mut self, params: DidOpenTextDocumentParams) {
+        let item = params.text_document;
+        let document = TextDocument::new(item.text, item.version, item.language_id);
+
+        self.documents.lock().unwrap().insert(item.uri.clone(), DocumentState {
+            document,
+            checked_version: None,
+            corrections: Vec::new(),
+        });
+        self.schedule_check(item.uri, item.version);
+    }
+
+    fn did_change(&mut self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let version = params.text_document.version;
+
+        {
+            let mut documents = self.documents.lock().unwrap();
+            let Some(state) = documents.get_mut(&uri) else {
+                warn!("didChange for unopened document {}", uri);
+                return;
+            };
+            for change in &params.content_changes {
+                state.document.apply_change(change);
+            }
+            state.document.version = version;
+        }
+
+        self.schedule_check(uri, version);
+    }
+
+    fn did_close(&mut self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.lock().unwrap().remove(&uri);
+        publish_diagnostics(&self.outgoing, uri, None, Vec::new());
+    }
+
+    /// Check the document in the background and publish diagnostics, unless it
+    /// has moved on to a newer version by the time the check starts or ends.
+    fn schedule_check(&self, uri: String, version: i32) {
+        let documents = self.documents.clone();
+        let harper = self.harper.clone();
+        let corrector = self.corrector.clone();
+        let config = self.config.clone();
+        let dialect = self.options.dialect;
+        let rules = self.options.rules.clone();
+        let outgoing = self.outgoing.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(CHECK_DEBOUNCE).await;
+
+            let Some(document) = current_document(&documents, &uri, version) else {
+                return;
+            };
+            let harper = harper.with_format(TextFormat::from_language_id(&document.language_id));
+
+            // Stage names were validated when the config was loaded
+            let pipeline = Pipeline::from_names(&config.pipeline.stages, corrector.get()).expect("validated pipeline stages");
+
+            // Offsets stay in bytes, which `position_at` maps to UTF-16 line/character
+            let response = check_grammar_professional(
+                &harper,
+                &document.text,
+                dialect,
+                &pipeline,
+                &GenerationConfig::default(),
+                &rules,
+            )
+            .await;
+
+            let located: Vec<LocatedCorrection> = response
+                .corrections
+                .into_iter()
+                .filter(|c| c.category != "summary")
+                .map(|correction| LocatedCorrection {
+                    range: Range {
+                        start: document.position_at(correction.offset),
+                        end: document.position_at(correction.offset + correction.length),
+                    },
+                    correction,
+                })
+                .collect();
+
+            let diagnostics = located.iter().map(diagnostic_for).collect();
+
+            {
+                let mut documents = documents.lock().unwrap();
+                match documents.get_mut(&uri) {
+                    Some(state) if state.document.version == version => {
+                        state.checked_version = Some(version);
+                        state.corrections = located;
+                    }
+                    _ => return,
+                }
+            }
+
+            publish_diagnostics(&outgoing, uri, Some(version), diagnostics);
+        });
+    }
+
+    /// Corrections for the document, provided they match its current text.
+    fn current_corrections(&self, uri: &str) -> Vec<LocatedCorrection> {
+        let documents = self.documents.lock().unwrap();
+        match documents.get(uri) {
+            Some(state) if state.checked_version == Some(state.document.version) => state.corrections.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn code_actions(&self, params: CodeActionParams) -> Value {
+        let uri = params.text_document.uri;
+        let corrections = self.current_corrections(&uri);
+        let mut actions = Vec::new();
+
+        for located in corrections.iter().filter(|c| c.range.intersects(&params.range)) {
+            let diagnostic = diagnostic_for(located);
+
+            for (index, suggestion) in located.correction.suggestions.iter().enumerate() {
+                let title = if suggestion.is_empty() {
+                    format!("Remove '{}'", located.correction.original_text)
+                } else {
+                    format!("Replace with '{}'", suggestion)
+                };
+
+                let mut edit = WorkspaceEdit::default();
+                edit.changes.insert(uri.clone(), vec![TextEdit {
+                    range: located.range,
+                    new_text: suggestion.clone(),
+                }]);
+
+                actions.push(CodeAction {
+                    title,
+                    kind: "quickfix".to_string(),
+                    diagnostics: vec![diagnostic.clone()],
+                    edit: Some(edit),
+                    command: None,
+                    is_preferred: index == 0,
+                });
+            }
+        }
+
+        if !corrections.is_empty() {
+            actions.push(CodeAction {
+                title: "Apply all QuillGuard suggestions".to_string(),
+                kind: "source.fixAll".to_string(),
+                diagnostics: Vec::new(),
+                edit: None,
+                command: Some(Command {
+                    title: "Apply all QuillGuard suggestions".to_string(),
+                    command: APPLY_ALL_COMMAND.to_string(),
+                    arguments: vec![json!(uri)],
+                }),
+                is_preferred: false,
+            });
+        }
+
+        json!(actions)
+    }
+
+    fn execute_command(&mut self, params: ExecuteCommandParams) -> Value {
+        if params.command != APPLY_ALL_COMMAND {
+            warn!("Unknown command {}", params.command);
+            return Value::Null;
+        }
+
+        let Some(uri) = params.arguments.first().and_then(Value::as_str).map(str::to_string) else {
+            warn!("{} called without a document uri", APPLY_ALL_COMMAND);
+            return Value::Null;
+        };
+
+        let edits: Vec<TextEdit> = self
+            .current_corrections(&uri)
+            .into_iter()
+            .filter(|c| !c.correction.suggestions.is_empty())
+            .map(|c| TextEdit {
+                range: c.range,
+                new_text: c.correction.primary_suggestion,
+            })
+            .collect();
+
+        if edits.is_empty() {
+            return Value::Null;
+        }
+
+        let mut edit = WorkspaceEdit::default();
+        edit.changes.insert(uri, edits);
+
+        self.next_request_id += 1;
+        let params = ApplyWorkspaceEditParams {
+            label: "Apply all QuillGuard suggestions".to_string(),
+            edit,
+        };
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": format!("quillguard-{}", self.next_request_id),
+            "method": "workspace/applyEdit",
+            "params": params,
+        }));
+
+        Value::Null
+    }
+
+    fn send(&self, message: Value) {
+        let _ = self.outgoing.send(message);
+    }
+
+    fn send_error(&self, id: Value, code: i64, message: &str) {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }));
+    }
+}
+
+fn current_document(documents: &Documents, uri: &str, version: i32) -> Option<TextDocument> {
+    let documents = documents.lock().unwrap();
+    documents
+        .get(uri)
+        .filter(|state| state.document.version == version)
+        .map(|state| state.document.clone())
+}
+
+fn publish_diagnostics(
+    outgoing: &mpsc::UnboundedSender<Value>,
+    uri: String,
+    version: Option<i32>,
+    diagnostics: Vec<Diagnostic>,
+) {
+    let params = PublishDiagnosticsParams { uri, version, diagnostics };
+    let _ = outgoing.send(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": params,
+    }));
+}
@@ -0,0 +1,114 @@
+// lsp/transport.rs - Content-Length framed JSON-RPC over byte streams
+use crate::lsp::protocol::Message;
+use serde_json::Value;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Read one framed message. Returns `Ok(None)` when the stream is closed.
+///
+/// A body that is not valid JSON is reported as `ErrorKind::InvalidData` so the
+/// caller can answer with a parse error and keep going.
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Message>> {
+    let mut content_length = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = content_length
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing Content-Length header"))?;
+
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Write one message with its Content-Length header and flush.
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &Value) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    let header = format!("Content-Length: {}\r\n\r\n", body.len());
+
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn frame(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[tokio::test]
+    async fn messages_round_trip() {
+        let mut written = Vec::new();
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "text": "naïve 😀" } });
+        write_message(&mut written, &message).await.unwrap();
+        write_message(&mut written, &json!({ "jsonrpc": "2.0", "method": "exit" })).await.unwrap();
+
+        let mut reader = written.as_slice();
+        let first = read_message(&mut reader).await.unwrap().unwrap();
+        assert_eq!(first.id, Some(json!(1)));
+        assert_eq!(first.params["text"], "naïve 😀");
+        assert_eq!(read_message(&mut reader).await.unwrap().unwrap().method.as_deref(), Some("exit"));
+        assert!(read_message(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn other_headers_and_header_case_are_ignored() {
+        let body = r#"{"method":"initialized"}"#;
+        let input = format!("content-length: {}\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{}", body.len(), body);
+        let message = read_message(&mut input.as_bytes()).await.unwrap().unwrap();
+        assert_eq!(message.method.as_deref(), Some("initialized"));
+    }
+
+    #[tokio::test]
+    async fn missing_length_is_invalid_data() {
+        let input = "Content-Type: application/json\r\n\r\n{}";
+        let error = read_message(&mut input.as_bytes()).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let input = "Content-Length: many\r\n\r\n{}";
+        assert_eq!(read_message(&mut input.as_bytes()).await.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn truncated_input() {
+        // Closed in the middle of the headers: nothing more to read
+        assert!(read_message(&mut "Content-Len".as_bytes()).await.unwrap().is_none());
+        assert!(read_message(&mut "".as_bytes()).await.unwrap().is_none());
+
+        // Closed in the middle of the body
+        let input = "Content-Length: 20\r\n\r\n{\"method\":";
+        assert_eq!(read_message(&mut input.as_bytes()).await.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn a_bad_body_does_not_lose_the_next_message() {
+        let input = format!("{}{}", frame("{not json"), frame(r#"{"method":"shutdown","id":2}"#));
+        let mut reader = input.as_bytes();
+        assert_eq!(read_message(&mut reader).await.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_message(&mut reader).await.unwrap().unwrap().method.as_deref(), Some("shutdown"));
+    }
+}
@@ -1,20 +1,45 @@
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{DefaultBodyLimit, Path, State},
+    http::{header, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post, put},
     Json, Router,
 };
 use harper_core::Dialect;
 use serde::{Deserialize, Serialize};
-use std::{net::SocketAddr, sync::Arc};
+use serde_json::json;
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use futures::StreamExt;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tower_http::cors::{Any, CorsLayer};
 
+pub mod config;
 pub mod lang;
-use crate::lang::{HarperConfig, JSONSuggestion, Corrector};
-use crate::lang::lint::{check_grammar_professional, GrammarResponse as ProfessionalGrammarResponse};
+pub mod lsp;
+mod cli;
+mod jobs;
+mod ws;
+use crate::config::Config;
+use crate::lang::{HarperConfig, JSONSuggestion, Corrector, OffsetEncoding, Pipeline};
+use crate::lang::generation::GenerationConfig;
+use crate::lang::pipeline::StageOptions;
+use crate::lang::rules::{RuleConfig, RuleInfo};
+use crate::lang::dictionary::{DictionaryStore, Scope, WordList};
+use crate::lang::format::TextFormat;
+use crate::lang::blocks::{total_stats, Block, BlockResult};
+use crate::lang::pool::PoolStats;
+use crate::lang::lint::{
+    calculate_stats, check_grammar_professional, stream_grammar_professional, GrammarCorrection, GrammarStats,
+    StreamEvent,
+};
+use crate::lang::incremental::{rebase, ChangeBatch};
+use crate::jobs::{Jobs, MemoryJobStore};
+use crate::cli::{Cli, Command};
+use clap::Parser;
 
 // Application state
 #[derive(Debug)]
@@ -22,7 +47,61 @@ struct AppState {
     app_name: String,
     request_count: Mutex<usize>,
     harper: HarperConfig,
-    t5_corrector: Corrector,
+    // Lives as long as the process, so pipelines can move into spawned tasks
+    t5_corrector: &'static Corrector,
+    // Configured stage order
+    stages: Vec<String>,
+    // Batch items checked at once; enough to fill the models' batches
+    batch_concurrency: usize,
+    // User and workspace word lists
+    dictionaries: DictionaryStore,
+    // Background checks of large documents
+    jobs: Jobs,
+}
+
+impl AppState {
+    fn new(config: &Config, harper: HarperConfig, t5_corrector: Corrector) -> Self {
+        Self {
+            app_name: "Language Server".to_string(),
+            request_count: Mutex::new(0),
+            harper,
+            t5_corrector: Box::leak(Box::new(t5_corrector)),
+            stages: config.pipeline.stages.clone(),
+            batch_concurrency: config.inference.concurrency(),
+            dictionaries: DictionaryStore::new(&config.dictionaries.dir),
+            jobs: Jobs::new(Arc::new(MemoryJobStore::default()), config.jobs.max_running),
+        }
+    }
+
+    /// Count one more request; returns the count so far. The lock is only held
+    /// for the increment, never while the request is checked.
+    async fn count_request(&self) -> usize {
+        let mut count = self.request_count.lock().await;
+        *count += 1;
+        *count
+    }
+
+    /// Harper with the request's word lists and format, once its rule settings check out.
+    fn harper(&self, request: &CheckOptions) -> anyhow::Result<HarperConfig> {
+        self.harper.check_rules(&request.rules)?;
+        let words = self.dictionaries.merged(request.user.as_deref(), request.workspace.as_deref())?;
+        Ok(self.harper.with_words(words).with_format(request.format))
+    }
+
+    /// The stages the request names, or the configured ones with model stages
+    /// only when `use_t5` is set; then the request's options and budget.
+    fn pipeline(&self, request: &CheckOptions) -> anyhow::Result<Pipeline<'static>> {
+        request.generation.validate()?;
+        let mut pipeline = match &request.stages {
+            Some(stages) => Pipeline::from_names(stages, Some(self.t5_corrector))?,
+            None => Pipeline::from_names(&self.stages, request.use_t5.then_some(self.t5_corrector))?,
+        }
+        .with_options(&request.stage_options)?;
+        if let Some(budget) = request.budget_ms {
+            pipeline = pipeline.with_budget(Duration::from_millis(budget));
+        }
+        Ok(pipeline)
+    }
 }
 
 #[derive(Serialize)]
@@ -30,6 +109,8 @@ struct InfoResponse {
     app_name: String,
     version: String,
     request_count: usize,
+    // Per-model inference load
+    inference: Vec<PoolStats>,
 }
 
 fn default_dialect() -> Dialect {
@@ -39,12 +120,153 @@ fn default_dialect() -> Dialect {
 #[derive(Deserialize)]
 struct GrammarRequest {
     text: String,
+    // Client's version of the document, echoed in the response for rebasing
+    #[serde(default)]
+    version: Option<i64>,
+    #[serde(flatten)]
+    options: CheckOptions,
+}
+
+/// Most texts one batch request may hold.
+const MAX_BATCH_ITEMS: usize = 1000;
+
+/// Largest batch request body, in bytes.
+const MAX_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    items: Vec<BatchItem>,
+}
+
+// One text of a batch, with its own options
+#[derive(Deserialize)]
+struct BatchItem {
+    // Client's id for the text, echoed in its result
+    id: String,
+    #[serde(flatten)]
+    request: GrammarRequest,
+}
+
+#[derive(Deserialize)]
+struct RebaseRequest {
+    // The document as the corrections were computed for it
+    text: String,
+    #[serde(default)]
+    version: Option<i64>,
+    corrections: Vec<GrammarCorrection>,
+    // Edits made since, oldest first
+    changes: Vec<ChangeBatch>,
+    #[serde(default)]
+    offset_encoding: OffsetEncoding,
+}
+
+#[derive(Serialize)]
+struct RebaseResponse {
+    version: Option<i64>,
+    corrections: Vec<GrammarCorrection>,
+    stats: GrammarStats,
+    // Ids of the corrections whose text was edited
+    invalidated: Vec<String>,
+    offset_encoding: OffsetEncoding,
+}
+
+#[derive(Deserialize)]
+struct BlocksRequest {
+    blocks: Vec<Block>,
+    #[serde(flatten)]
+    options: CheckOptions,
+}
+
+// How to check, shared by every request shape
+#[derive(Deserialize)]
+struct CheckOptions {
     // If client omits `dialect`, this will default to American.
     #[serde(default = "default_dialect")]
     dialect: Dialect,
     // Optional flag to enable T5 contextual correction
     #[serde(default)]
     use_t5: bool,
+    // How to read `text`: plain, markdown or html
+    #[serde(default)]
+    format: TextFormat,
+    // Unit for offsets/lengths in the response; defaults to UTF-16 like JavaScript strings
+    #[serde(default)]
+    offset_encoding: OffsetEncoding,
+    // Beam search settings for the AI stages; greedy when omitted
+    #[serde(default)]
+    generation: GenerationConfig,
+    // Stages to run, in order; overrides `use_t5` and the configured pipeline
+    #[serde(default)]
+    stages: Option<Vec<String>>,
+    // Per-stage overrides keyed by stage name
+    #[serde(default)]
+    stage_options: HashMap<String, StageOptions>,
+    // Stop starting stage work after this many milliseconds
+    #[serde(default)]
+    budget_ms: Option<u64>,
+    // Harper rule settings by rule name, over the server defaults
+    #[serde(default)]
+    rules: RuleConfig,
+    // Word lists whose words count as correctly spelled
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    workspace: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WordsRequest {
+    words: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct WordsResponse {
+    scope: Scope,
+    id: String,
+    words: Vec<String>,
+}
+
+impl WordsResponse {
+    fn new(scope: Scope, id: String, words: &WordList) -> Self {
+        Self { scope, id, words: words.iter().cloned().collect() }
+    }
+}
+
+fn bad_request(error: anyhow::Error) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error.to_string() }))).into_response()
+}
+
+#[derive(Serialize)]
+struct BlocksResponse {
+    blocks: Vec<BlockResult>,
+    // Totals over every block
+    stats: GrammarStats,
+    offset_encoding: OffsetEncoding,
+}
+
+#[derive(Serialize)]
+struct BatchResponse {
+    // In the order of the request's items
+    items: Vec<BatchItemResult>,
+    // Totals over every item that was checked
+    stats: GrammarStats,
+    // Items not checked because their options were invalid
+    failed: usize,
+}
+
+#[derive(Serialize)]
+struct BatchItemResult {
+    id: String,
+    // The item's check, or why it could not be checked
+    #[serde(flatten)]
+    response: Option<lang::lint::GrammarResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RulesResponse {
+    rules: Vec<RuleInfo>,
 }
 
 #[derive(Serialize)]
@@ -52,28 +274,51 @@ struct GrammarResponse {
     dialect: Dialect,
     suggestion_count: usize,
     suggestions: Vec<JSONSuggestion>,
+    offset_encoding: OffsetEncoding,
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    let command = cli.command();
+    match command {
+        // stdout carries the protocol, so logs go to stderr
+        Command::Lsp => tracing_subscriber::fmt().with_writer(std::io::stderr).init(),
+        // stdout carries diagnostics or fixed text; only problems are logged
+        Command::Check(_) | Command::Fix(_) => tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_max_level(tracing::Level::WARN)
+            .init(),
+        Command::Serve => tracing_subscriber::fmt::init(),
+    }
 
-    // Initialize T5 corrector
-    let t5_corrector = Corrector::new().await;
+    let config = Config::load().unwrap_or_else(|e| {
+        tracing::error!("Invalid configuration: {:#}", e);
+        std::process::exit(cli::EXIT_ERROR);
+    });
 
-    let state = Arc::new(AppState {
-        app_name: "Language Server".to_string(),
-        request_count: Mutex::new(0),
-        harper: HarperConfig::new(),
-        t5_corrector,
+    let harper = HarperConfig::new().with_rules(config.rules.clone()).unwrap_or_else(|e| {
+        tracing::error!("Invalid configuration: {:#}", e);
+        std::process::exit(cli::EXIT_ERROR);
     });
 
-    let app = Router::new()
-        .route("/api/info", get(info))
-        .route("/api/grammar", post(check_grammar))
-        .route("/api/grammar/professional", post(check_grammar_pro))
-        .with_state(state)
-        .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any));
+    match command {
+        Command::Lsp => {
+            if let Err(e) = lsp::run_stdio(harper, config).await {
+                tracing::error!("Language server stopped: {}", e);
+                std::process::exit(cli::EXIT_ERROR);
+            }
+        }
+        Command::Check(args) => std::process::exit(cli::check(&config, harper, args).await),
+        Command::Fix(args) => std::process::exit(cli::fix(&config, harper, args).await),
+        Command::Serve => serve(&config, harper).await,
+    }
+}
+
+/// Load the models and answer HTTP requests until the process is stopped.
+async fn serve(config: &Config, harper: HarperConfig) {
+    let state = Arc::new(AppState::new(config, harper, load_models(config).await));
+    let app = router(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     tracing::info!("Listening on http://{}", addr);
@@ -82,37 +327,139 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Every HTTP route, with its body limit where it has its own.
+fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/api/info", get(info))
+        .route("/api/rules", get(rules))
+        .route("/api/dictionaries/{scope}/{id}", get(list_words))
+        .route("/api/dictionaries/{scope}/{id}/words", post(add_words).delete(remove_words))
+        .route("/api/dictionaries/{scope}/{id}/export", get(export_words))
+        .route("/api/dictionaries/{scope}/{id}/import", put(import_words))
+        .route("/api/grammar", post(check_grammar))
+        .route("/api/grammar/professional", post(check_grammar_pro))
+        .route("/api/grammar/professional/stream", post(stream_grammar_pro))
+        .route("/api/grammar/blocks", post(check_blocks))
+        .route("/api/grammar/batch", post(check_batch).layer(DefaultBodyLimit::max(MAX_BATCH_BYTES)))
+        .route("/api/grammar/rebase", post(rebase_corrections))
+        .route("/api/grammar/session", get(ws::grammar_session))
+        .route("/api/jobs", post(jobs::create_job).layer(DefaultBodyLimit::max(jobs::MAX_JOB_BYTES)))
+        .route("/api/jobs/{id}", get(jobs::get_job).delete(jobs::delete_job))
+        .route("/api/jobs/{id}/cancel", post(jobs::cancel_job))
+        .with_state(state)
+        .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any))
+}
+
+/// Initialize T5 corrector; only fails when models are required
+async fn load_models(config: &Config) -> Corrector {
+    Corrector::new(config).await.unwrap_or_else(|e| {
+        tracing::error!("Failed to load models: {:#}", e);
+        std::process::exit(cli::EXIT_ERROR);
+    })
+}
+
 // Route handlers
 
 async fn info(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let mut count = state.request_count.lock().await;
-    *count += 1;
+    let request_count = state.count_request().await;
 
     Json(InfoResponse {
         app_name: state.app_name.clone(),
         version: env!("CARGO_PKG_VERSION").to_string(),
-        request_count: *count,
+        request_count,
+        inference: state.t5_corrector.pool_stats(),
     })
 }
 
+/// Every Harper rule with its description and whether it runs by default
+async fn rules(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(RulesResponse { rules: state.harper.rules().to_vec() })
+}
+
+async fn list_words(
+    State(state): State<Arc<AppState>>,
+    Path((scope, id)): Path<(Scope, String)>,
+) -> impl IntoResponse {
+    match state.dictionaries.words(scope, &id) {
+        Ok(words) => Json(WordsResponse::new(scope, id, &words)).into_response(),
+        Err(e) => bad_request(e),
+    }
+}
+
+async fn add_words(
+    State(state): State<Arc<AppState>>,
+    Path((scope, id)): Path<(Scope, String)>,
+    Json(request): Json<WordsRequest>,
+) -> impl IntoResponse {
+    match state.dictionaries.add(scope, &id, &request.words) {
+        Ok(words) => Json(WordsResponse::new(scope, id, &words)).into_response(),
+        Err(e) => bad_request(e),
+    }
+}
+
+async fn remove_words(
+    State(state): State<Arc<AppState>>,
+    Path((scope, id)): Path<(Scope, String)>,
+    Json(request): Json<WordsRequest>,
+) -> impl IntoResponse {
+    match state.dictionaries.remove(scope, &id, &request.words) {
+        Ok(words) => Json(WordsResponse::new(scope, id, &words)).into_response(),
+        Err(e) => bad_request(e),
+    }
+}
+
+/// The word list as plain text, one word per line
+async fn export_words(
+    State(state): State<Arc<AppState>>,
+    Path((scope, id)): Path<(Scope, String)>,
+) -> impl IntoResponse {
+    match state.dictionaries.export(scope, &id) {
+        Ok(text) => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response(),
+        Err(e) => bad_request(e),
+    }
+}
+
+/// Replace the word list with a plain text one, as produced by export
+async fn import_words(
+    State(state): State<Arc<AppState>>,
+    Path((scope, id)): Path<(Scope, String)>,
+    text: String,
+) -> impl IntoResponse {
+    match state.dictionaries.import(scope, &id, &text) {
+        Ok(words) => Json(WordsResponse::new(scope, id, &words)).into_response(),
+        Err(e) => bad_request(e),
+    }
+}
+
 async fn check_grammar(
     State(state): State<Arc<AppState>>,
     Json(request): Json<GrammarRequest>,
 ) -> impl IntoResponse {
-    let mut count = state.request_count.lock().await;
-    *count += 1;
+    state.count_request().await;
+
+    let options = &request.options;
+    let harper = match state.harper(options) {
+        Ok(harper) => harper,
+        Err(e) => return bad_request(e),
+    };
 
-    let suggestions = if request.use_t5 {
-        JSONSuggestion::new_with_t5(&state.harper, &request.text, request.dialect, Some(&state.t5_corrector)).await
+    let mut suggestions = if options.use_t5 || options.stages.is_some() {
+        let pipeline = match state.pipeline(options) {
+            Ok(pipeline) => pipeline,
+            Err(e) => return bad_request(e),
+        };
+        JSONSuggestion::new_with_t5(&harper, &request.text, options.dialect, &pipeline, &options.rules).await
     } else {
-        JSONSuggestion::new(&state.harper, &request.text, request.dialect)
+        JSONSuggestion::new(&harper, &request.text, options.dialect, &options.rules)
     };
+    JSONSuggestion::encode_offsets(&mut suggestions, &request.text, options.offset_encoding);
 
     (StatusCode::OK, Json(GrammarResponse {
-        dialect: request.dialect,
+        dialect: options.dialect,
         suggestion_count: suggestions.len(),
         suggestions,
-    }))
+        offset_encoding: options.offset_encoding,
+    })).into_response()
 }
 
 /// Professional UX-focused grammar checking endpoint
@@ -120,21 +467,291 @@ async fn check_grammar_pro(
     State(state): State<Arc<AppState>>,
     Json(request): Json<GrammarRequest>,
 ) -> impl IntoResponse {
-    let mut count = state.request_count.lock().await;
-    *count += 1;
+    state.count_request().await;
 
-    let corrector = if request.use_t5 {
-        Some(&state.t5_corrector)
-    } else {
-        None
+    let options = &request.options;
+    let harper = match state.harper(options) {
+        Ok(harper) => harper,
+        Err(e) => return bad_request(e),
     };
+    let pipeline = match state.pipeline(options) {
+        Ok(pipeline) => pipeline,
+        Err(e) => return bad_request(e),
+    };
+
+    let mut response = check_grammar_professional(
+        &harper,
+        &request.text,
+        options.dialect,
+        &pipeline,
+        &options.generation,
+        &options.rules,
+    ).await
+    .with_offset_encoding(&request.text, options.offset_encoding);
+    response.version = request.version;
+
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+/// Check many independent texts, each with its own options. Results come back
+/// in request order; an item with invalid options gets an error, not the batch
+async fn check_batch(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<BatchRequest>,
+) -> impl IntoResponse {
+    if request.items.len() > MAX_BATCH_ITEMS {
+        return bad_request(anyhow::Error::msg(format!(
+            "A batch holds at most {} items, not {}",
+            MAX_BATCH_ITEMS,
+            request.items.len()
+        )));
+    }
+    state.count_request().await;
+
+    // A bounded number of items at a time, so their sentences share model batches
+    // without one large batch holding every text in flight
+    let checks = request.items.into_iter().map(|item| {
+        let state = &state;
+        async move {
+            let BatchItem { id, request } = item;
+            let options = &request.options;
+            let checked = state.harper(options).and_then(|harper| Ok((harper, state.pipeline(options)?)));
+            let (harper, pipeline) = match checked {
+                Ok(checked) => checked,
+                Err(e) => return BatchItemResult { id, response: None, error: Some(e.to_string()) },
+            };
 
-    let response = check_grammar_professional(
-        &state.harper,
+            let mut response = check_grammar_professional(
+                &harper,
+                &request.text,
+                options.dialect,
+                &pipeline,
+                &options.generation,
+                &options.rules,
+            ).await
+            .with_offset_encoding(&request.text, options.offset_encoding);
+            response.version = request.version;
+
+            // Linting is CPU-bound; let other requests in between items
+            tokio::task::yield_now().await;
+            BatchItemResult { id, response: Some(response), error: None }
+        }
+    });
+    let items: Vec<BatchItemResult> = futures::stream::iter(checks)
+        .buffered(state.batch_concurrency)
+        .collect()
+        .await;
+
+    let mut stats = GrammarStats::default();
+    items.iter().filter_map(|item| item.response.as_ref()).for_each(|response| stats.add(&response.stats));
+    let failed = items.iter().filter(|item| item.error.is_some()).count();
+    tracing::info!("Checked a batch of {} texts, {} failed", items.len(), failed);
+
+    (StatusCode::OK, Json(BatchResponse { items, stats, failed })).into_response()
+}
+
+/// Move corrections onto the current document through the edits made since
+/// they were computed, dropping those whose text was edited
+async fn rebase_corrections(Json(request): Json<RebaseRequest>) -> impl IntoResponse {
+    let rebased = match rebase(
         &request.text,
-        request.dialect,
-        corrector
-    ).await;
+        request.version,
+        request.corrections,
+        &request.changes,
+        request.offset_encoding,
+    ) {
+        Ok(rebased) => rebased,
+        Err(e) => return bad_request(e),
+    };
+
+    Json(RebaseResponse {
+        version: rebased.version,
+        stats: calculate_stats(&rebased.corrections),
+        corrections: rebased.corrections,
+        invalidated: rebased.invalidated.into_iter().map(|correction| correction.id).collect(),
+        offset_encoding: request.offset_encoding,
+    }).into_response()
+}
+
+/// The professional check as Server-Sent Events: each stage's corrections as
+/// soon as it finishes, so Harper's arrive before the models have started
+async fn stream_grammar_pro(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<GrammarRequest>,
+) -> impl IntoResponse {
+    state.count_request().await;
+
+    let harper = match state.harper(&request.options) {
+        Ok(harper) => harper,
+        Err(e) => return bad_request(e),
+    };
+    let pipeline = match state.pipeline(&request.options) {
+        Ok(pipeline) => pipeline,
+        Err(e) => return bad_request(e),
+    };
+
+    // The check outlives this handler, so it owns what it needs and reports through a channel
+    let (events, receiver) = mpsc::unbounded_channel::<StreamEvent>();
+    tokio::spawn(async move {
+        let options = &request.options;
+        let check = stream_grammar_professional(
+            &harper,
+            &request.text,
+            options.dialect,
+            &pipeline,
+            &options.generation,
+            &options.rules,
+            |event| {
+                let _ = events.send(event.with_offset_encoding(&request.text, options.offset_encoding));
+            },
+        );
+        // Stop running models for a client that has gone away
+        tokio::select! {
+            _ = check => {}
+            _ = events.closed() => tracing::info!("Client disconnected; abandoning streamed check"),
+        }
+    });
+
+    let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        let sse = Event::default().event(event.name()).json_data(&event).unwrap_or_default();
+        Some((Ok::<_, Infallible>(sse), receiver))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// Check an Editor.js document block by block; offsets are relative to each block
+async fn check_blocks(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<BlocksRequest>,
+) -> impl IntoResponse {
+    state.count_request().await;
+
+    let options = &request.options;
+    let harper = match state.harper(options) {
+        Ok(harper) => harper,
+        Err(e) => return bad_request(e),
+    };
+    // One pipeline per set of stages some block type leaves out, built up front
+    let mut pipelines: Vec<(&[&str], Pipeline)> = Vec::new();
+    let exclusions = std::iter::once(&[][..]).chain(request.blocks.iter().map(Block::excluded_stages));
+    for excluded in exclusions {
+        if pipelines.iter().any(|(built, _)| *built == excluded) {
+            continue;
+        }
+        match state.pipeline(options) {
+            Ok(pipeline) => pipelines.push((excluded, pipeline.without(excluded))),
+            Err(e) => return bad_request(e),
+        }
+    }
+
+    // A bounded number of blocks at a time, so their sentences share model
+    // batches without one large document flooding the batcher. The futures are
+    // collected first; a lazy map over borrowed blocks fails the handler's `Send` check
+    let checks: Vec<_> = request.blocks.iter().map(|block| {
+        let (pipelines, harper) = (&pipelines, &harper);
+        async move {
+            if !block.is_checked() {
+                return BlockResult::unchecked(block);
+            }
+            let (_, pipeline) = pipelines
+                .iter()
+                .find(|(excluded, _)| *excluded == block.excluded_stages())
+                .expect("a pipeline is built for every block's exclusions");
+            let harper = harper.with_format(block.format.unwrap_or(options.format));
+
+            let response = check_grammar_professional(
+                &harper,
+                &block.text,
+                options.dialect,
+                pipeline,
+                &options.generation,
+                &options.rules,
+            ).await
+            .with_offset_encoding(&block.text, options.offset_encoding);
+            BlockResult::new(block, response)
+        }
+    }).collect();
+    let blocks: Vec<BlockResult> = futures::stream::iter(checks).buffered(state.batch_concurrency).collect().await;
+
+    (StatusCode::OK, Json(BlocksResponse {
+        stats: total_stats(&blocks),
+        blocks,
+        offset_encoding: options.offset_encoding,
+    })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    /// The HTTP routes over Harper alone: the model directories do not exist.
+    async fn app() -> Router {
+        let missing = std::env::temp_dir().join(format!("quillguard-no-models-{}", std::process::id()));
+        let mut config = Config::default();
+        config.models.gramformer_dir = missing.join("gramformer");
+        config.models.flan_t5_dir = missing.join("flan_t5");
+        config.models.offline = true;
+        config.dictionaries.dir = missing.join("dictionaries");
+        let corrector = Corrector::new(&config).await.unwrap();
+        router(Arc::new(AppState::new(&config, HarperConfig::new(), corrector)))
+    }
+
+    async fn post_batch(body: String) -> (StatusCode, Value) {
+        let request = Request::post("/api/grammar/batch")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app().await.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn batches_over_the_item_limit_are_rejected() {
+        let items: Vec<Value> = (0..=MAX_BATCH_ITEMS).map(|i| json!({ "id": i.to_string(), "text": "" })).collect();
+        let (status, body) = post_batch(json!({ "items": items }).to_string()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "A batch holds at most 1000 items, not 1001");
+    }
+
+    #[tokio::test]
+    async fn batches_over_the_size_limit_are_rejected() {
+        let text = "a".repeat(MAX_BATCH_BYTES);
+        let (status, _) = post_batch(json!({ "items": [{ "id": "big", "text": text }] }).to_string()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn items_with_invalid_options_fail_alone() {
+        let items = json!([
+            { "id": "typo", "text": "This is teh end." },
+            { "id": "rule", "text": "Fine.", "rules": { "NoSuchRule": false } },
+            { "id": "beams", "text": "Fine.", "generation": { "num_beams": 99 } },
+            { "id": "clean", "text": "This is the end." },
+        ]);
+        let (status, body) = post_batch(json!({ "items": items }).to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let ids: Vec<&str> = body["items"].as_array().unwrap().iter().map(|item| item["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["typo", "rule", "beams", "clean"]);
+        assert_eq!(body["failed"], 2);
+        let [typo, rule, beams, clean] = &body["items"].as_array().unwrap()[..] else {
+            panic!("expected four items");
+        };
+        assert!(rule["error"].as_str().unwrap().contains("NoSuchRule"));
+        assert!(beams["error"].as_str().unwrap().contains("num_beams"));
+        assert!(rule.get("corrections").is_none());
+        assert!(typo.get("error").is_none() && clean.get("error").is_none());
 
-    (StatusCode::OK, Json(response))
+        // Totals cover the checked items only
+        assert_eq!(typo["corrections"][0]["original_text"], "teh");
+        assert_eq!(body["stats"], typo["stats"]);
+        assert_eq!(clean["stats"]["total_issues"], 0);
+    }
 }
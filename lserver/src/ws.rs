@@ -0,0 +1,260 @@
+// ws.rs - Document sessions over WebSocket
+//
+// A client opens one document per socket, then sends edits instead of the
+// whole text. Every message is a JSON text frame:
+//
+//   → {"type": "open", "version": 1, "text": "...", ...request options}
+//   → {"type": "change", "version": 2, "changes": [{"offset": 4, "length": 3, "replacement": "the"}]}
+//   ← {"type": "corrections", "version": 2, "checked_version": 2, "corrections": [...], ...}
+//   ← {"type": "error", "message": "..."}
+//
+// Checks run in the background, a moment after the last edit, and only on
+// the paragraphs and sentences that changed (see `lang::incremental`). When
+// the document was edited during a check, its corrections are rebased onto
+// the latest version before they are sent. Word lists edited during the
+// session apply from the next check on, which rechecks the whole document.
+use anyhow::{Error as E, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use crate::lang::edits::Edit;
+use crate::lang::incremental::{apply_changes, rebase, ChangeBatch, DocumentCache};
+use crate::lang::lint::{calculate_stats, GrammarCorrection, GrammarStats};
+use crate::lang::{HarperConfig, OffsetEncoding, Pipeline};
+use crate::{AppState, CheckOptions};
+
+/// Wait this long after an edit before checking, so bursts of typing only
+/// trigger one check.
+const CHECK_DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Open {
+        text: String,
+        version: i64,
+        #[serde(flatten)]
+        options: Box<CheckOptions>,
+    },
+    Change {
+        version: i64,
+        // Applied in order, offsets in the session's `offset_encoding`
+        changes: Vec<Edit>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Corrections {
+        // Document version the corrections apply to
+        version: i64,
+        // Version that was checked; older than `version` when edits came in during the check
+        checked_version: i64,
+        corrections: Vec<GrammarCorrection>,
+        stats: GrammarStats,
+        offset_encoding: OffsetEncoding,
+        // Paragraphs checked again and paragraphs whose corrections were reused
+        rechecked_paragraphs: usize,
+        reused_paragraphs: usize,
+        // Corrections dropped because their text was edited during the check
+        invalidated: usize,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// The document as of the latest edit.
+#[derive(Clone)]
+struct Snapshot {
+    version: i64,
+    text: String,
+}
+
+/// Edits not yet covered by a check, oldest first.
+type History = Arc<Mutex<Vec<ChangeBatch>>>;
+
+/// A session's document and the task checking it.
+struct OpenDocument {
+    snapshot: Snapshot,
+    offset_encoding: OffsetEncoding,
+    history: History,
+    snapshots: watch::Sender<Snapshot>,
+    checker: JoinHandle<()>,
+}
+
+pub async fn grammar_session(State(state): State<Arc<AppState>>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| run_session(state, socket))
+}
+
+async fn run_session(state: Arc<AppState>, socket: WebSocket) {
+    let (mut sink, mut incoming) = socket.split();
+    let (outgoing, mut queue) = mpsc::unbounded_channel::<ServerMessage>();
+
+    // The reader and the checker both answer, through one writer
+    let writer = tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            let text = serde_json::to_string(&message).expect("session messages serialize");
+            if sink.send(Message::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut document: Option<OpenDocument> = None;
+    while let Some(Ok(message)) = incoming.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let handled = serde_json::from_str::<ClientMessage>(&text)
+            .map_err(E::from)
+            .and_then(|message| handle_message(&state, &mut document, message, &outgoing));
+        if let Err(e) = handled {
+            let _ = outgoing.send(ServerMessage::Error { message: e.to_string() });
+        }
+    }
+
+    if let Some(document) = document {
+        document.checker.abort();
+    }
+    drop(outgoing);
+    let _ = writer.await;
+}
+
+fn handle_message(
+    state: &Arc<AppState>,
+    document: &mut Option<OpenDocument>,
+    message: ClientMessage,
+    outgoing: &mpsc::UnboundedSender<ServerMessage>,
+) -> Result<()> {
+    match (message, document.as_mut()) {
+        (ClientMessage::Open { text, version, options }, None) => {
+            let harper = state.harper(&options)?;
+            let pipeline = state.pipeline(&options)?;
+
+            let snapshot = Snapshot { version, text };
+            let (snapshots, latest) = watch::channel(snapshot.clone());
+            let offset_encoding = options.offset_encoding;
+            let history = History::default();
+            let checker = tokio::spawn(check_document(
+                state.clone(),
+                options,
+                harper,
+                pipeline,
+                latest,
+                history.clone(),
+                outgoing.clone(),
+            ));
+            info!("Opened document session at version {}", version);
+            *document = Some(OpenDocument { snapshot, offset_encoding, history, snapshots, checker });
+            Ok(())
+        }
+        (ClientMessage::Open { .. }, Some(_)) => Err(E::msg("This session already has a document open")),
+        (ClientMessage::Change { .. }, None) => Err(E::msg("Open a document before changing it")),
+        (ClientMessage::Change { version, changes }, Some(document)) => {
+            if version <= document.snapshot.version {
+                return Err(E::msg(format!(
+                    "Version {} is not newer than the document's version {}",
+                    version, document.snapshot.version
+                )));
+            }
+            apply_changes(&mut document.snapshot.text, &changes, document.offset_encoding)?;
+            document.snapshot.version = version;
+            document.history.lock().unwrap().push(ChangeBatch { version, changes });
+            document.snapshots.send_replace(document.snapshot.clone());
+            Ok(())
+        }
+    }
+}
+
+/// Check each new version of the document until the session ends.
+async fn check_document(
+    state: Arc<AppState>,
+    options: Box<CheckOptions>,
+    mut harper: HarperConfig,
+    pipeline: Pipeline<'static>,
+    mut snapshots: watch::Receiver<Snapshot>,
+    history: History,
+    outgoing: mpsc::UnboundedSender<ServerMessage>,
+) {
+    // Count each session once, like a request
+    state.count_request().await;
+    let mut cache = DocumentCache::new();
+    let mut pipeline = cache.attach(pipeline);
+
+    loop {
+        let Snapshot { version, text } = snapshots.borrow_and_update().clone();
+        history.lock().unwrap().retain(|batch| batch.version > version);
+
+        // Cached corrections were made against the old words, so they go too
+        if let Some(rebuilt) = with_current_words(&state, &options, &harper) {
+            harper = rebuilt;
+            cache = DocumentCache::new();
+            pipeline = cache.attach(pipeline);
+            info!("Word lists changed; rechecking the document at version {}", version);
+        }
+
+        let check = cache.check(&harper, &text, options.dialect, &pipeline, &options.generation, &options.rules).await;
+        let response = check.response.with_offset_encoding(&text, options.offset_encoding);
+
+        // Edits are validated when they arrive, so this should not fail; if it
+        // does, report it and let the next check catch up instead of stopping
+        let since = history.lock().unwrap().clone();
+        let message = match rebase(&text, Some(version), response.corrections, &since, options.offset_encoding) {
+            Ok(rebased) => ServerMessage::Corrections {
+                version: rebased.version.unwrap_or(version),
+                checked_version: version,
+                stats: calculate_stats(&rebased.corrections),
+                corrections: rebased.corrections,
+                offset_encoding: options.offset_encoding,
+                rechecked_paragraphs: check.rechecked,
+                reused_paragraphs: check.reused,
+                invalidated: rebased.invalidated.len(),
+            },
+            Err(e) => {
+                warn!("Cannot rebase session corrections: {:#}", e);
+                ServerMessage::Error { message: format!("Corrections for version {} were dropped: {}", version, e) }
+            }
+        };
+        let sent = outgoing.send(message);
+        if sent.is_err() {
+            warn!("Document session closed during a check");
+            return;
+        }
+
+        if snapshots.changed().await.is_err() {
+            return;
+        }
+        tokio::time::sleep(CHECK_DEBOUNCE).await;
+    }
+}
+
+/// Harper with the session's word lists as they are now, if they changed
+/// since `harper` was built.
+fn with_current_words(state: &AppState, options: &CheckOptions, harper: &HarperConfig) -> Option<HarperConfig> {
+    let words = state.dictionaries.merged(options.user.as_deref(), options.workspace.as_deref());
+    match words {
+        Ok(words) if words != *harper.custom_words() => match state.harper(options) {
+            Ok(rebuilt) => Some(rebuilt),
+            Err(e) => {
+                warn!("Cannot rebuild the session's dictionary: {:#}", e);
+                None
+            }
+        },
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Cannot read the session's word lists: {:#}", e);
+            None
+        }
+    }
+}
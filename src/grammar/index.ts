@@ -76,7 +76,8 @@ class GrammarChecker {
         body: JSON.stringify({ 
           text, 
           dialect: this.config.dialect || 'American',
-          use_t5: true  // Enable T5 grammar corrections
+          use_t5: true,  // Enable T5 grammar corrections
+          offset_encoding: 'utf16'  // Offsets index JavaScript strings
         })
       });
 
@@ -121,7 +122,8 @@ class GrammarChecker {
         body: JSON.stringify({ 
           text, 
           dialect: this.config.dialect || 'American',
-          use_t5: true  // Enable full three-stage pipeline
+          use_t5: true,  // Enable full three-stage pipeline
+          offset_encoding: 'utf16'  // Offsets index JavaScript strings
         })
       });
 